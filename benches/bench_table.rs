@@ -1,10 +1,11 @@
 mod common;
 
-use agatedb::{Table, TableBuilder, TableOptions, Value};
+use agatedb::{BlockCache, ReadOptions, Table, TableBuilder, TableOptions, Value};
 use bytes::Bytes;
 use common::rand_value;
 use criterion::{criterion_group, criterion_main, Criterion};
 use rand::Rng;
+use std::sync::Arc;
 use tempdir::TempDir;
 
 fn bench_table_builder(c: &mut Criterion) {
@@ -24,35 +25,83 @@ fn bench_table_builder(c: &mut Criterion) {
             block_size: 4 * 1024,
             bloom_false_positive: 0.01,
             table_size: 5 << 20,
+            ..TableOptions::default()
         };
 
         b.iter(|| {
             let mut builder = TableBuilder::new(opt.clone());
             for j in 0..KEY_COUNT {
-                builder.add(&key_list[j], vs.clone(), 0);
+                builder.add(&key_list[j], vs.clone(), 0).unwrap();
             }
             builder.finish()
         });
     });
 }
 
-fn get_table_for_benchmark(count: usize) -> Table {
-    let tmp_dir = TempDir::new("agatedb").unwrap();
+fn bench_table_builder_reset(c: &mut Criterion) {
+    const BUILDS: usize = 1000;
+    const KEY_COUNT: usize = 100;
+
+    let key_list: Vec<_> = (0..KEY_COUNT)
+        .map(|i| Bytes::from(format!("{:032}", i)))
+        .collect();
+    let vs = Value::new(Bytes::from(rand_value()));
+    let opt = TableOptions {
+        block_size: 4 * 1024,
+        bloom_false_positive: 0.01,
+        table_size: 2 << 20,
+        ..TableOptions::default()
+    };
+    let build = |builder: &mut TableBuilder| {
+        for k in &key_list {
+            builder.add(k, vs.clone(), 0).unwrap();
+        }
+        builder.finish()
+    };
+
+    let mut group = c.benchmark_group("table builder 1000 builds");
+    group.sample_size(10);
+    group.bench_function("new", |b| {
+        b.iter(|| {
+            for _ in 0..BUILDS {
+                build(&mut TableBuilder::new(opt.clone()));
+            }
+        });
+    });
+    group.bench_function("reset", |b| {
+        let mut builder = TableBuilder::new(opt.clone());
+        b.iter(|| {
+            for _ in 0..BUILDS {
+                build(&mut builder);
+                builder.reset();
+            }
+        });
+    });
+    group.finish();
+}
 
+fn get_table_for_benchmark(count: usize) -> Table {
     let opts = TableOptions {
         // TODO: add compression parameter
         block_size: 4 * 1024,
         bloom_false_positive: 0.01,
         table_size: 0,
+        ..TableOptions::default()
     };
 
+    get_table_for_benchmark_with_opts(count, opts)
+}
+
+fn get_table_for_benchmark_with_opts(count: usize, opts: TableOptions) -> Table {
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+
     let mut builder = TableBuilder::new(opts.clone());
     let filename = tmp_dir.path().join("1.sst".to_string());
 
     for i in 0..count {
         let k = Bytes::from(format!("{:016x}", i));
         let v = Bytes::from(i.to_string());
-        builder.add(&k, Value::new(v), 0);
+        builder.add(&k, Value::new(v), 0).unwrap();
     }
 
     Table::create(&filename, builder.finish(), opts).unwrap()
@@ -63,7 +112,85 @@ fn bench_table(c: &mut Criterion) {
     c.bench_function("table read", |b| {
         let table = get_table_for_benchmark(n);
         b.iter(|| {
-            let mut it = table.new_iterator(0);
+            let mut it = table.new_iterator(ReadOptions::default());
+            it.seek_to_first();
+            while it.valid() {
+                it.next();
+            }
+        });
+    });
+
+    c.bench_function("table read without mmap", |b| {
+        let opts = TableOptions {
+            block_size: 4 * 1024,
+            bloom_false_positive: 0.01,
+            table_size: 0,
+            use_mmap: false,
+            ..TableOptions::default()
+        };
+        let table = get_table_for_benchmark_with_opts(n, opts);
+        b.iter(|| {
+            let mut it = table.new_iterator(ReadOptions::default());
+            it.seek_to_first();
+            while it.valid() {
+                it.next();
+            }
+        });
+    });
+
+    // same as above, but allocating for every block read as before reads
+    // were split off a per-thread buffer
+    c.bench_function("table read without mmap or read buffer reuse", |b| {
+        let opts = TableOptions {
+            block_size: 4 * 1024,
+            bloom_false_positive: 0.01,
+            table_size: 0,
+            use_mmap: false,
+            reuse_read_buffer: false,
+            ..TableOptions::default()
+        };
+        let table = get_table_for_benchmark_with_opts(n, opts);
+        b.iter(|| {
+            let mut it = table.new_iterator(ReadOptions::default());
+            it.seek_to_first();
+            while it.valid() {
+                it.next();
+            }
+        });
+    });
+
+    c.bench_function("table read without mmap and with prefetch", |b| {
+        let opts = TableOptions {
+            block_size: 4 * 1024,
+            bloom_false_positive: 0.01,
+            table_size: 0,
+            use_mmap: false,
+            ..TableOptions::default()
+        };
+        let table = get_table_for_benchmark_with_opts(n, opts);
+        b.iter(|| {
+            let mut it = table.new_iterator(ReadOptions {
+                prefetch_size: 1,
+                ..ReadOptions::default()
+            });
+            it.seek_to_first();
+            while it.valid() {
+                it.next();
+            }
+        });
+    });
+
+    c.bench_function("table read loaded to ram", |b| {
+        let opts = TableOptions {
+            block_size: 4 * 1024,
+            bloom_false_positive: 0.01,
+            table_size: 0,
+            load_to_ram: true,
+            ..TableOptions::default()
+        };
+        let table = get_table_for_benchmark_with_opts(n, opts);
+        b.iter(|| {
+            let mut it = table.new_iterator(ReadOptions::default());
             it.seek_to_first();
             while it.valid() {
                 it.next();
@@ -75,16 +202,19 @@ fn bench_table(c: &mut Criterion) {
         block_size: 4 * 1024,
         bloom_false_positive: 0.01,
         table_size: 0,
+        ..TableOptions::default()
     };
 
     c.bench_function("table read and build", |b| {
         let table = get_table_for_benchmark(n);
         b.iter(|| {
-            let mut it = table.new_iterator(0);
+            let mut it = table.new_iterator(ReadOptions::default());
             let mut builder = TableBuilder::new(builder_opts.clone());
             it.seek_to_first();
             while it.valid() {
-                builder.add(&Bytes::copy_from_slice(it.key()), it.value(), 0);
+                builder
+                    .add(&Bytes::copy_from_slice(it.key()), it.value(), 0)
+                    .unwrap();
                 it.next();
             }
             builder.finish()
@@ -96,7 +226,7 @@ fn bench_table(c: &mut Criterion) {
     let mut rng = rand::thread_rng();
     c.bench_function("table random read", |b| {
         let table = get_table_for_benchmark(n);
-        let mut it = table.new_iterator(0);
+        let mut it = table.new_iterator(ReadOptions::default());
         b.iter_batched(
             || {
                 let i = rng.gen_range(0, n);
@@ -115,10 +245,235 @@ fn bench_table(c: &mut Criterion) {
     });
 }
 
+fn bench_block_cache(c: &mut Criterion) {
+    let n = 1000000;
+    // every key in the hot working set lives in a different block,
+    // so the working set fits in a 64MB cache
+    let hot_keys = 1000;
+
+    let mut rng = rand::thread_rng();
+    let mut bench_hot_read = |c: &mut Criterion, name: &str, table: Table| {
+        let mut it = table.new_iterator(ReadOptions::default());
+        c.bench_function(name, |b| {
+            b.iter_batched(
+                || {
+                    let i = rng.gen_range(0, hot_keys) * (n / hot_keys);
+                    Bytes::from(format!("{:016x}", i))
+                },
+                |k| {
+                    it.seek(&k);
+                    assert!(it.valid());
+                },
+                criterion::BatchSize::SmallInput,
+            );
+        });
+    };
+
+    bench_hot_read(c, "table hot read", get_table_for_benchmark(n));
+
+    let opts = TableOptions {
+        block_size: 4 * 1024,
+        bloom_false_positive: 0.01,
+        table_size: 0,
+        block_cache: Some(Arc::new(BlockCache::with_capacity(64 << 20))),
+        ..TableOptions::default()
+    };
+    bench_hot_read(
+        c,
+        "table hot read with block cache",
+        get_table_for_benchmark_with_opts(n, opts),
+    );
+}
+
+fn bench_block_seek_small_keys(c: &mut Criterion) {
+    // 4-byte user keys with 1-byte values pack thousands of entries into
+    // a 64KB block, and the block cache keeps all of them decoded, so
+    // only seeking in a block is measured
+    let n: u32 = 1 << 20;
+    let cache = Arc::new(BlockCache::with_capacity(256 << 20));
+    let opts = TableOptions {
+        block_size: 64 * 1024,
+        bloom_false_positive: 0.01,
+        table_size: 0,
+        block_cache: Some(cache),
+        ..TableOptions::default()
+    };
+    let key = |i: u32| {
+        let mut k = i.to_be_bytes().to_vec();
+        k.extend_from_slice(&[0; 8]);
+        Bytes::from(k)
+    };
+    let mut builder = TableBuilder::new(opts.clone());
+    for i in 0..n {
+        builder
+            .add(&key(i), Value::new(Bytes::from_static(b"v")), 0)
+            .unwrap();
+    }
+    let table = Table::open_in_memory(builder.finish(), 1, opts).unwrap();
+
+    let mut rng = rand::thread_rng();
+    for &(name, reversed) in &[
+        ("block seek small keys", false),
+        ("block seek small keys reversed", true),
+    ] {
+        let mut it = table.new_iterator(ReadOptions {
+            reversed,
+            ..ReadOptions::default()
+        });
+        c.bench_function(name, |b| {
+            b.iter_batched(
+                || key(rng.gen_range(0, n)),
+                |k| {
+                    it.seek(&k);
+                    assert!(it.valid());
+                },
+                criterion::BatchSize::SmallInput,
+            );
+        });
+    }
+}
+
+fn bench_table_open(c: &mut Criterion) {
+    let n = 300;
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    let opts = TableOptions {
+        block_size: 4 * 1024,
+        bloom_false_positive: 0.01,
+        table_size: 0,
+        ..TableOptions::default()
+    };
+
+    let paths: Vec<_> = (0..n)
+        .map(|id| {
+            let mut builder = TableBuilder::new(opts.clone());
+            for i in 0..10000 {
+                let k = Bytes::from(format!("{:016x}", id * 10000 + i));
+                builder
+                    .add(&k, Value::new(Bytes::from(i.to_string())), 0)
+                    .unwrap();
+            }
+            let path = tmp_dir.path().join(format!("{}.sst", id + 1));
+            Table::create(&path, builder.finish(), opts.clone()).unwrap();
+            path
+        })
+        .collect();
+
+    c.bench_function("table open", |b| {
+        b.iter(|| {
+            for path in &paths {
+                Table::open(path, opts.clone()).unwrap();
+            }
+        });
+    });
+}
+
+fn bench_table_open_in_memory(c: &mut Criterion) {
+    let opts = TableOptions {
+        block_size: 4 * 1024,
+        bloom_false_positive: 0.01,
+        table_size: 0,
+        ..TableOptions::default()
+    };
+    let mut builder = TableBuilder::new(opts.clone());
+    let vs = Value::new(Bytes::from(rand_value()));
+    // about 64MB
+    for i in 0..1300000 {
+        builder
+            .add(&Bytes::from(format!("{:032}", i)), vs.clone(), 0)
+            .unwrap();
+    }
+    let data = builder.finish();
+
+    c.bench_function("table open in memory", |b| {
+        b.iter(|| Table::open_in_memory(data.clone(), 1, opts.clone()).unwrap());
+    });
+}
+
+fn bench_table_create(c: &mut Criterion) {
+    let n = 100000;
+    let mut bench_create = |name: &str, flush_threshold: usize| {
+        let tmp_dir = TempDir::new("agatedb").unwrap();
+        let opts = TableOptions {
+            block_size: 4 * 1024,
+            bloom_false_positive: 0.01,
+            table_size: 0,
+            flush_threshold,
+            ..TableOptions::default()
+        };
+        let mut id = 0;
+        c.bench_function(name, |b| {
+            b.iter_batched(
+                || {
+                    let mut builder = TableBuilder::new(opts.clone());
+                    for i in 0..n {
+                        let k = Bytes::from(format!("{:016x}", i));
+                        builder
+                            .add(&k, Value::new(Bytes::from(i.to_string())), 0)
+                            .unwrap();
+                    }
+                    id += 1;
+                    (builder, tmp_dir.path().join(format!("{}.sst", id)))
+                },
+                |(builder, path)| {
+                    let table = Table::from_builder(&path, builder, opts.clone()).unwrap();
+                    table.mark_delete();
+                },
+                criterion::BatchSize::PerIteration,
+            );
+        });
+    };
+
+    bench_create("table create", 0);
+    bench_create("table create in memory", 64 << 20);
+}
+
+fn bench_table_verify_checksum(c: &mut Criterion) {
+    // 256K entries of 1KB values make a table of about 256MB
+    let n = 256 << 10;
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    let opts = TableOptions {
+        block_size: 64 * 1024,
+        bloom_false_positive: 0.01,
+        table_size: 0,
+        ..TableOptions::default()
+    };
+    let mut builder = TableBuilder::new(opts.clone());
+    let value = Bytes::from(vec![b'v'; 1024]);
+    for i in 0..n {
+        let k = Bytes::from(format!("{:016x}", i));
+        builder.add(&k, Value::new(value.clone()), 0).unwrap();
+    }
+    let path = tmp_dir.path().join("1.sst");
+    drop(Table::create(&path, builder.finish(), opts.clone()).unwrap());
+
+    let mut group = c.benchmark_group("table verify checksum");
+    group.sample_size(10);
+    let single_thread = rayon::ThreadPoolBuilder::new()
+        .num_threads(1)
+        .build()
+        .unwrap();
+    for &(name, pool) in &[
+        ("sequential", Some(Arc::new(single_thread))),
+        ("parallel", None),
+    ] {
+        let opts = TableOptions {
+            verify_checksum_pool: pool.clone(),
+            ..opts.clone()
+        };
+        let table = Table::open(&path, opts).unwrap();
+        group.bench_function(name, |b| {
+            b.iter(|| table.verify_checksum_parallel().unwrap());
+        });
+    }
+    group.finish();
+}
+
 criterion_group! {
     name = benches_table;
     config = Criterion::default();
-    targets = bench_table_builder, bench_table
+    targets = bench_table_builder, bench_table_builder_reset, bench_table, bench_block_cache,
+        bench_block_seek_small_keys, bench_table_open,
+        bench_table_open_in_memory, bench_table_create, bench_table_verify_checksum
 }
 
 criterion_main!(benches_table);
@@ -0,0 +1,142 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use std::f64::consts;
+
+/// Bloom implements a bloom filter over a bit-slice of data.
+///
+/// The last byte of the encoded filter stores the number of probes,
+/// the same layout as in LevelDB and badger. `Bloom` only borrows the
+/// encoded data, so it is cheap to construct on every lookup.
+#[derive(Clone, Copy)]
+pub struct Bloom<'a> {
+    filter: &'a [u8],
+    k: u8,
+}
+
+impl<'a> Bloom<'a> {
+    /// Create a bloom filter from its encoded form
+    pub fn new(buf: &'a [u8]) -> Self {
+        if buf.is_empty() {
+            return Self { filter: buf, k: 0 };
+        }
+        let filter = &buf[..buf.len() - 1];
+        let k = buf[buf.len() - 1];
+        Self { filter, k }
+    }
+
+    /// Get bits per key from entries count and false positive rate
+    pub fn bloom_bits_per_key(num_entries: usize, false_positive_rate: f64) -> usize {
+        let size = -1.0 * (num_entries as f64) * false_positive_rate.ln() / consts::LN_2.powi(2);
+        let locs = (size / (num_entries as f64)).ceil();
+        locs as usize
+    }
+
+    /// Build an encoded bloom filter from key hashes
+    pub fn build_from_key_hashes(keys: &[u32], bits_per_key: usize) -> Bytes {
+        // 0.69 is approximately ln(2)
+        let k = ((bits_per_key as f64) * 0.69) as u32;
+        let k = k.max(1).min(30);
+
+        // For small n, we can see a very high false positive rate.
+        // Fix it by enforcing a minimum bloom filter length.
+        let mut nbits = keys.len() * bits_per_key;
+        if nbits < 64 {
+            nbits = 64;
+        }
+        let nbytes = (nbits + 7) / 8;
+        let nbits = (nbytes * 8) as u32;
+
+        let mut filter = BytesMut::with_capacity(nbytes + 1);
+        filter.resize(nbytes, 0);
+        for h in keys {
+            let mut h = *h;
+            let delta = h.rotate_right(17);
+            for _ in 0..k {
+                let bit_pos = h % nbits;
+                filter[(bit_pos / 8) as usize] |= 1 << (bit_pos % 8);
+                h = h.wrapping_add(delta);
+            }
+        }
+        filter.put_u8(k as u8);
+        filter.freeze()
+    }
+
+    /// Check if the filter may contain the key with hash `h`.
+    /// Returns false only if the key is definitely absent.
+    pub fn may_contain(&self, mut h: u32) -> bool {
+        if self.filter.is_empty() {
+            return true;
+        }
+        if self.k > 30 {
+            // Reserved for potentially new encodings of short bloom filters.
+            // Consider it a match.
+            return true;
+        }
+        let nbits = (self.filter.len() * 8) as u32;
+        let delta = h.rotate_right(17);
+        for _ in 0..self.k {
+            let bit_pos = h % nbits;
+            if self.filter[(bit_pos / 8) as usize] & (1 << (bit_pos % 8)) == 0 {
+                return false;
+            }
+            h = h.wrapping_add(delta);
+        }
+        true
+    }
+}
+
+/// Hash used for keys in bloom filters. Only the user key (without
+/// timestamp) should be hashed.
+pub fn hash(key: &[u8]) -> u32 {
+    farmhash::fingerprint32(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_small_bloom_filter() {
+        let hashes: Vec<u32> = vec![b"hello" as &[u8], b"world"]
+            .into_iter()
+            .map(hash)
+            .collect();
+        let filter = Bloom::build_from_key_hashes(&hashes, 10);
+        let bloom = Bloom::new(&filter);
+
+        assert!(bloom.may_contain(hash(b"hello")));
+        assert!(bloom.may_contain(hash(b"world")));
+        for key in &[b"x" as &[u8], b"bar", b"foo"] {
+            assert!(!bloom.may_contain(hash(key)));
+        }
+    }
+
+    #[test]
+    fn test_bloom_bits_per_key() {
+        assert_eq!(Bloom::bloom_bits_per_key(1000, 0.01), 10);
+        assert_eq!(Bloom::bloom_bits_per_key(1000, 0.1), 5);
+    }
+
+    #[test]
+    fn test_bloom_false_positive_rate() {
+        let n = 10000;
+        let hashes: Vec<u32> = (0..n)
+            .map(|i| hash(format!("key{:08}", i).as_bytes()))
+            .collect();
+        let filter = Bloom::build_from_key_hashes(&hashes, Bloom::bloom_bits_per_key(n, 0.01));
+        let bloom = Bloom::new(&filter);
+
+        for h in &hashes {
+            assert!(bloom.may_contain(*h));
+        }
+
+        let false_positives = (n..2 * n)
+            .filter(|i| bloom.may_contain(hash(format!("key{:08}", i).as_bytes())))
+            .count();
+        assert!(
+            false_positives < n / 50,
+            "false positive rate too high: {}/{}",
+            false_positives,
+            n
+        );
+    }
+}
@@ -0,0 +1,252 @@
+use crate::table::Block;
+use proto::meta::TableIndex;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::File;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// BlockCache caches SST blocks by table id and block index,
+/// weighted by block size.
+pub type BlockCache = LruCache<(u64, usize), Arc<Block>>;
+
+/// IndexCache caches SST indexes by table id, weighted by index size.
+pub type IndexCache = LruCache<u64, Arc<TableIndex>>;
+
+/// FileCache keeps SST files open by table id. Each file weighs 1, so
+/// capacity is the maximum number of open files.
+pub type FileCache = LruCache<u64, Arc<File>>;
+
+struct CacheEntry<V> {
+    value: V,
+    weight: u64,
+    /// the tick when the entry was last accessed
+    tick: u64,
+}
+
+struct LruCore<K, V> {
+    /// maximum total weight of entries in cache
+    capacity: u64,
+    /// total weight of entries in cache
+    size: u64,
+    entries: HashMap<K, CacheEntry<V>>,
+    /// access order of entries, least recently used comes first
+    lru: BTreeMap<u64, K>,
+    /// monotonic access counter
+    tick: u64,
+}
+
+impl<K: Hash + Eq + Copy, V: Clone> LruCore<K, V> {
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    fn get(&mut self, key: &K) -> Option<V> {
+        let tick = self.next_tick();
+        let entry = self.entries.get_mut(key)?;
+        self.lru.remove(&entry.tick);
+        entry.tick = tick;
+        self.lru.insert(tick, *key);
+        Some(entry.value.clone())
+    }
+
+    fn insert(&mut self, key: K, value: V, weight: u64) {
+        let tick = self.next_tick();
+        self.size += weight;
+        let entry = CacheEntry {
+            value,
+            weight,
+            tick,
+        };
+        if let Some(old) = self.entries.insert(key, entry) {
+            self.size -= old.weight;
+            self.lru.remove(&old.tick);
+        }
+        self.lru.insert(tick, key);
+        while self.size > self.capacity {
+            self.evict();
+        }
+    }
+
+    fn remove(&mut self, key: &K) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.size -= entry.weight;
+            self.lru.remove(&entry.tick);
+        }
+    }
+
+    fn evict(&mut self) {
+        let oldest = match self.lru.keys().next() {
+            Some(tick) => *tick,
+            None => return,
+        };
+        let key = self.lru.remove(&oldest).unwrap();
+        if let Some(entry) = self.entries.remove(&key) {
+            self.size -= entry.weight;
+        }
+    }
+}
+
+/// LruCache is a weighted LRU cache. It can be shared across tables
+/// by setting the same cache in their options.
+pub struct LruCache<K, V> {
+    core: Mutex<LruCore<K, V>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl<K: Hash + Eq + Copy, V: Clone> LruCache<K, V> {
+    /// Create a cache holding entries of at most `capacity` in total weight
+    pub fn with_capacity(capacity: u64) -> Self {
+        LruCache {
+            core: Mutex::new(LruCore {
+                capacity,
+                size: 0,
+                entries: HashMap::new(),
+                lru: BTreeMap::new(),
+                tick: 0,
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Get an entry from cache and mark it as recently used
+    pub(crate) fn get(&self, key: &K) -> Option<V> {
+        let value = self.core.lock().unwrap().get(key);
+        if value.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+        value
+    }
+
+    /// Insert an entry into cache, evicting least recently used entries if needed
+    pub(crate) fn insert(&self, key: K, value: V, weight: u64) {
+        self.core.lock().unwrap().insert(key, value, weight)
+    }
+
+    /// Remove an entry from cache
+    pub(crate) fn remove(&self, key: &K) {
+        self.core.lock().unwrap().remove(key)
+    }
+
+    /// Get number of entries in cache
+    pub fn len(&self) -> usize {
+        self.core.lock().unwrap().entries.len()
+    }
+
+    /// Check if the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get total weight of entries in cache
+    pub fn size(&self) -> u64 {
+        self.core.lock().unwrap().size
+    }
+
+    /// Get number of lookups that found the entry in cache
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Get number of lookups that didn't find the entry in cache
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
+
+impl<K, V> fmt::Debug for LruCache<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let core = self.core.lock().unwrap();
+        f.debug_struct("LruCache")
+            .field("capacity", &core.capacity)
+            .field("size", &core.size)
+            .field("len", &core.entries.len())
+            .field("hits", &self.hits.load(Ordering::Relaxed))
+            .field("misses", &self.misses.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lru_cache() {
+        let cache = LruCache::with_capacity(2);
+        cache.insert(1, "a", 1);
+        cache.insert(2, "b", 1);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.size(), 2);
+
+        // make 1 recently used, so 2 is evicted
+        assert_eq!(cache.get(&1), Some("a"));
+        cache.insert(3, "c", 1);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&1), Some("a"));
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&3), Some("c"));
+
+        assert_eq!(cache.hits(), 3);
+        assert_eq!(cache.misses(), 1);
+    }
+
+    #[test]
+    fn test_lru_cache_weight() {
+        let cache = LruCache::with_capacity(10);
+        cache.insert(1, "a", 4);
+        cache.insert(2, "b", 4);
+        cache.insert(3, "c", 4);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.size(), 8);
+        assert_eq!(cache.get(&1), None);
+
+        // an entry heavier than capacity is never kept
+        cache.insert(4, "d", 11);
+        assert!(cache.is_empty());
+        assert_eq!(cache.size(), 0);
+    }
+
+    #[test]
+    fn test_lru_cache_reinsert() {
+        let cache = LruCache::with_capacity(10);
+        cache.insert(1, "a", 2);
+        cache.insert(1, "b", 3);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.size(), 3);
+        assert_eq!(cache.get(&1), Some("b"));
+        assert_eq!(cache.core.lock().unwrap().lru.len(), 1);
+    }
+
+    #[test]
+    fn test_lru_cache_remove() {
+        let cache = LruCache::with_capacity(10);
+        cache.insert(1, "a", 2);
+        cache.insert(2, "b", 3);
+        cache.remove(&1);
+        cache.remove(&3);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.size(), 3);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.core.lock().unwrap().lru.len(), 1);
+    }
+
+    #[test]
+    fn test_block_cache() {
+        let block_size = Block::default().size();
+        let cache = BlockCache::with_capacity(2 * block_size);
+        for i in 0..3 {
+            let block = Arc::new(Block::default());
+            cache.insert((1, i), block.clone(), block.size());
+        }
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(&(1, 0)).is_none());
+        assert!(cache.get(&(1, 2)).is_some());
+    }
+}
@@ -10,16 +10,55 @@ pub fn calculate_checksum(data: &[u8], algo: ChecksumAlgorithm) -> u64 {
 }
 
 pub fn verify_checksum(data: &[u8], expected: &Checksum) -> Result<()> {
-    let actual = calculate_checksum(data, ChecksumAlgorithm::from_i32(expected.algo).unwrap());
+    let algo = ChecksumAlgorithm::from_i32(expected.algo).ok_or_else(|| {
+        Error::InvalidChecksum(format!("unknown checksum algorithm {}", expected.algo))
+    })?;
+    let actual = calculate_checksum(data, algo);
     if actual == expected.sum {
         return Ok(());
     }
-    Err(Error::InvalidChecksum(format!(
-        "checksum not correct, expected {:?}, got {}",
-        expected, actual
+    Err(Error::Corruption(format!(
+        "checksum mismatch with {:?}, expected {:#x}, actual {:#x}",
+        algo, expected.sum, actual
     )))
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_checksum() {
+        let data = b"agatedb checksum";
+        for algo in vec![ChecksumAlgorithm::Crc32c, ChecksumAlgorithm::XxHash64] {
+            let mut chksum = Checksum {
+                algo: algo as i32,
+                sum: calculate_checksum(data, algo),
+            };
+            verify_checksum(data, &chksum).unwrap();
+            assert!(matches!(
+                verify_checksum(b"agatedb checksuM", &chksum),
+                Err(Error::Corruption(_))
+            ));
+            chksum.sum += 1;
+            assert!(matches!(
+                verify_checksum(data, &chksum),
+                Err(Error::Corruption(_))
+            ));
+        }
+        assert_ne!(
+            calculate_checksum(data, ChecksumAlgorithm::Crc32c),
+            calculate_checksum(data, ChecksumAlgorithm::XxHash64)
+        );
+
+        // unknown algorithms never pass
+        let chksum = Checksum { algo: 7, sum: 0 };
+        let err = verify_checksum(data, &chksum).unwrap_err();
+        assert!(matches!(err, Error::InvalidChecksum(_)));
+        assert!(err.to_string().contains("unknown checksum algorithm 7"));
+    }
+}
+
 mod xxhash {
     use std::{ptr, u64};
 
@@ -0,0 +1,133 @@
+use crate::opt::CompressionType;
+use crate::{Error, Result};
+use bytes::Bytes;
+
+/// Compress data with the given compression type
+pub fn compress(data: &[u8], compression: CompressionType) -> Result<Bytes> {
+    match compression {
+        CompressionType::None => Ok(Bytes::copy_from_slice(data)),
+        CompressionType::Snappy => snap::raw::Encoder::new()
+            .compress_vec(data)
+            .map(Bytes::from)
+            .map_err(|e| Error::Compression(e.to_string())),
+        CompressionType::Zstd(level) => zstd::encode_all(data, level)
+            .map(Bytes::from)
+            .map_err(|e| Error::Compression(e.to_string())),
+    }
+}
+
+/// Decompress data compressed with the given compression type
+pub fn decompress(data: Bytes, compression: CompressionType) -> Result<Bytes> {
+    CompressedBlock::new(data, compression).decompress()
+}
+
+/// Data of a block as stored in SST, tagged with how it is compressed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompressedBlock {
+    /// block which is not compressed
+    Raw(Bytes),
+    /// block compressed with snappy
+    Snappy(Bytes),
+    /// block compressed with zstd. The level is only known when the block
+    /// is compressed, and is 0 for blocks read from SST.
+    Zstd { data: Bytes, level: i32 },
+}
+
+impl CompressedBlock {
+    /// Tag `data` compressed with `compression`
+    pub fn new(data: Bytes, compression: CompressionType) -> CompressedBlock {
+        match compression {
+            CompressionType::None => CompressedBlock::Raw(data),
+            CompressionType::Snappy => CompressedBlock::Snappy(data),
+            CompressionType::Zstd(level) => CompressedBlock::Zstd { data, level },
+        }
+    }
+
+    /// Compress `data` with `compression`
+    pub fn compress(data: &[u8], compression: CompressionType) -> Result<CompressedBlock> {
+        Ok(Self::new(compress(data, compression)?, compression))
+    }
+
+    /// Get compression type of the block
+    pub fn compression(&self) -> CompressionType {
+        match self {
+            CompressedBlock::Raw(_) => CompressionType::None,
+            CompressedBlock::Snappy(_) => CompressionType::Snappy,
+            CompressedBlock::Zstd { level, .. } => CompressionType::Zstd(*level),
+        }
+    }
+
+    /// Get data of the block as stored in SST
+    pub fn data(&self) -> &Bytes {
+        match self {
+            CompressedBlock::Raw(data)
+            | CompressedBlock::Snappy(data)
+            | CompressedBlock::Zstd { data, .. } => data,
+        }
+    }
+
+    /// Decompress the block. Raw blocks are returned without copying.
+    pub fn decompress(self) -> Result<Bytes> {
+        match self {
+            CompressedBlock::Raw(data) => Ok(data),
+            CompressedBlock::Snappy(data) => snap::raw::Decoder::new()
+                .decompress_vec(&data)
+                .map(Bytes::from)
+                .map_err(|e| Error::Compression(e.to_string())),
+            CompressedBlock::Zstd { data, .. } => zstd::decode_all(&data[..])
+                .map(Bytes::from)
+                .map_err(|e| Error::Compression(e.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compression() {
+        let data = Bytes::from("agatedb".repeat(100));
+        for compression in &[
+            CompressionType::None,
+            CompressionType::Snappy,
+            CompressionType::Zstd(1),
+            CompressionType::Zstd(19),
+        ] {
+            let compressed = compress(&data, *compression).unwrap();
+            assert_eq!(decompress(compressed, *compression).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_compressed_block() {
+        let data = Bytes::from("agatedb".repeat(100));
+        for compression in &[
+            CompressionType::None,
+            CompressionType::Snappy,
+            CompressionType::Zstd(3),
+        ] {
+            let block = CompressedBlock::compress(&data, *compression).unwrap();
+            assert_eq!(block.compression(), *compression);
+            assert_eq!(block.compression().to_u32(), compression.to_u32());
+            if *compression != CompressionType::None {
+                assert!(block.data().len() < data.len());
+            }
+            // blocks read from SST only know the stored compression type
+            let stored = CompressionType::from_u32(compression.to_u32()).unwrap();
+            let read = CompressedBlock::new(block.data().clone(), stored);
+            assert_eq!(read.decompress().unwrap(), data);
+        }
+
+        // raw blocks are not copied
+        let raw = CompressedBlock::new(data.clone(), CompressionType::None);
+        assert_eq!(raw.decompress().unwrap().as_ptr(), data.as_ptr());
+    }
+
+    #[test]
+    fn test_decompress_corrupted() {
+        let data = Bytes::from_static(b"\xff\xff\xff\xff\xff");
+        assert!(decompress(data.clone(), CompressionType::Snappy).is_err());
+        assert!(decompress(data, CompressionType::Zstd(0)).is_err());
+    }
+}
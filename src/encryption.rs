@@ -0,0 +1,65 @@
+use crate::{Error, Result};
+use aes::{Aes128, Aes192, Aes256};
+use ctr::cipher::{NewCipher, StreamCipher};
+use ctr::Ctr128BE;
+use rand::RngCore;
+
+/// Size of initialization vector of AES-CTR
+pub const IV_SIZE: usize = 16;
+
+/// Generate a random initialization vector
+pub fn generate_iv() -> [u8; IV_SIZE] {
+    let mut iv = [0; IV_SIZE];
+    rand::thread_rng().fill_bytes(&mut iv);
+    iv
+}
+
+fn apply_keystream<C: NewCipher + StreamCipher>(
+    data: &mut [u8],
+    key: &[u8],
+    iv: &[u8],
+) -> Result<()> {
+    let mut cipher = C::new_from_slices(key, iv).map_err(|e| Error::Encryption(e.to_string()))?;
+    cipher.apply_keystream(data);
+    Ok(())
+}
+
+/// Encrypt or decrypt data in place with AES-CTR. The key must be 16, 24
+/// or 32 bytes long, choosing AES-128, AES-192 or AES-256 respectively.
+pub fn xor_block(data: &mut [u8], key: &[u8], iv: &[u8]) -> Result<()> {
+    match key.len() {
+        16 => apply_keystream::<Ctr128BE<Aes128>>(data, key, iv),
+        24 => apply_keystream::<Ctr128BE<Aes192>>(data, key, iv),
+        32 => apply_keystream::<Ctr128BE<Aes256>>(data, key, iv),
+        len => Err(Error::Encryption(format!(
+            "invalid key length {}, should be 16, 24 or 32",
+            len
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xor_block() {
+        let data = b"agatedb".repeat(100);
+        let iv = generate_iv();
+        for len in &[16, 24, 32] {
+            let key = vec![7; *len];
+            let mut buf = data.clone();
+            xor_block(&mut buf, &key, &iv).unwrap();
+            assert_ne!(buf, data);
+            xor_block(&mut buf, &key, &iv).unwrap();
+            assert_eq!(buf, data);
+        }
+    }
+
+    #[test]
+    fn test_xor_block_invalid_key() {
+        let mut buf = vec![0; 16];
+        assert!(xor_block(&mut buf, &[0; 15], &generate_iv()).is_err());
+        assert!(xor_block(&mut buf, &[0; 16], &[0; 8]).is_err());
+    }
+}
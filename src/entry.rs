@@ -1,12 +1,14 @@
 use bytes::Bytes;
 
-const DELETE: u8 = 1 << 0;
+pub(crate) const DELETE: u8 = 1 << 0;
 const VALUE_POINTER: u8 = 1 << 1;
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct Entry {
     pub key: Bytes,
     pub value: Bytes,
     pub meta: u8,
+    pub version: u64,
 }
 
 impl Entry {
@@ -15,6 +17,7 @@ impl Entry {
             key,
             value,
             meta: 0,
+            version: 0,
         }
     }
 
@@ -1,3 +1,4 @@
+use std::fmt;
 use std::io;
 use std::result;
 
@@ -13,7 +14,7 @@ pub enum Error {
     EmptyKey,
     #[error("{0}")]
     TooLong(String),
-    #[error("Invalid checksum")]
+    #[error("Invalid checksum: {0}")]
     InvalidChecksum(String),
     #[error("Invalid filename")]
     InvalidFilename(String),
@@ -23,6 +24,68 @@ pub enum Error {
     VarDecode(&'static str),
     #[error("{0}")]
     TableRead(String),
+    #[error("Compression error: {0}")]
+    Compression(String),
+    #[error("Encryption error: {0}")]
+    Encryption(String),
+    #[error("Invalid data key: {0}")]
+    InvalidDataKey(String),
+    #[error("Bad magic number: {0}")]
+    BadMagic(String),
+    #[error("WAL is closed: {0}")]
+    WalClosed(String),
+    #[error("Data corruption: {0}")]
+    Corruption(String),
+    #[error("Key out of order: {0}")]
+    KeyOutOfOrder(String),
+    #[error("failed to open table {path} when {phase} at offset {offset}: {source}")]
+    TableOpen {
+        path: String,
+        phase: OpenPhase,
+        offset: u64,
+        #[source]
+        source: Box<Error>,
+    },
+}
+
+impl Error {
+    /// Check if the error is caused by corrupted data, such as a checksum
+    /// mismatch, rather than by failing to read the data
+    pub fn is_corruption(&self) -> bool {
+        match self {
+            Error::Corruption(_) => true,
+            Error::TableOpen { source, .. } => source.is_corruption(),
+            _ => false,
+        }
+    }
+}
+
+/// Phase of opening a table, reported by `Error::TableOpen`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenPhase {
+    /// opening or mapping the file
+    File,
+    /// reading format version, magic number and lengths at the end
+    Footer,
+    /// reading and verifying checksums of index and blocks
+    Checksum,
+    /// reading and decoding index
+    Index,
+    /// reading the last block for the biggest key
+    LastBlock,
+}
+
+impl fmt::Display for OpenPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let phase = match self {
+            OpenPhase::File => "opening file",
+            OpenPhase::Footer => "reading footer",
+            OpenPhase::Checksum => "verifying checksum",
+            OpenPhase::Index => "decoding index",
+            OpenPhase::LastBlock => "reading last block",
+        };
+        f.write_str(phase)
+    }
 }
 
 impl From<io::Error> for Error {
@@ -0,0 +1,17 @@
+use crate::value::Value;
+use bytes::Bytes;
+
+/// AgateIterator is the common interface of iterators over sorted
+/// key-value pairs, such as table iterators and merge iterators.
+pub trait AgateIterator {
+    /// Move to next entry
+    fn next(&mut self);
+    /// Reset the iterator to first entry
+    fn rewind(&mut self);
+    /// Seek to first entry >= key, or first entry <= key if reversed
+    fn seek(&mut self, key: &Bytes);
+    fn key(&self) -> &[u8];
+    fn value(&self) -> Value;
+    /// Check if the iterator points to an entry
+    fn valid(&self) -> bool;
+}
@@ -0,0 +1,152 @@
+use crate::{Error, Result};
+use bytes::Bytes;
+use proto::meta::DataKey;
+use rand::RngCore;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+struct KeyRegistryInner {
+    data_keys: HashMap<u64, DataKey>,
+    /// id of latest data key, 0 if no data key is generated
+    next_key_id: u64,
+    /// creation time of latest data key in seconds
+    last_created: i64,
+}
+
+/// KeyRegistry stores data keys used to encrypt SSTs, and rotates the
+/// latest data key after `rotation_duration`. Data keys are generated
+/// with the same length as the encryption key.
+// TODO: persist data keys encrypted with `encryption_key`
+pub struct KeyRegistry {
+    inner: RwLock<KeyRegistryInner>,
+    encryption_key: Bytes,
+    rotation_duration: Duration,
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+impl KeyRegistry {
+    /// Create a key registry. Encryption is disabled if `encryption_key`
+    /// is empty, otherwise it must be 16, 24 or 32 bytes long.
+    pub fn new(encryption_key: Bytes, rotation_duration: Duration) -> Result<KeyRegistry> {
+        match encryption_key.len() {
+            0 | 16 | 24 | 32 => {}
+            len => {
+                return Err(Error::Encryption(format!(
+                    "invalid encryption key length {}, should be 16, 24 or 32",
+                    len
+                )))
+            }
+        }
+        Ok(KeyRegistry {
+            inner: RwLock::new(KeyRegistryInner {
+                data_keys: HashMap::new(),
+                next_key_id: 0,
+                last_created: 0,
+            }),
+            encryption_key,
+            rotation_duration,
+        })
+    }
+
+    /// Get the data key to encrypt new SSTs with, generating a new one if
+    /// there is none or the latest one has expired. Returns `None` if
+    /// encryption is disabled.
+    pub fn latest_data_key(&self) -> Option<DataKey> {
+        if self.encryption_key.is_empty() {
+            return None;
+        }
+        let valid = |inner: &KeyRegistryInner| {
+            let age = Duration::from_secs((now() - inner.last_created).max(0) as u64);
+            inner.next_key_id != 0 && age < self.rotation_duration
+        };
+        {
+            let inner = self.inner.read().unwrap();
+            if valid(&inner) {
+                return inner.data_keys.get(&inner.next_key_id).cloned();
+            }
+        }
+        let mut inner = self.inner.write().unwrap();
+        // the key may have been rotated by others
+        if valid(&inner) {
+            return inner.data_keys.get(&inner.next_key_id).cloned();
+        }
+        let mut data = vec![0; self.encryption_key.len()];
+        let mut iv = vec![0; crate::encryption::IV_SIZE];
+        rand::thread_rng().fill_bytes(&mut data);
+        rand::thread_rng().fill_bytes(&mut iv);
+        let created_at = now();
+        inner.next_key_id += 1;
+        let key = DataKey {
+            key_id: inner.next_key_id,
+            data,
+            iv,
+            created_at,
+        };
+        inner.last_created = created_at;
+        inner.data_keys.insert(key.key_id, key.clone());
+        Some(key)
+    }
+
+    /// Get data key by id
+    pub fn data_key(&self, id: u64) -> Result<DataKey> {
+        self.inner
+            .read()
+            .unwrap()
+            .data_keys
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| Error::InvalidDataKey(format!("data key {} not found", id)))
+    }
+}
+
+impl std::fmt::Debug for KeyRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let inner = self.inner.read().unwrap();
+        f.debug_struct("KeyRegistry")
+            .field("data_keys", &inner.data_keys.len())
+            .field("next_key_id", &inner.next_key_id)
+            .field("rotation_duration", &self.rotation_duration)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_registry() {
+        let kr = KeyRegistry::new(Bytes::from(vec![1; 16]), Duration::from_secs(3600)).unwrap();
+        let key = kr.latest_data_key().unwrap();
+        assert_eq!(key.key_id, 1);
+        assert_eq!(key.data.len(), 16);
+        assert_eq!(kr.latest_data_key().unwrap(), key);
+        assert_eq!(kr.data_key(1).unwrap(), key);
+        assert!(matches!(kr.data_key(2), Err(Error::InvalidDataKey(_))));
+    }
+
+    #[test]
+    fn test_key_registry_rotation() {
+        let kr = KeyRegistry::new(Bytes::from(vec![1; 32]), Duration::from_secs(0)).unwrap();
+        let k1 = kr.latest_data_key().unwrap();
+        let k2 = kr.latest_data_key().unwrap();
+        assert_eq!(k2.key_id, k1.key_id + 1);
+        assert_ne!(k1.data, k2.data);
+        assert_eq!(kr.data_key(k1.key_id).unwrap(), k1);
+        assert_eq!(kr.data_key(k2.key_id).unwrap(), k2);
+    }
+
+    #[test]
+    fn test_key_registry_disabled() {
+        let kr = KeyRegistry::new(Bytes::new(), Duration::from_secs(3600)).unwrap();
+        assert!(kr.latest_data_key().is_none());
+        assert!(KeyRegistry::new(Bytes::from(vec![1; 10]), Duration::from_secs(3600)).is_err());
+    }
+}
@@ -1 +1,221 @@
 mod table;
+
+use crate::table::Table;
+use bytes::Bytes;
+
+/// A range of user keys with inclusive bounds, used to check whether
+/// tables overlap when assigning them to levels. Keys in the range carry
+/// no timestamp, so all versions of a boundary user key are covered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyRange {
+    /// smallest user key in the range
+    pub left: Bytes,
+    /// biggest user key in the range
+    pub right: Bytes,
+    /// the range covers all keys, and `left` and `right` are ignored
+    pub inf: bool,
+    /// the range contains no key, and `left` and `right` are ignored. An
+    /// empty user key is a valid key, so a range from `b""` to `b""` is
+    /// not empty
+    pub empty: bool,
+}
+
+impl Default for KeyRange {
+    /// The default range is empty
+    fn default() -> KeyRange {
+        KeyRange::empty()
+    }
+}
+
+impl KeyRange {
+    /// Create a range from `left` to `right`, both inclusive
+    pub fn new(left: impl Into<Bytes>, right: impl Into<Bytes>) -> KeyRange {
+        KeyRange {
+            left: left.into(),
+            right: right.into(),
+            inf: false,
+            empty: false,
+        }
+    }
+
+    /// Create a range covering all keys
+    pub fn inf() -> KeyRange {
+        KeyRange {
+            left: Bytes::new(),
+            right: Bytes::new(),
+            inf: true,
+            empty: false,
+        }
+    }
+
+    /// Create a range containing no key
+    pub fn empty() -> KeyRange {
+        KeyRange {
+            left: Bytes::new(),
+            right: Bytes::new(),
+            inf: false,
+            empty: true,
+        }
+    }
+
+    /// Create a range of user keys in `table`, which is empty if the
+    /// table has no key
+    pub fn from_table(table: &Table) -> KeyRange {
+        if table.is_empty() {
+            return KeyRange::empty();
+        }
+        KeyRange::new(
+            Bytes::copy_from_slice(table.smallest_user_key()),
+            Bytes::copy_from_slice(table.biggest_user_key()),
+        )
+    }
+
+    /// Check if the range contains no key, which is the range of an empty table
+    pub fn is_empty(&self) -> bool {
+        self.empty
+    }
+
+    /// Check if two ranges share any user key. Empty ranges overlap with
+    /// nothing, and the infinite range overlaps with any other range.
+    pub fn overlaps_with(&self, other: &KeyRange) -> bool {
+        if self.is_empty() || other.is_empty() {
+            return false;
+        }
+        if self.inf || other.inf {
+            return true;
+        }
+        self.left <= other.right && other.left <= self.right
+    }
+
+    /// Extend the range to cover `other` as well
+    pub fn extend(&mut self, other: &KeyRange) {
+        if other.is_empty() || self.inf {
+            return;
+        }
+        if self.is_empty() || other.inf {
+            *self = other.clone();
+            return;
+        }
+        if other.left < self.left {
+            self.left = other.left.clone();
+        }
+        if other.right > self.right {
+            self.right = other.right.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_range_overlap() {
+        let r = |left: &'static str, right: &'static str| KeyRange::new(left, right);
+        // adjacent ranges sharing a boundary key
+        assert!(r("a", "c").overlaps_with(&r("c", "e")));
+        assert!(r("c", "e").overlaps_with(&r("a", "c")));
+        // disjoint ranges
+        assert!(!r("a", "b").overlaps_with(&r("c", "e")));
+        assert!(!r("c", "e").overlaps_with(&r("a", "b")));
+        // nested ranges
+        assert!(r("a", "z").overlaps_with(&r("c", "e")));
+        assert!(r("c", "e").overlaps_with(&r("a", "z")));
+        assert!(r("c", "c").overlaps_with(&r("c", "c")));
+        // empty ranges
+        assert!(!KeyRange::default().overlaps_with(&r("a", "z")));
+        assert!(!r("a", "z").overlaps_with(&KeyRange::default()));
+        assert!(!KeyRange::default().overlaps_with(&KeyRange::default()));
+        // the infinite range
+        assert!(KeyRange::inf().overlaps_with(&r("a", "z")));
+        assert!(r("a", "z").overlaps_with(&KeyRange::inf()));
+        assert!(KeyRange::inf().overlaps_with(&KeyRange::inf()));
+        assert!(!KeyRange::inf().overlaps_with(&KeyRange::default()));
+        assert!(!KeyRange::inf().is_empty());
+        assert!(KeyRange::empty().is_empty());
+        assert_eq!(KeyRange::default(), KeyRange::empty());
+    }
+
+    #[test]
+    fn test_key_range_extend() {
+        let r = |left: &'static str, right: &'static str| KeyRange::new(left, right);
+        let cases = vec![
+            // touching ranges
+            (r("a", "c"), r("c", "e"), r("a", "e")),
+            (r("c", "e"), r("a", "c"), r("a", "e")),
+            // disjoint ranges
+            (r("a", "b"), r("d", "e"), r("a", "e")),
+            // nested ranges
+            (r("a", "z"), r("c", "e"), r("a", "z")),
+            (r("c", "e"), r("a", "z"), r("a", "z")),
+            // empty ranges
+            (KeyRange::default(), r("a", "c"), r("a", "c")),
+            (r("a", "c"), KeyRange::default(), r("a", "c")),
+            (
+                KeyRange::default(),
+                KeyRange::default(),
+                KeyRange::default(),
+            ),
+            // the infinite range
+            (KeyRange::inf(), r("a", "c"), KeyRange::inf()),
+            (r("a", "c"), KeyRange::inf(), KeyRange::inf()),
+            (KeyRange::default(), KeyRange::inf(), KeyRange::inf()),
+        ];
+        for (mut range, other, expected) in cases {
+            let before = range.clone();
+            range.extend(&other);
+            assert_eq!(range, expected, "{:?} extended by {:?}", before, other);
+            if !other.is_empty() {
+                assert!(range.overlaps_with(&other));
+            }
+        }
+    }
+
+    #[test]
+    fn test_key_range_from_table() {
+        use crate::format::key_with_ts;
+        use crate::opt::Options;
+        use crate::table::builder::Builder;
+        use crate::value::Value;
+
+        let build = |keys: &[(&'static str, u64)]| {
+            let mut builder = Builder::new(Options::default());
+            for (k, ts) in keys {
+                builder
+                    .add(&key_with_ts(k.as_bytes(), *ts), Value::new(Bytes::new()), 0)
+                    .unwrap();
+            }
+            Table::open_in_memory(builder.finish(), 1, Options::default()).unwrap()
+        };
+
+        // all versions of a single key make a single key range
+        let t1 = build(&[("b", 9), ("b", 3)]);
+        assert_eq!(KeyRange::from_table(&t1), KeyRange::new("b", "b"));
+        assert_eq!(KeyRange::from_table(&t1), t1.key_range());
+        let t2 = build(&[("b", 1)]);
+        assert!(KeyRange::from_table(&t1).overlaps_with(&KeyRange::from_table(&t2)));
+        let t3 = build(&[("c", 5)]);
+        assert!(!KeyRange::from_table(&t1).overlaps_with(&KeyRange::from_table(&t3)));
+
+        let mut range = KeyRange::from_table(&t3);
+        range.extend(&KeyRange::from_table(&t1));
+        assert_eq!(range, KeyRange::new("b", "c"));
+
+        let empty = build(&[]);
+        assert!(KeyRange::from_table(&empty).is_empty());
+        assert!(!KeyRange::from_table(&empty).overlaps_with(&KeyRange::inf()));
+
+        // an empty user key is a key like any other
+        let t4 = build(&[("", 7)]);
+        let range = KeyRange::from_table(&t4);
+        assert_eq!(range, KeyRange::new("", ""));
+        assert!(!range.is_empty());
+        assert!(range.overlaps_with(&KeyRange::from_table(&t4)));
+        assert!(range.overlaps_with(&KeyRange::new("", "a")));
+        assert!(range.overlaps_with(&KeyRange::inf()));
+        assert!(!range.overlaps_with(&KeyRange::from_table(&t1)));
+        let mut extended = range.clone();
+        extended.extend(&KeyRange::from_table(&t3));
+        assert_eq!(extended, KeyRange::new("", "c"));
+    }
+}
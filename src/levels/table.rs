@@ -1,5 +1,5 @@
 use bytes::Bytes;
-use memmap::Mmap;
+use memmap2::Mmap;
 use proto::meta;
 use std::fs::File;
 
@@ -1,9 +1,15 @@
 #![allow(dead_code)]
+mod bloom;
+mod cache;
 mod checksum;
+mod compression;
 mod db;
+mod encryption;
 mod entry;
 mod error;
 mod format;
+mod iterator_trait;
+mod key_registry;
 mod levels;
 mod memtable;
 mod ops;
@@ -13,12 +19,22 @@ mod util;
 mod value;
 mod wal;
 
+pub use cache::{BlockCache, FileCache, IndexCache};
 pub use format::{get_ts, key_with_ts};
-pub use opt::Options as TableOptions;
+pub use iterator_trait::AgateIterator;
+pub use key_registry::KeyRegistry;
+pub use levels::KeyRange;
+pub use opt::{
+    ChecksumAlgorithm, ChecksumVerificationMode, CompressionType, Options as TableOptions,
+    ReadOptions,
+};
 pub use table::builder::Builder as TableBuilder;
-pub use table::Table;
+pub use table::{
+    AccessPattern, BlockDump, ConcatIterator, DumpVerbosity, EntryDump, IoStats, MergeIterator,
+    MultiTableIterator, Table, TableDump, TableStats,
+};
 pub use value::Value;
 
 pub use db::{Agate, AgateOptions};
-pub use error::{Error, Result};
+pub use error::{Error, OpenPhase, Result};
 pub use skiplist::Skiplist;
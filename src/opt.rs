@@ -1,3 +1,77 @@
+use crate::cache::{BlockCache, FileCache, IndexCache};
+use crate::key_registry::KeyRegistry;
+use crate::{Error, Result};
+use bytes::Bytes;
+use proto::meta::DataKey;
+use rayon::ThreadPool;
+use std::sync::Arc;
+
+pub use proto::meta::checksum::Algorithm as ChecksumAlgorithm;
+
+/// Specifies when the checksums of an SST should be verified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumVerificationMode {
+    /// never verify checksums
+    NoVerification,
+    /// verify checksums of all blocks when opening an SST
+    OnTableRead,
+    /// verify checksum of a block every time it is read
+    OnBlockRead,
+    /// verify checksums both when opening an SST and on every block read
+    OnTableAndBlockRead,
+}
+
+impl ChecksumVerificationMode {
+    /// Returns if all blocks should be verified when opening an SST
+    pub fn verify_on_table_read(self) -> bool {
+        matches!(
+            self,
+            ChecksumVerificationMode::OnTableRead | ChecksumVerificationMode::OnTableAndBlockRead
+        )
+    }
+
+    /// Returns if a block should be verified every time it is read
+    pub fn verify_on_block_read(self) -> bool {
+        matches!(
+            self,
+            ChecksumVerificationMode::OnBlockRead | ChecksumVerificationMode::OnTableAndBlockRead
+        )
+    }
+}
+
+/// Specifies how blocks of an SST are compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionType {
+    /// blocks are not compressed
+    None,
+    /// blocks are compressed with snappy
+    Snappy,
+    /// blocks are compressed with zstd at the given level. The level is
+    /// only used when building SST, and is not stored in SST.
+    Zstd(i32),
+}
+
+impl CompressionType {
+    /// Get the value stored in `BlockOffset` for this compression type
+    pub(crate) fn to_u32(self) -> u32 {
+        match self {
+            CompressionType::None => 0,
+            CompressionType::Snappy => 1,
+            CompressionType::Zstd(_) => 2,
+        }
+    }
+
+    /// Get compression type from the value stored in `BlockOffset`
+    pub(crate) fn from_u32(v: u32) -> Option<CompressionType> {
+        match v {
+            0 => Some(CompressionType::None),
+            1 => Some(CompressionType::Snappy),
+            2 => Some(CompressionType::Zstd(0)),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Options {
     /// size of each block inside SST
@@ -6,4 +80,211 @@ pub struct Options {
     pub block_size: usize,
     /// false positive probability of bloom filter
     pub bloom_false_positive: f64,
+    /// length of user key prefixes put into prefix bloom filter, which
+    /// lets prefix scans skip SSTs. Built with `bloom_false_positive`,
+    /// and disabled if `None` or bloom filter is disabled
+    pub prefix_bloom_len: Option<u32>,
+    /// when to verify checksums of SST
+    pub checksum_mode: ChecksumVerificationMode,
+    /// algorithm of checksums of blocks and index in new SST. Checksums
+    /// are verified with the algorithm recorded along with them
+    pub checksum_algorithm: ChecksumAlgorithm,
+    /// block cache shared by all tables opened with these options,
+    /// blocks are not cached if `None`
+    pub block_cache: Option<Arc<BlockCache>>,
+    /// index cache shared by all tables opened with these options,
+    /// indexes are kept in memory for the lifetime of tables if `None`
+    pub index_cache: Option<Arc<IndexCache>>,
+    /// compression of blocks in SST
+    pub compression: CompressionType,
+    /// read SST files through memory map, SST files are read with
+    /// positional reads if disabled or mapping fails
+    pub use_mmap: bool,
+    /// file cache shared by all tables opened with these options, which
+    /// limits open files of tables read with positional reads. Files are
+    /// kept open for the lifetime of tables if `None`
+    pub file_cache: Option<Arc<FileCache>>,
+    /// data key to encrypt new SST with, SST is not encrypted if `None`.
+    /// The key must be 16, 24 or 32 bytes long
+    pub data_key: Option<DataKey>,
+    /// key registry to look up data keys of encrypted SST
+    pub key_registry: Option<Arc<KeyRegistry>>,
+    /// SSTs no larger than this keep data in memory when created, instead
+    /// of reading it back from file
+    pub flush_threshold: usize,
+    /// keep all data of SST in memory, so that reads never touch disk
+    pub load_to_ram: bool,
+    /// open SSTs written before format version and magic number were
+    /// added to footer. It will be removed in the next release
+    pub legacy_format: bool,
+    /// split reads of SSTs opened without mmap off a per-thread buffer
+    /// instead of allocating for each read. Blocks put into block cache
+    /// are always read into their own allocation
+    pub reuse_read_buffer: bool,
+    /// thread pool verifying blocks of SSTs in parallel, shared by all
+    /// tables opened with these options. rayon's global thread pool is
+    /// used if `None`
+    pub verify_checksum_pool: Option<Arc<ThreadPool>>,
+}
+
+/// Options of iterating over an SST
+#[derive(Debug, Clone, Default)]
+pub struct ReadOptions {
+    /// iterate from the biggest key to the smallest key
+    pub reversed: bool,
+    /// don't put blocks read by the iterator into block cache
+    pub no_cache: bool,
+    /// read ahead the next block on a helper thread if not zero. Only
+    /// one block is read ahead for now
+    pub prefetch_size: usize,
+    /// smallest user key to iterate over, inclusive
+    pub start_key: Option<Bytes>,
+    /// biggest user key to iterate over, exclusive
+    pub end_key: Option<Bytes>,
+}
+
+impl Options {
+    /// Check that options are consistent. `bloom_false_positive` of 0
+    /// disables the bloom filter.
+    pub fn validate(&self) -> Result<()> {
+        if self.block_size < 512 || !self.block_size.is_power_of_two() {
+            return Err(Error::Config(format!(
+                "block_size {} should be a power of two and at least 512",
+                self.block_size
+            )));
+        }
+        if !(0.0..1.0).contains(&self.bloom_false_positive) {
+            return Err(Error::Config(format!(
+                "bloom_false_positive {} should be in [0, 1)",
+                self.bloom_false_positive
+            )));
+        }
+        if self.prefix_bloom_len == Some(0) {
+            return Err(Error::Config(
+                "prefix_bloom_len should be None or at least 1".to_string(),
+            ));
+        }
+        if self.table_size != 0 && self.table_size < self.block_size as u64 {
+            return Err(Error::Config(format!(
+                "table_size {} should be 0 or at least block_size {}",
+                self.table_size, self.block_size
+            )));
+        }
+        if let Some(data_key) = &self.data_key {
+            if ![16, 24, 32].contains(&data_key.data.len()) {
+                return Err(Error::Config(format!(
+                    "data_key {} is {} bytes, should be 16, 24 or 32",
+                    data_key.key_id,
+                    data_key.data.len()
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            table_size: 2 << 20,
+            block_size: 4 * 1024,
+            bloom_false_positive: 0.01,
+            prefix_bloom_len: None,
+            checksum_mode: ChecksumVerificationMode::NoVerification,
+            checksum_algorithm: ChecksumAlgorithm::Crc32c,
+            block_cache: None,
+            index_cache: None,
+            compression: CompressionType::None,
+            use_mmap: true,
+            file_cache: None,
+            data_key: None,
+            key_registry: None,
+            flush_threshold: 0,
+            load_to_ram: false,
+            legacy_format: false,
+            reuse_read_buffer: true,
+            verify_checksum_pool: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_options() {
+        Options::default().validate().unwrap();
+        Options {
+            table_size: 0,
+            bloom_false_positive: 0.0,
+            block_size: 512,
+            ..Options::default()
+        }
+        .validate()
+        .unwrap();
+        for &len in &[16, 24, 32] {
+            Options {
+                data_key: Some(DataKey {
+                    key_id: 1,
+                    data: vec![0; len],
+                    ..DataKey::default()
+                }),
+                ..Options::default()
+            }
+            .validate()
+            .unwrap();
+        }
+
+        let invalid = vec![
+            Options {
+                block_size: 0,
+                ..Options::default()
+            },
+            Options {
+                block_size: 256,
+                ..Options::default()
+            },
+            Options {
+                block_size: 4000,
+                ..Options::default()
+            },
+            Options {
+                bloom_false_positive: -0.1,
+                ..Options::default()
+            },
+            Options {
+                bloom_false_positive: 1.0,
+                ..Options::default()
+            },
+            Options {
+                bloom_false_positive: std::f64::NAN,
+                ..Options::default()
+            },
+            Options {
+                table_size: 1024,
+                block_size: 4096,
+                ..Options::default()
+            },
+            Options {
+                prefix_bloom_len: Some(0),
+                ..Options::default()
+            },
+            Options {
+                data_key: Some(DataKey {
+                    key_id: 1,
+                    data: vec![0; 15],
+                    ..DataKey::default()
+                }),
+                ..Options::default()
+            },
+        ];
+        for opts in invalid {
+            assert!(
+                matches!(opts.validate(), Err(Error::Config(_))),
+                "{:?}",
+                opts
+            );
+        }
+    }
 }
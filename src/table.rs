@@ -1,32 +1,63 @@
 pub(crate) mod builder;
 mod iterator;
 
-use crate::checksum;
-use crate::opt::Options;
+use crate::bloom::{self, Bloom};
+use crate::cache::{BlockCache, FileCache, IndexCache};
+use crate::compression::CompressedBlock;
+use crate::entry;
+use crate::error::OpenPhase;
+use crate::format::{get_ts, key_with_ts, user_key};
+use crate::levels::KeyRange;
+use crate::opt::{ChecksumVerificationMode, CompressionType, Options, ReadOptions};
+use crate::util::{KeyComparator, COMPARATOR};
+use crate::value::Value;
 use crate::Error;
 use crate::Result;
-use bytes::{Buf, Bytes};
-use iterator::{Iterator as TableIterator, ITERATOR_NOCACHE, ITERATOR_REVERSED};
-use memmap::{Mmap, MmapOptions};
+use crate::{checksum, encryption, util};
+use builder::{Builder, FORMAT_VERSION, MAGIC, TRAILER_SIZE};
+use bytes::{Buf, Bytes, BytesMut};
+pub use iterator::BlockIterator;
+use iterator::{
+    iterator_flags, Iterator as TableIterator, IteratorError, ITERATOR_NOCACHE, ITERATOR_REVERSED,
+};
+pub use iterator::{ConcatIterator, MergeIterator, MultiTableIterator};
+use memmap2::{Mmap, MmapOptions};
 use prost::Message;
-use proto::meta::{BlockOffset, Checksum, TableIndex};
+use proto::meta::{Checksum, DataKey, TableIndex};
+use rand::Rng;
+use rayon::prelude::*;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
 use std::fs;
-use std::io::Write;
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 
 #[cfg(test)]
 mod tests;
 
-/// MmapFile stores SST data. `File` refers to a file on disk,
-/// and `Memory` refers to data in memory.
-// TODO: use a mmap library instead of handling I/O on our own
+/// MmapFile stores SST data. `Mmap` refers to a read-only memory map
+/// of a file on disk, `File` refers to a file on disk read with
+/// positional reads, `Buffered` refers to a file on disk whose data is
+/// also kept in memory, and `Memory` refers to data in memory.
 enum MmapFile {
-    File {
+    Mmap {
         name: PathBuf,
         file: fs::File,
         mmap: Mmap,
     },
+    File {
+        name: PathBuf,
+        file: FileHandle,
+        len: usize,
+    },
+    Buffered {
+        name: PathBuf,
+        file: fs::File,
+        data: Bytes,
+    },
     Memory {
         data: Bytes,
     },
@@ -36,26 +67,319 @@ impl MmapFile {
     /// Returns if data is in memory.
     pub fn is_in_memory(&self) -> bool {
         match self {
-            Self::File { .. } => false,
+            Self::Mmap { .. } | Self::File { .. } | Self::Buffered { .. } => false,
             Self::Memory { .. } => true,
         }
     }
 
-    pub fn open(path: &Path, file: std::fs::File) -> Result<Self> {
-        let mmap = unsafe { MmapOptions::new().map(&file)? };
+    /// Returns length of data.
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Mmap { mmap, .. } => mmap.len(),
+            Self::File { len, .. } => *len,
+            Self::Buffered { data, .. } | Self::Memory { data } => data.len(),
+        }
+    }
+
+    /// Open SST file. If `load_to_ram` is set, the whole file is read into
+    /// memory. Otherwise if `use_mmap` is set, the file is memory-mapped,
+    /// and falls back to positional reads if mapping fails. Files read
+    /// with positional reads are put into file cache if there is one.
+    pub fn open(path: &Path, file: std::fs::File, id: u64, opts: &Options) -> Result<Self> {
+        if opts.load_to_ram {
+            let mut data = vec![0; file.metadata()?.len() as usize];
+            read_exact_at(&file, &mut data, 0)?;
+            return Ok(MmapFile::Buffered {
+                file,
+                data: Bytes::from(data),
+                name: path.to_path_buf(),
+            });
+        }
+        if opts.use_mmap {
+            // SAFETY: SST files are immutable once written, and are never
+            // truncated or modified while a table holds the mapping, so the
+            // mapped region stays valid for the lifetime of `MmapFile`.
+            if let Ok(mmap) = unsafe { MmapOptions::new().map(&file) } {
+                return Ok(MmapFile::Mmap {
+                    file,
+                    mmap,
+                    name: path.to_path_buf(),
+                });
+            }
+        }
+        let len = file.metadata()?.len() as usize;
+        let file = Arc::new(file);
+        let file = match &opts.file_cache {
+            Some(cache) => {
+                cache.insert(id, file, 1);
+                FileHandle::Cached {
+                    id,
+                    cache: cache.clone(),
+                    reopen_lock: Mutex::new(()),
+                }
+            }
+            None => FileHandle::Owned(file),
+        };
         Ok(MmapFile::File {
             file,
-            mmap,
+            len,
             name: path.to_path_buf(),
         })
     }
+
+    /// Hint the OS about how the data will be read. `None` means the data
+    /// is no longer needed, so its cached pages can be dropped.
+    #[cfg(target_os = "linux")]
+    fn advise(&self, pattern: Option<AccessPattern>) -> Result<()> {
+        use std::os::unix::io::AsRawFd;
+
+        let (fadvice, madvice) = match pattern {
+            Some(AccessPattern::Normal) => (libc::POSIX_FADV_NORMAL, libc::MADV_NORMAL),
+            Some(AccessPattern::Sequential) => (libc::POSIX_FADV_SEQUENTIAL, libc::MADV_SEQUENTIAL),
+            Some(AccessPattern::Random) => (libc::POSIX_FADV_RANDOM, libc::MADV_RANDOM),
+            None => (libc::POSIX_FADV_DONTNEED, libc::MADV_DONTNEED),
+        };
+        let fadvise = |file: &fs::File| -> Result<()> {
+            // SAFETY: the descriptor is owned by `file`, which outlives the call.
+            match unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, fadvice) } {
+                0 => Ok(()),
+                errno => Err(io::Error::from_raw_os_error(errno).into()),
+            }
+        };
+        match self {
+            Self::Mmap { mmap, .. } => {
+                // SAFETY: the range is exactly the mapping, which is valid
+                // until `self` is dropped, and the advice doesn't change
+                // its content as it maps a file read-only.
+                let ret = unsafe {
+                    libc::madvise(mmap.as_ptr() as *mut libc::c_void, mmap.len(), madvice)
+                };
+                if ret != 0 {
+                    return Err(io::Error::last_os_error().into());
+                }
+                Ok(())
+            }
+            Self::File { name, file, .. } => fadvise(&file.get(name)?),
+            Self::Buffered { file, .. } => fadvise(file),
+            Self::Memory { .. } => Ok(()),
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn advise(&self, _pattern: Option<AccessPattern>) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// AccessPattern describes how a table is going to be read, so that
+/// the OS can tune read-ahead and caching of its file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessPattern {
+    /// no particular pattern, which is the default
+    Normal,
+    /// read from start to end once, such as by compaction
+    Sequential,
+    /// read at random offsets, such as by point gets
+    Random,
+}
+
+/// SstFile is the file of an SST shared by tables cloned with
+/// `clone_with_cache`. The file is removed when it's dropped if it's
+/// marked for deletion, so that it's removed once no table refers to it.
+struct SstFile {
+    file: MmapFile,
+    /// whether to remove the file when it's dropped
+    delete_on_drop: AtomicBool,
+}
+
+impl SstFile {
+    fn new(file: MmapFile) -> SstFile {
+        SstFile {
+            file,
+            delete_on_drop: AtomicBool::new(false),
+        }
+    }
+
+    /// Close, truncate and remove the file. Does nothing for in-memory
+    /// SST, and it's fine if the file has already been removed.
+    fn remove(&mut self) -> Result<()> {
+        self.delete_on_drop.store(false, Ordering::Release);
+        let file = std::mem::replace(&mut self.file, MmapFile::Memory { data: Bytes::new() });
+        let name = match file {
+            MmapFile::Memory { .. } => return Ok(()),
+            MmapFile::Mmap { name, file, mmap } => {
+                drop(mmap);
+                drop(file);
+                name
+            }
+            MmapFile::File { name, file, .. } => {
+                drop(file);
+                name
+            }
+            MmapFile::Buffered { name, file, .. } => {
+                drop(file);
+                name
+            }
+        };
+        let res = fs::OpenOptions::new()
+            .write(true)
+            .open(&name)
+            .and_then(|f| f.set_len(0))
+            .and_then(|_| fs::remove_file(&name));
+        match res {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl std::ops::Deref for SstFile {
+    type Target = MmapFile;
+
+    fn deref(&self) -> &MmapFile {
+        &self.file
+    }
+}
+
+impl Drop for SstFile {
+    fn drop(&mut self) {
+        if self.delete_on_drop.load(Ordering::Acquire) {
+            // errors can't be reported when dropping
+            let _ = self.remove();
+        }
+    }
+}
+
+/// FileHandle is the handle of an SST file read with positional reads.
+/// `Owned` keeps the file open for the lifetime of the table, while
+/// `Cached` closes the file once it's evicted from file cache, and
+/// reopens it on next read.
+enum FileHandle {
+    Owned(Arc<fs::File>),
+    Cached {
+        id: u64,
+        cache: Arc<FileCache>,
+        /// serializes reopening, so racing readers open the file once
+        reopen_lock: Mutex<()>,
+    },
+}
+
+impl FileHandle {
+    /// Get the opened file at `name`, reopening it if needed.
+    ///
+    /// A file evicted while being read is closed once the read is done,
+    /// so open files may exceed file cache capacity for a short while.
+    fn get(&self, name: &Path) -> Result<Arc<fs::File>> {
+        let (id, cache, reopen_lock) = match self {
+            Self::Owned(file) => return Ok(file.clone()),
+            Self::Cached {
+                id,
+                cache,
+                reopen_lock,
+            } => (*id, cache, reopen_lock),
+        };
+        if let Some(file) = cache.get(&id) {
+            return Ok(file);
+        }
+        // file cache never waits for `reopen_lock` with its own lock held,
+        // so there's no deadlock
+        let _guard = reopen_lock.lock().unwrap();
+        if let Some(file) = cache.get(&id) {
+            return Ok(file);
+        }
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .open(name)
+            .map_err(|e| Error::TableRead(format!("failed to reopen {}: {}", name.display(), e)))?;
+        let file = Arc::new(file);
+        cache.insert(id, file.clone(), 1);
+        Ok(file)
+    }
+}
+
+impl Drop for FileHandle {
+    fn drop(&mut self) {
+        // close the file, so it won't be kept open after the table is gone
+        if let Self::Cached { id, cache, .. } = self {
+            cache.remove(id);
+        }
+    }
+}
+
+/// Read exactly `buf.len()` bytes from `offset` of file, without
+/// moving the file cursor.
+#[cfg(unix)]
+fn read_exact_at(file: &fs::File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.read_exact_at(buf, offset)
+}
+
+/// Read exactly `buf.len()` bytes from `offset` of file.
+#[cfg(windows)]
+fn read_exact_at(file: &fs::File, mut buf: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+    use std::io::ErrorKind;
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Source of `TableInner::cache_id`
+static NEXT_CACHE_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Size of per-thread buffer which reads from file are carved from
+const READ_BUF_SIZE: usize = 64 << 10;
+
+thread_local! {
+    static READ_BUF: RefCell<BytesMut> = RefCell::new(BytesMut::new());
+}
+
+/// Read `size` bytes from `offset` of file.
+///
+/// Small reads are split off a per-thread buffer, so that one allocation
+/// serves many reads. Once all `Bytes` split off the buffer are dropped,
+/// `reserve` reuses the allocation instead of allocating a new one.
+fn read_at(file: &fs::File, offset: usize, size: usize) -> io::Result<Bytes> {
+    if size > READ_BUF_SIZE {
+        return read_at_owned(file, offset, size);
+    }
+    READ_BUF.with(|buf| {
+        let mut buf = buf.borrow_mut();
+        if buf.capacity() < size {
+            buf.reserve(READ_BUF_SIZE);
+        }
+        buf.resize(size, 0);
+        if let Err(e) = read_exact_at(file, &mut buf, offset as u64) {
+            buf.clear();
+            return Err(e);
+        }
+        Ok(buf.split_to(size).freeze())
+    })
+}
+
+/// Read `size` bytes from `offset` of file into an allocation of its own,
+/// for data kept long after reading, which would otherwise pin the whole
+/// per-thread buffer it's split off.
+fn read_at_owned(file: &fs::File, offset: usize, size: usize) -> io::Result<Bytes> {
+    let mut buf = vec![0; size];
+    read_exact_at(file, &mut buf, offset as u64)?;
+    Ok(Bytes::from(buf))
 }
 
 /// TableInner stores data of an SST.
 /// It is immutable once created and initialized.
 pub struct TableInner {
-    /// file struct of SST
-    file: MmapFile,
+    /// file struct of SST, shared with tables cloned by `clone_with_cache`
+    file: Arc<SstFile>,
     /// size of SST
     table_size: usize,
     /// smallest key
@@ -64,24 +388,58 @@ pub struct TableInner {
     biggest: Bytes,
     /// SST id
     id: u64,
-    /// checksum of SST
+    /// key of SST index and blocks in caches shared by tables. SST ids
+    /// are not unique, as SSTs of different directories, or in memory,
+    /// may have the same id, so every opened SST gets its own
+    cache_id: u64,
+    /// encoded checksum of SST index, stored in the footer
     checksum: Bytes,
     /// estimated size, only used on encryption or compression
-    estimated_size: u32,
-    /// index of SST
-    index: TableIndex,
+    estimated_size: u64,
+    /// index of SST, or index cache it's kept in
+    index: IndexHandle,
+    /// fields of index needed without reading it
+    summary: IndexSummary,
     /// start position of index
     index_start: usize,
     /// length of index
     index_len: usize,
+    /// data key SST is encrypted with, `None` if not encrypted
+    data_key: Option<DataKey>,
+    /// metadata set by users when building SST, kept out of index so that
+    /// it stays in memory with index cache
+    user_metadata: HashMap<String, Bytes>,
+    /// I/O statistics of SST
+    io_stats: Arc<IoStats>,
     /// table options
     opts: Options,
 }
 
+#[derive(Clone)]
 pub struct Table {
     inner: Arc<TableInner>,
 }
 
+/// Where the index of an SST is kept. An index in cache may be evicted,
+/// and is read from SST again when needed.
+#[derive(Clone)]
+enum IndexHandle {
+    Resident(Arc<TableIndex>),
+    Cached(Arc<IndexCache>),
+}
+
+/// Fields of index which are kept in table, so that reading them never
+/// fails even if the index is evicted from index cache
+#[derive(Clone, Default)]
+struct IndexSummary {
+    block_count: usize,
+    key_count: u32,
+    max_version: u64,
+    stale_data_size: u64,
+    vlog_size: u64,
+    bloom_filter_size: usize,
+}
+
 /// `AsRef<TableInner>` is only used in `init_biggest_and_smallest`
 /// to construct a table iterator from `&TableInner`.
 impl AsRef<TableInner> for TableInner {
@@ -98,38 +456,193 @@ impl TableInner {
             .read(true)
             .write(true)
             .open(path)?;
-        f.write(&data)?;
-        // TODO: pass file object directly to open and sync write
-        drop(f);
-        Self::open(path, opts)
+        f.write_all(&data)?;
+        f.sync_all()?;
+        sync_parent_dir(path)?;
+        // small tables are served from the data just written instead of
+        // reading it back from file
+        let id = parse_file_id(&path.file_name().unwrap().to_string_lossy())?;
+        let file = if opts.load_to_ram || data.len() <= opts.flush_threshold {
+            MmapFile::Buffered {
+                name: path.to_path_buf(),
+                file: f,
+                data,
+            }
+        } else {
+            MmapFile::open(path, f, id, &opts)?
+        };
+        Self::open_file(file, id, opts)
+    }
+
+    /// Create an SST at `path` with a streaming builder, see
+    /// `Table::create_streaming`
+    fn create_streaming<F>(path: &Path, opts: Options, build: F) -> Result<TableInner>
+    where
+        F: FnOnce(&mut Builder) -> Result<()>,
+    {
+        let id = parse_file_id(&path.file_name().unwrap().to_string_lossy())?;
+        let f = fs::OpenOptions::new()
+            .create_new(true)
+            .read(true)
+            .write(true)
+            .open(path)?;
+        let write = || -> Result<()> {
+            let writer = io::BufWriter::new(f.try_clone()?);
+            let mut builder = Builder::new_streaming(opts.clone(), Box::new(writer));
+            build(&mut builder)?;
+            builder.finish_streaming()?;
+            f.sync_all()?;
+            sync_parent_dir(path)?;
+            Ok(())
+        };
+        if let Err(e) = write() {
+            // don't leave a partial SST behind
+            let _ = fs::remove_file(path);
+            return Err(e);
+        }
+        let file = MmapFile::open(path, f, id, &opts)?;
+        Self::open_file(file, id, opts)
     }
 
     /// Open an existing SST on disk
     fn open(path: &Path, opts: Options) -> Result<TableInner> {
+        let open_error = |e: Error| Error::TableOpen {
+            path: path.to_string_lossy().into_owned(),
+            phase: OpenPhase::File,
+            offset: 0,
+            source: Box::new(e),
+        };
         let f = fs::OpenOptions::new()
             .read(true)
             .write(false)
             .create(false)
+            .open(path)
+            .map_err(|e| open_error(e.into()))?;
+        let id = parse_file_id(&path.file_name().unwrap().to_string_lossy())?;
+        let file = MmapFile::open(path, f, id, &opts).map_err(open_error)?;
+        Self::open_file(file, id, opts)
+    }
+
+    /// Adopt a finished SST at `src` as SST `id` in `dir`. The file is
+    /// validated before it's linked, or moved if it can't be linked, to
+    /// the SST path of `id`.
+    fn ingest(src: &Path, id: u64, dir: &Path, opts: Options) -> Result<TableInner> {
+        let f = fs::OpenOptions::new().read(true).open(src)?;
+        // data must be durable before the file becomes part of the directory
+        f.sync_all()?;
+        {
+            // blocks and files are not cached until the file is adopted,
+            // in case the file turns out to be invalid
+            let validate_opts = Options {
+                block_cache: None,
+                index_cache: None,
+                file_cache: None,
+                ..opts.clone()
+            };
+            let file = MmapFile::open(src, f, id, &validate_opts)?;
+            let inner = Self::open_file(file, id, validate_opts)?;
+            if inner.is_empty() {
+                return Err(Error::TableRead(format!(
+                    "failed to ingest table {}: key range is empty",
+                    src.display()
+                )));
+            }
+        }
+
+        let path = new_filename(id, dir);
+        if fs::hard_link(src, &path).is_err() {
+            if path.exists() {
+                return Err(Error::TableRead(format!(
+                    "failed to ingest table {}: {} already exists",
+                    src.display(),
+                    path.display()
+                )));
+            }
+            fs::rename(src, &path)?;
+            if let Some(src_dir) = src.parent().filter(|d| !d.as_os_str().is_empty()) {
+                util::sync_dir(src_dir)?;
+            }
+        }
+        util::sync_dir(dir)?;
+
+        // checksums have been verified above
+        let mut opts = opts;
+        opts.checksum_mode = match opts.checksum_mode {
+            ChecksumVerificationMode::OnTableRead => ChecksumVerificationMode::NoVerification,
+            ChecksumVerificationMode::OnTableAndBlockRead => ChecksumVerificationMode::OnBlockRead,
+            mode => mode,
+        };
+        Self::open(&path, opts)
+    }
+
+    /// Copy data of this SST to a new file at `path`, see `Table::copy_to`
+    fn copy_to(&self, path: &Path) -> Result<TableInner> {
+        let id = parse_file_id(&path.file_name().unwrap().to_string_lossy())?;
+        let opts = self.opts.clone();
+        if let Some(data) = self.data() {
+            return Self::create(path, data, opts);
+        }
+        let mut f = fs::OpenOptions::new()
+            .create_new(true)
+            .read(true)
+            .write(true)
             .open(path)?;
-        let file_name = path.file_name().unwrap().to_str().unwrap();
-        let id = parse_file_id(file_name)?;
-        let meta = f.metadata()?;
-        let table_size = meta.len();
+        let mut write = || -> Result<()> {
+            match &**self.file {
+                MmapFile::Mmap { mmap, .. } => f.write_all(&mmap[..])?,
+                MmapFile::File { name, .. } => {
+                    // read with a file of its own, as positional reads on
+                    // the shared one don't move its cursor
+                    let mut src = fs::File::open(name)?;
+                    io::copy(&mut src, &mut f)?;
+                }
+                MmapFile::Memory { .. } | MmapFile::Buffered { .. } => unreachable!(),
+            }
+            f.sync_all()?;
+            sync_parent_dir(path)?;
+            Ok(())
+        };
+        if let Err(e) = write() {
+            // don't leave a partial SST behind
+            let _ = fs::remove_file(path);
+            return Err(e);
+        }
+        let file = MmapFile::open(path, f, id, &opts)?;
+        Self::open_file(file, id, opts)
+    }
+
+    /// Open an SST from opened file with `id`
+    fn open_file(file: MmapFile, id: u64, opts: Options) -> Result<TableInner> {
         let mut inner = TableInner {
-            file: MmapFile::open(path, f)?,
-            table_size: table_size as usize,
+            table_size: file.len(),
+            file: Arc::new(SstFile::new(file)),
             smallest: Bytes::new(),
             biggest: Bytes::new(),
             id,
+            cache_id: NEXT_CACHE_ID.fetch_add(1, Ordering::Relaxed),
             checksum: Bytes::new(),
             estimated_size: 0,
-            index: TableIndex::default(),
+            index: IndexHandle::Resident(Arc::new(TableIndex::default())),
+            summary: IndexSummary::default(),
             index_start: 0,
             index_len: 0,
+            data_key: None,
+            user_metadata: HashMap::new(),
+            io_stats: Arc::new(IoStats::default()),
             opts,
         };
+        inner.init_index()?;
+        // verify blocks before reading any of them, so that corrupted data
+        // is reported as an error instead of being decoded, index has been
+        // verified when it's read
+        if inner.opts.checksum_mode.verify_on_table_read() {
+            let blocks: Vec<usize> = (0..inner.offsets_length()).collect();
+            let index = inner.fetch_index()?;
+            inner.verify_blocks(&blocks, |idx, e| {
+                inner.open_error(OpenPhase::Checksum, index.offsets[idx].offset, e)
+            })?;
+        }
         inner.init_biggest_and_smallest()?;
-        // TODO: verify checksum
         Ok(inner)
     }
 
@@ -137,144 +650,435 @@ impl TableInner {
     fn open_in_memory(data: Bytes, id: u64, opts: Options) -> Result<TableInner> {
         let table_size = data.len();
         let mut inner = TableInner {
-            file: MmapFile::Memory { data },
+            file: Arc::new(SstFile::new(MmapFile::Memory { data })),
             opts,
             table_size,
             id,
+            cache_id: NEXT_CACHE_ID.fetch_add(1, Ordering::Relaxed),
             smallest: Bytes::new(),
             biggest: Bytes::new(),
             checksum: Bytes::new(),
             estimated_size: 0,
-            index: TableIndex::default(),
+            index: IndexHandle::Resident(Arc::new(TableIndex::default())),
+            summary: IndexSummary::default(),
             index_start: 0,
             index_len: 0,
+            data_key: None,
+            user_metadata: HashMap::new(),
+            io_stats: Arc::new(IoStats::default()),
         };
+        inner.init_index()?;
         inner.init_biggest_and_smallest()?;
         Ok(inner)
     }
 
     fn init_biggest_and_smallest(&mut self) -> Result<()> {
-        let ko = self.init_index()?;
-        self.smallest = Bytes::from(ko.key.clone());
+        let index = self.fetch_index()?;
+        // an SST without any block has empty smallest and biggest keys
+        let first = match index.offsets.first() {
+            Some(first) => first,
+            None => return Ok(()),
+        };
+        self.smallest = Bytes::from(first.key.clone());
         let mut it = TableIterator::new(&self, ITERATOR_REVERSED | ITERATOR_NOCACHE);
         it.rewind();
         if !it.valid() {
-            return Err(Error::TableRead(format!(
-                "failed to initialize biggest for table {}",
-                self.filename()
-            )));
+            let e = match it.error() {
+                Some(IteratorError::Error(e)) => Error::TableRead(e.clone()),
+                _ => Error::TableRead("no entry in last block".to_string()),
+            };
+            let last = &index.offsets[index.offsets.len() - 1];
+            return Err(self.open_error(OpenPhase::LastBlock, last.offset, e));
         }
         self.biggest = Bytes::copy_from_slice(it.key());
         Ok(())
     }
 
-    fn init_index(&mut self) -> Result<&BlockOffset> {
+    fn init_index(&mut self) -> Result<()> {
+        // where parsing has got to, so that errors tell which part is bad
+        let mut at = (OpenPhase::Footer, self.table_size);
+        self.read_index(&mut at)
+            .map_err(|e| self.open_error(at.0, at.1 as u64, e))
+    }
+
+    /// Read index of SST, updating `at` with phase and offset of the part
+    /// being parsed
+    fn read_index(&mut self, at: &mut (OpenPhase, usize)) -> Result<()> {
+        // Read the tail of SST at once, which holds the footer and in most
+        // cases the whole index, so only a large index needs another read.
+        let tail_start = self.table_size.saturating_sub(TAIL_READ_SIZE);
+        *at = (OpenPhase::Footer, tail_start);
+        let tail = self.read(tail_start, self.table_size - tail_start)?;
+        let read_tail = |pos: usize, len: usize| {
+            if pos >= tail_start {
+                Ok(tail.slice(pos - tail_start..pos - tail_start + len))
+            } else {
+                self.read(pos, len)
+            }
+        };
+
         let mut read_pos = self.table_size;
 
-        // read checksum length from last 4 bytes
-        read_pos -= 4;
-        let mut buf = self.read(read_pos, 4)?;
+        // read format version and magic number from trailer
+        read_pos = step_back(read_pos, TRAILER_SIZE, "footer")?;
+        *at = (OpenPhase::Footer, read_pos);
+        let mut buf = read_tail(read_pos, TRAILER_SIZE)?;
+        let reserved = buf.get_u16();
+        let version = buf.get_u16();
+        let magic = buf.get_u32();
+        if magic == MAGIC {
+            if version != FORMAT_VERSION {
+                return Err(Error::TableRead(format!(
+                    "unsupported format version {} of table {}",
+                    version,
+                    self.filename()
+                )));
+            }
+            if reserved != 0 {
+                return Err(Error::TableRead(format!(
+                    "reserved footer bytes of table {} are not zero",
+                    self.filename()
+                )));
+            }
+        } else if self.opts.legacy_format {
+            // legacy SSTs have no trailer, and end with index checksum
+            read_pos = self.table_size;
+        } else {
+            return Err(Error::BadMagic(format!(
+                "table {} ends with {:#010x}",
+                self.filename(),
+                magic
+            )));
+        }
+
+        // read checksum length
+        *at = (OpenPhase::Checksum, read_pos);
+        read_pos = step_back(read_pos, 4, "index checksum length")?;
+        *at = (OpenPhase::Checksum, read_pos);
+        let mut buf = read_tail(read_pos, 4)?;
         let checksum_len = buf.get_u32() as usize;
 
         // read checksum
-        read_pos -= checksum_len;
-        let buf = self.read(read_pos, checksum_len)?;
-        let chksum = Checksum::decode(buf)?;
+        read_pos = step_back(read_pos, checksum_len, "index checksum")?;
+        *at = (OpenPhase::Checksum, read_pos);
+        let checksum = read_tail(read_pos, checksum_len)?;
+        let chksum = Checksum::decode(checksum.clone())?;
 
         // read index size from footer
-        read_pos -= 4;
-        let mut buf = self.read(read_pos, 4)?;
-        self.index_len = buf.get_u32() as usize;
+        *at = (OpenPhase::Index, read_pos);
+        read_pos = step_back(read_pos, 4, "index length")?;
+        *at = (OpenPhase::Index, read_pos);
+        let mut buf = read_tail(read_pos, 4)?;
+        let index_len = buf.get_u32() as usize;
 
         // read index
-        read_pos -= self.index_len;
-        self.index_start = read_pos;
-        let data = self.read(read_pos, self.index_len)?;
+        read_pos = step_back(read_pos, index_len, "index")?;
+        *at = (OpenPhase::Index, read_pos);
+        let data = read_tail(read_pos, index_len)?;
+        *at = (OpenPhase::Checksum, read_pos);
         checksum::verify_checksum(&data, &chksum)?;
+        self.checksum = checksum;
+        self.index_len = index_len;
+        self.index_start = read_pos;
+
+        *at = (OpenPhase::Index, read_pos);
+        let index = TableIndex::decode(data)?;
+        if !index.encrypted_index.is_empty() {
+            let registry = self.opts.key_registry.as_ref().ok_or_else(|| {
+                Error::InvalidDataKey(format!(
+                    "table {} is encrypted with data key {}, but no key registry is set",
+                    self.filename(),
+                    index.key_id
+                ))
+            })?;
+            self.data_key = Some(registry.data_key(index.key_id)?);
+        }
+        let index = Arc::new(decrypt_index(index, self.data_key.as_ref())?);
+        self.user_metadata = index
+            .user_metadata
+            .iter()
+            .map(|(k, v)| (k.clone(), Bytes::from(v.clone())))
+            .collect();
+
+        // check the blocks rather than current options, as the table may be
+        // written with a different compression type
+        let compressed = index
+            .offsets
+            .iter()
+            .any(|offset| offset.compression != CompressionType::None.to_u32());
+        self.estimated_size = if compressed || self.data_key.is_some() {
+            index.estimated_size
+        } else {
+            self.table_size as u64
+        };
 
-        self.index = self.read_table_index()?;
+        self.summary = IndexSummary {
+            block_count: index.offsets.len(),
+            key_count: index.key_count,
+            max_version: index.max_version,
+            stale_data_size: index.stale_data_size,
+            vlog_size: index.vlog_size,
+            bloom_filter_size: index.bloom_filter.len(),
+        };
+        self.index = match &self.opts.index_cache {
+            Some(cache) => {
+                cache.insert(self.cache_id, index, self.index_len as u64);
+                IndexHandle::Cached(cache.clone())
+            }
+            None => IndexHandle::Resident(index),
+        };
 
-        // TODO: compression
-        self.estimated_size = self.table_size as u32;
+        Ok(())
+    }
 
-        // TODO: has bloom filter
+    /// Get up to `n` keys evenly splitting the SST, chosen from the first
+    /// keys of blocks which start with `prefix`.
+    pub fn key_splits(&self, n: usize, prefix: &[u8]) -> Result<Vec<String>> {
+        if n == 0 {
+            return Ok(vec![]);
+        }
+        let index = self.fetch_index()?;
+        // blocks are picked among the ones matching `prefix`, so that a
+        // prefix covering a few blocks still gets about `n` splits
+        let matched: Vec<_> = index
+            .offsets
+            .iter()
+            .filter(|ko| ko.key.starts_with(prefix))
+            .collect();
+        let count = n.min(matched.len());
+        Ok((0..count)
+            .map(|i| {
+                let ko = matched[i * matched.len() / count];
+                String::from_utf8_lossy(user_key(&ko.key)).into_owned()
+            })
+            .collect())
+    }
 
-        Ok(&self.index.offsets[0])
+    /// Split SST at block boundaries into ranges of keys of about
+    /// `target` bytes each.
+    pub fn split_by_size(&self, target: u64) -> Result<Vec<(Bytes, Bytes)>> {
+        if self.is_empty() {
+            return Ok(vec![]);
+        }
+        let index = self.fetch_index()?;
+        let offsets = &index.offsets;
+        // first block of each range
+        let mut starts = vec![0];
+        let mut size = 0;
+        for (idx, prev) in (1..offsets.len()).zip(offsets.iter()) {
+            size += prev.len as u64;
+            if size >= target {
+                starts.push(idx);
+                size = 0;
+            }
+        }
+        size += offsets[offsets.len() - 1].len as u64;
+        // merge a small remainder into the range before it
+        if starts.len() > 1 && size < target / 2 {
+            starts.pop();
+        }
+        let mut ranges: Vec<_> = starts
+            .windows(2)
+            .map(|w| {
+                (
+                    Bytes::from(offsets[w[0]].key.clone()),
+                    Bytes::from(offsets[w[1]].key.clone()),
+                )
+            })
+            .collect();
+        let last = Bytes::from(offsets[*starts.last().unwrap()].key.clone());
+        ranges.push((last, self.biggest.clone()));
+        Ok(ranges)
     }
 
-    fn key_splits(&mut self, _n: usize, _prefix: Bytes) -> Vec<String> {
-        unimplemented!()
+    /// Estimate bytes taken by user keys in `[start, end]` from sizes of
+    /// blocks which may hold them.
+    pub fn size_of_key_range(&self, start: &[u8], end: &[u8]) -> Result<u64> {
+        if self.is_empty() || start > end || start > user_key(&self.biggest) {
+            return Ok(0);
+        }
+        let index = self.fetch_index()?;
+        let offsets = &index.offsets;
+        // older versions of `start` may be at the end of the block before
+        // the first block starting with `start`
+        let first = util::search(offsets.len(), |idx| user_key(&offsets[idx].key) >= start)
+            .saturating_sub(1);
+        let last = util::search(offsets.len(), |idx| user_key(&offsets[idx].key) > end);
+        Ok(offsets[first.min(last)..last]
+            .iter()
+            .map(|ko| ko.len as u64)
+            .sum())
     }
 
-    fn fetch_index(&self) -> &TableIndex {
-        return &self.index;
-        // TODO: encryption
+    /// Estimate offset of `key` in SST, which is the offset of the last
+    /// block starting with a key no bigger than `key`. Returns 0 if `key`
+    /// is before all blocks, and size of SST if `key` is after all keys.
+    pub fn approximate_offset_of(&self, key: &[u8]) -> Result<u64> {
+        if self.is_empty() {
+            return Ok(0);
+        }
+        if COMPARATOR.compare_key(key, &self.biggest) == std::cmp::Ordering::Greater {
+            return Ok(self.table_size as u64);
+        }
+        let index = self.fetch_index()?;
+        let offsets = &index.offsets;
+        let idx = util::search(offsets.len(), |idx| {
+            COMPARATOR.compare_key(&offsets[idx].key, key) == std::cmp::Ordering::Greater
+        });
+        Ok(match idx.checked_sub(1) {
+            Some(idx) => offsets[idx].offset,
+            None => 0,
+        })
     }
 
-    fn offsets_length(&self) -> usize {
-        self.fetch_index().offsets.len()
+    /// Get index of SST. If index cache is enabled, the index is read
+    /// from disk again when it has been evicted from cache, which fails
+    /// if it can't be read.
+    pub(crate) fn fetch_index(&self) -> Result<Arc<TableIndex>> {
+        let cache = match &self.index {
+            IndexHandle::Resident(index) => return Ok(index.clone()),
+            IndexHandle::Cached(cache) => cache,
+        };
+        if let Some(index) = cache.get(&self.cache_id) {
+            return Ok(index);
+        }
+        // the index has been verified when opening the table, and it's
+        // decrypted by `read_table_index` if the table is encrypted
+        let index = self.read_table_index().map_err(|e| {
+            Error::TableRead(format!(
+                "failed to read index of table {}: {}",
+                self.filename(),
+                e
+            ))
+        })?;
+        let index = Arc::new(index);
+        cache.insert(self.cache_id, index.clone(), self.index_len as u64);
+        Ok(index)
     }
 
-    fn offsets(&self, idx: usize) -> Option<&BlockOffset> {
-        self.fetch_index().offsets.get(idx)
+    fn offsets_length(&self) -> usize {
+        self.summary.block_count
     }
 
-    fn block(&self, idx: usize, _use_cache: bool) -> Result<Arc<Block>> {
-        // TODO: support cache
-        if idx >= self.offsets_length() {
+    fn block(&self, idx: usize, use_cache: bool) -> Result<Arc<Block>> {
+        let index = self.fetch_index()?;
+        if idx >= index.offsets.len() {
             return Err(Error::TableRead("block out of index".to_string()));
         }
-        let block_offset = self.offsets(idx).ok_or(Error::TableRead(format!(
-            "failed to get offset block {}",
-            idx
-        )))?;
-
-        let offset = block_offset.offset as usize;
-        let data = self.read(offset, block_offset.len as usize)?;
+        if use_cache {
+            if let Some(cache) = &self.opts.block_cache {
+                if let Some(block) = cache.get(&(self.cache_id, idx)) {
+                    self.io_stats.cache_hits.fetch_add(1, Ordering::Relaxed);
+                    return Ok(block);
+                }
+                self.io_stats.cache_misses.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        let block_offset = &index.offsets[idx];
+
+        let offset = usize::try_from(block_offset.offset).map_err(|_| {
+            Error::TableRead(format!(
+                "offset {} of block {} is out of range",
+                block_offset.offset, idx
+            ))
+        })?;
+        let compression = CompressionType::from_u32(block_offset.compression).ok_or_else(|| {
+            Error::TableRead(format!(
+                "unknown compression type {} of block {}",
+                block_offset.compression, idx
+            ))
+        })?;
+        // blocks in cache live long, so they are read into their own buffer
+        let data = if use_cache && self.opts.block_cache.is_some() {
+            self.read_owned(offset, block_offset.len as usize)?
+        } else {
+            self.read(offset, block_offset.len as usize)?
+        };
 
-        let mut read_pos = data.len() - 4; // first read checksum length
+        // first read checksum length
+        let mut read_pos = step_back(data.len(), 4, "block checksum length")?;
         let checksum_len = (&data[read_pos..read_pos + 4]).get_u32() as usize;
 
-        if checksum_len > data.len() {
-            return Err(Error::TableRead("invalid checksum length".to_string()));
-        }
-
         // read checksum
-        read_pos -= checksum_len;
+        read_pos = step_back(read_pos, checksum_len, "block checksum")?;
         let checksum = data.slice(read_pos..read_pos + checksum_len);
 
+        // The checksum is calculated for data on disk, which is
+        // actual data + entry index + index length, maybe compressed and encrypted.
+        let disk_data = data.slice(..read_pos);
+        if self.opts.checksum_mode.verify_on_block_read() {
+            verify_block_checksum(&disk_data, &checksum)?;
+        }
+        let raw_data = match &self.data_key {
+            Some(data_key) => decrypt_block(&disk_data, data_key)?,
+            None => disk_data.clone(),
+        };
+        let disk_data = if compression != CompressionType::None || self.data_key.is_some() {
+            Some(disk_data)
+        } else {
+            None
+        };
+        let data = CompressedBlock::new(raw_data, compression).decompress()?;
+
         // read num entries
-        read_pos -= 4;
+        let read_pos = step_back(data.len(), 4, "block entry count")?;
         let num_entries = (&data[read_pos..read_pos + 4]).get_u32() as usize;
 
-        let entries_index_start = read_pos - num_entries * 4;
-        let entries_index_end = entries_index_start + num_entries * 4;
+        let entries_index_start = step_back(read_pos, num_entries * 4, "block entry offsets")?;
+        let entries_index_end = read_pos;
 
         let mut entry_offsets_ptr = &data[entries_index_start..entries_index_end];
         let mut entry_offsets = Vec::with_capacity(num_entries);
+        let mut last_offset = 0;
         for _ in 0..num_entries {
-            entry_offsets.push(entry_offsets_ptr.get_u32_le());
+            let entry_offset = entry_offsets_ptr.get_u32_le();
+            if entry_offset < last_offset || entry_offset as usize > entries_index_start {
+                return Err(Error::TableRead(format!(
+                    "invalid entry offset {} in block {}",
+                    entry_offset, idx
+                )));
+            }
+            last_offset = entry_offset;
+            entry_offsets.push(entry_offset);
         }
 
-        Ok(Arc::new(Block {
+        let block = Block {
             offset,
             entries_index_start,
-            // Drop checksum and checksum length.
-            // The checksum is calculated for actual data + entry index + index length
-            data: data.slice(..read_pos + 4),
+            data,
             entry_offsets,
             checksum_len,
             checksum,
-        }))
-    }
+            disk_data,
+        };
 
-    fn index_key(&self) -> u64 {
-        self.id
+        let block = Arc::new(block);
+        if use_cache {
+            if let Some(cache) = &self.opts.block_cache {
+                cache.insert((self.cache_id, idx), block.clone(), block.size());
+            }
+        }
+        Ok(block)
     }
 
     /// Get number of keys in SST
     pub fn key_count(&self) -> u32 {
-        self.fetch_index().key_count
+        self.summary.key_count
+    }
+
+    /// Get value of user metadata `key` set when building SST
+    pub fn user_metadata(&self, key: &str) -> Option<&Bytes> {
+        self.user_metadata.get(key)
+    }
+
+    /// Check if the SST has no block, such as one built from an empty builder
+    pub fn is_empty(&self) -> bool {
+        self.smallest.is_empty()
+    }
+
+    /// Get size of stale entries in SST
+    pub fn stale_data_size(&self) -> usize {
+        self.summary.stale_data_size as usize
     }
 
     /// Get size of index
@@ -284,7 +1088,7 @@ impl TableInner {
 
     /// Get size of bloom filter
     pub fn bloom_filter_size(&self) -> usize {
-        self.fetch_index().bloom_filter.len()
+        self.summary.bloom_filter_size
     }
 
     /// Get size of SST
@@ -292,6 +1096,108 @@ impl TableInner {
         self.table_size as u64
     }
 
+    /// Get total length of values in value log referred to by SST
+    pub fn vlog_size(&self) -> u64 {
+        self.summary.vlog_size
+    }
+
+    /// Get size of data in SST and value log that SST refers to
+    pub fn logical_size(&self) -> u64 {
+        self.estimated_size + self.vlog_size()
+    }
+
+    fn stats(&self) -> Result<TableStats> {
+        let index = self.fetch_index()?;
+        Ok(TableStats {
+            id: self.id,
+            filename: self.filename(),
+            size: self.size(),
+            estimated_size: self.estimated_size(),
+            block_count: index.offsets.len(),
+            block_sizes: index.offsets.iter().map(|offset| offset.len).collect(),
+            key_count: index.key_count,
+            stale_data_size: index.stale_data_size as usize,
+            index_size: self.index_len,
+            bloom_filter_size: index.bloom_filter.len(),
+            smallest: self.smallest.clone(),
+            biggest: self.biggest.clone(),
+        })
+    }
+
+    fn dump(&self, verbosity: DumpVerbosity) -> Result<TableDump> {
+        let index = self.fetch_index()?;
+        let blocks = index
+            .offsets
+            .iter()
+            .enumerate()
+            .map(|(idx, ko)| {
+                let mut block = BlockDump {
+                    offset: ko.offset,
+                    len: ko.len,
+                    base_key: Bytes::from(ko.key.clone()),
+                    compression: ko.compression,
+                    entry_count: None,
+                    error: None,
+                    entries: vec![],
+                };
+                if verbosity >= DumpVerbosity::Blocks {
+                    if let Err(e) = self.dump_block(idx, verbosity, &mut block) {
+                        block.entry_count = None;
+                        block.entries.clear();
+                        block.error = Some(e.to_string());
+                    }
+                }
+                block
+            })
+            .collect();
+        Ok(TableDump {
+            id: self.id,
+            filename: self.filename(),
+            size: self.size(),
+            index_offset: self.index_start,
+            index_size: self.index_len,
+            key_count: index.key_count,
+            smallest: self.smallest.clone(),
+            biggest: self.biggest.clone(),
+            blocks,
+        })
+    }
+
+    /// Read and verify block `idx` for `dump`. Blocks are always verified
+    /// regardless of `checksum_mode`, as dumping is for finding corruption.
+    fn dump_block(&self, idx: usize, verbosity: DumpVerbosity, dump: &mut BlockDump) -> Result<()> {
+        let block = self.block(idx, false)?;
+        block.verify_checksum()?;
+        dump.entry_count = Some(block.entry_offsets.len());
+        if verbosity < DumpVerbosity::Entries {
+            return Ok(());
+        }
+        let mut it = block.iter();
+        it.seek_to_first();
+        while it.valid() {
+            let key = it.key();
+            let value = it.value();
+            dump.entries.push(EntryDump {
+                user_key: Bytes::copy_from_slice(user_key(key)),
+                ts: get_ts(key),
+                meta: value.meta,
+                user_meta: value.user_meta,
+                expires_at: value.expires_at,
+                value_len: value.value.len(),
+            });
+            it.next();
+        }
+        match it.error() {
+            Some(IteratorError::Error(e)) => Err(Error::TableRead(e.clone())),
+            _ => Ok(()),
+        }
+    }
+
+    /// Get size of data before compression and encryption
+    pub fn estimated_size(&self) -> u64 {
+        self.estimated_size
+    }
+
     /// Get smallest key of current table
     pub fn smallest(&self) -> &Bytes {
         &self.smallest
@@ -304,9 +1210,47 @@ impl TableInner {
 
     /// Get filename of current SST. Returns `<memtable>` if in-memory.
     pub fn filename(&self) -> String {
-        match &self.file {
+        match &**self.file {
             MmapFile::Memory { .. } => "<memtable>".to_string(),
-            MmapFile::File { name, .. } => name.to_string_lossy().into_owned(),
+            MmapFile::Mmap { name, .. }
+            | MmapFile::File { name, .. }
+            | MmapFile::Buffered { name, .. } => name.to_string_lossy().into_owned(),
+        }
+    }
+
+    /// Create a table of the same SST, which caches blocks in `cache`
+    /// instead. The file is shared rather than opened again, and is only
+    /// closed or removed once no table refers to it.
+    fn clone_with_cache(&self, cache: Arc<BlockCache>) -> TableInner {
+        let mut opts = self.opts.clone();
+        opts.block_cache = Some(cache);
+        TableInner {
+            file: self.file.clone(),
+            table_size: self.table_size,
+            smallest: self.smallest.clone(),
+            biggest: self.biggest.clone(),
+            id: self.id,
+            cache_id: self.cache_id,
+            checksum: self.checksum.clone(),
+            estimated_size: self.estimated_size,
+            index: self.index.clone(),
+            summary: self.summary.clone(),
+            index_start: self.index_start,
+            index_len: self.index_len,
+            data_key: self.data_key.clone(),
+            user_metadata: self.user_metadata.clone(),
+            io_stats: Arc::new(IoStats::default()),
+            opts,
+        }
+    }
+
+    /// Wrap error `e` of opening SST with where it failed
+    fn open_error(&self, phase: OpenPhase, offset: u64, e: Error) -> Error {
+        Error::TableOpen {
+            path: self.filename(),
+            phase,
+            offset,
+            source: Box::new(e),
         }
     }
 
@@ -316,61 +1260,208 @@ impl TableInner {
     }
 
     /// Check if the table doesn't contain an entry with bloom filter.
-    /// Always return false if no bloom filter is present in SST.
-    pub fn does_not_have(_hash: u32) -> bool {
-        false
-        // TODO: add bloom filter
+    /// Always return false if no bloom filter is present in SST, or bloom
+    /// filter is disabled in options.
+    pub fn does_not_have(&self, hash: u32) -> Result<bool> {
+        if self.opts.bloom_false_positive <= 0.0 {
+            return Ok(false);
+        }
+        let index = self.fetch_index()?;
+        Ok(match read_bloom_filter(&index) {
+            Some(bloom) => !bloom.may_contain(hash),
+            None => false,
+        })
     }
 
-    fn read_bloom_filter(&self) {
-        unimplemented!()
+    /// Check if the table doesn't contain a user key starting with
+    /// `prefix` with prefix bloom filter. Always return false if no prefix
+    /// bloom filter is present in SST, or `prefix` is shorter than the
+    /// prefixes in filter.
+    pub fn does_not_have_prefix(&self, prefix: &[u8]) -> Result<bool> {
+        let index = self.fetch_index()?;
+        let len = index.prefix_len as usize;
+        if len == 0 || prefix.len() < len || index.prefix_bloom_filter.is_empty() {
+            return Ok(false);
+        }
+        let bloom = Bloom::new(&index.prefix_bloom_filter);
+        Ok(!bloom.may_contain(bloom::hash(&prefix[..len])))
+    }
+
+    /// Check if SST contains the user key of `key` at a version no newer
+    /// than the version of `key`.
+    pub fn contains_key(&self, key: &[u8]) -> Result<bool> {
+        Ok(self.get_entry(key)?.is_some())
+    }
+
+    /// Get the latest version of `key` no newer than the version of `key`.
+    fn get_entry(&self, key: &[u8]) -> Result<Option<(Bytes, Value)>> {
+        if self.does_not_have(bloom::hash(user_key(key)))? {
+            return Ok(None);
+        }
+
+        let index = self.fetch_index()?;
+        // first block whose base key is greater than key
+        let idx = util::search(index.offsets.len(), |idx| {
+            COMPARATOR.compare_key(&index.offsets[idx].key, key) == std::cmp::Ordering::Greater
+        });
+
+        // The entry is either in the block before `idx`, or is the first
+        // entry of block `idx` if all entries of the previous block are
+        // smaller than key.
+        for block_idx in idx.saturating_sub(1)..(idx + 1).min(index.offsets.len()) {
+            let mut it = self.block(block_idx, true)?.iter();
+            it.seek(key);
+            match it.error() {
+                None => {
+                    if !COMPARATOR.same_key(it.key(), key) {
+                        return Ok(None);
+                    }
+                    return Ok(Some((Bytes::copy_from_slice(it.key()), it.value())));
+                }
+                Some(IteratorError::EOF) => continue,
+                Some(IteratorError::Error(e)) => return Err(Error::TableRead(e.clone())),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Get value of `user_key` visible at `snapshot_ts`, which is the
+    /// version with the highest timestamp no greater than `snapshot_ts`.
+    /// Returns `None` if there is no such version, or it is a deletion.
+    pub fn get_at_snapshot(&self, user_key: &[u8], snapshot_ts: u64) -> Result<Option<Value>> {
+        // versions of a user key are sorted from newest to oldest, so the
+        // first entry found is the newest one visible at snapshot
+        match self.get_entry(&key_with_ts(user_key, snapshot_ts))? {
+            Some((key, mut value)) if value.meta & entry::DELETE == 0 => {
+                value.version = get_ts(&key);
+                Ok(Some(value))
+            }
+            _ => Ok(None),
+        }
     }
 
     pub(crate) fn read_table_index(&self) -> Result<TableIndex> {
         let data = self.read(self.index_start, self.index_len)?;
         // TODO: prefetch
-        let result = Message::decode(data)?;
-        Ok(result)
+        let index = TableIndex::decode(data)?;
+        decrypt_index(index, self.data_key.as_ref())
     }
 
-    fn verify_checksum(&self) -> Result<()> {
-        let table_index = self.fetch_index();
-        for i in 0..table_index.offsets.len() {
-            let block = self.block(i, true)?;
-            // TODO: table opts
-            block.verify_checksum()?;
+    /// Verify checksums of index and blocks in SST. Blocks are verified
+    /// in parallel on `verify_checksum_pool`. If `sample`
+    /// is set, only that fraction of blocks chosen at random is verified.
+    /// Returns the first error found, which is not necessarily of the
+    /// first corrupted block.
+    fn verify_checksum(&self, sample: Option<f64>) -> Result<()> {
+        if let Some(sample) = sample {
+            if !(0.0..=1.0).contains(&sample) {
+                return Err(Error::Config(format!(
+                    "sample {} of blocks to verify is not in [0, 1]",
+                    sample
+                )));
+            }
         }
-        Ok(())
+        self.verify_index_checksum()?;
+
+        let n = self.offsets_length();
+        let blocks: Vec<usize> = match sample {
+            Some(sample) => {
+                let mut rng = rand::thread_rng();
+                (0..n).filter(|_| rng.gen_bool(sample)).collect()
+            }
+            None => (0..n).collect(),
+        };
+        self.verify_blocks(&blocks, |_, e| e)
+    }
+
+    /// Verify checksum of `blocks` on `verify_checksum_pool`, converting
+    /// the error of a failed block with `on_error`
+    fn verify_blocks<F>(&self, blocks: &[usize], on_error: F) -> Result<()>
+    where
+        F: Fn(usize, Error) -> Error + Sync,
+    {
+        let verify_block = |idx: &usize| {
+            self.verify_block_checksum(*idx)
+                .map_err(|e| on_error(*idx, e))
+        };
+        let verify = || blocks.par_iter().try_for_each(verify_block);
+        match &self.opts.verify_checksum_pool {
+            Some(pool) => pool.install(verify),
+            None => verify(),
+        }
+    }
+
+    fn verify_index_checksum(&self) -> Result<()> {
+        let index_data = self.read(self.index_start, self.index_len)?;
+        verify_block_checksum(&index_data, &self.checksum).map_err(|e| {
+            let msg = format!("failed to verify index of table {}: {}", self.filename(), e);
+            wrap_verify_error(e, msg)
+        })
+    }
+
+    fn verify_block_checksum(&self, idx: usize) -> Result<()> {
+        let offset = self.fetch_index()?.offsets[idx].offset;
+        let verify = || -> Result<()> {
+            let block = self.block(idx, false)?;
+            // blocks are already verified when read if checksum is verified on block read
+            if !self.opts.checksum_mode.verify_on_block_read() {
+                block.verify_checksum()?;
+            }
+            Ok(())
+        };
+        verify().map_err(|e| {
+            let msg = format!(
+                "failed to verify block {} at offset {} of table {} ({}): {}",
+                idx,
+                offset,
+                self.id,
+                self.filename(),
+                e
+            );
+            wrap_verify_error(e, msg)
+        })
     }
 
     fn read(&self, offset: usize, size: usize) -> Result<Bytes> {
-        self.bytes(offset, size)
-    }
-
-    fn bytes(&self, offset: usize, size: usize) -> Result<Bytes> {
-        match &self.file {
-            MmapFile::Memory { data } => {
-                if offset + size > data.len() {
-                    Err(Error::TableRead(format!(
-                        "out of range, offset={}, size={}, len={}",
-                        offset,
-                        size,
-                        data.len()
-                    )))
-                } else {
-                    Ok(data.slice(offset..offset + size))
-                }
+        self.bytes(offset, size, !self.opts.reuse_read_buffer)
+    }
+
+    /// Same as `read`, but data read from file doesn't share the per-thread
+    /// read buffer
+    fn read_owned(&self, offset: usize, size: usize) -> Result<Bytes> {
+        self.bytes(offset, size, true)
+    }
+
+    fn bytes(&self, offset: usize, size: usize, owned: bool) -> Result<Bytes> {
+        self.io_stats.read_count.fetch_add(1, Ordering::Relaxed);
+        self.io_stats
+            .read_bytes
+            .fetch_add(size as u64, Ordering::Relaxed);
+        let len = self.file.len();
+        let end = match offset.checked_add(size) {
+            Some(end) if end <= len => end,
+            _ => {
+                return Err(Error::TableRead(format!(
+                    "out of range, offset={}, size={}, len={}",
+                    offset, size, len
+                )))
+            }
+        };
+        match &**self.file {
+            MmapFile::Memory { data } | MmapFile::Buffered { data, .. } => {
+                Ok(data.slice(offset..end))
+            }
+            MmapFile::Mmap { mmap, .. } => {
+                // `Bytes` in bytes 0.5 can't borrow from the mapping, and
+                // blocks may outlive the table in block cache, so copy here.
+                Ok(Bytes::copy_from_slice(&mmap[offset..end]))
             }
-            MmapFile::File { mmap, .. } => {
-                if offset + size > mmap.len() {
-                    Err(Error::TableRead(format!(
-                        "out of range, offset={}, size={}, len={}",
-                        offset,
-                        size,
-                        mmap.len()
-                    )))
+            MmapFile::File { name, file, .. } => {
+                let file = file.get(name)?;
+                if owned {
+                    Ok(read_at_owned(&file, offset, size)?)
                 } else {
-                    Ok(Bytes::copy_from_slice(&mmap[offset..offset + size]))
+                    Ok(read_at(&file, offset, size)?)
                 }
             }
         }
@@ -380,59 +1471,410 @@ impl TableInner {
         self.file.is_in_memory()
     }
 
+    /// Get data of SST if it's kept in memory. The data is shared, not copied.
+    fn data(&self) -> Option<Bytes> {
+        match &**self.file {
+            MmapFile::Memory { data } | MmapFile::Buffered { data, .. } => Some(data.clone()),
+            MmapFile::Mmap { .. } | MmapFile::File { .. } => None,
+        }
+    }
+
+    /// Close, truncate and remove SST file. Does nothing for in-memory
+    /// SST, and it's fine if the file has already been removed. Fails if
+    /// the file is still shared with a cloned table.
+    fn remove_file(&mut self) -> Result<()> {
+        let file = std::mem::replace(
+            &mut self.file,
+            Arc::new(SstFile::new(MmapFile::Memory { data: Bytes::new() })),
+        );
+        match Arc::try_unwrap(file) {
+            Ok(mut file) => file.remove(),
+            Err(file) => {
+                self.file = file;
+                Err(Error::TableRead(format!(
+                    "failed to remove table {}: file is shared with another table",
+                    self.filename()
+                )))
+            }
+        }
+    }
+
+    /// Close and remove SST file. Does nothing for in-memory SST.
+    fn delete(mut self) -> Result<()> {
+        self.remove_file()
+    }
+
+    /// Get maximum version of entries in SST. Returns 0 for SSTs
+    /// built without max version.
     fn max_version(&self) -> u64 {
-        unimplemented!()
-        // self.fetch_index()?.max_version()
+        self.summary.max_version
     }
 }
 
-/*
-impl Drop for TableInner {
-    fn drop(&mut self) {
-        let f = match self.file.take() {
-            Some(f) => f,
-            None => return,
-        };
-        f.file.set_len(0).unwrap();
-        drop(f.file);
-        fs::remove_file(&f.path).unwrap();
+/// Async versions of the table I/O which may block. Reads are done by
+/// the sync code on tokio's blocking threads, so only the I/O boundary
+/// differs from the sync API.
+#[cfg(feature = "tokio")]
+impl Table {
+    /// Open an existing SST on disk without blocking the async runtime
+    pub async fn open_async(path: &Path, opts: Options) -> Result<Table> {
+        let path = path.to_path_buf();
+        spawn_blocking(move || Table::open(&path, opts)).await
+    }
+
+    /// Get one block from table without blocking the async runtime
+    pub(crate) async fn block_async(
+        &self,
+        block_pos: usize,
+        use_cache: bool,
+    ) -> Result<Arc<Block>> {
+        let table = self.clone();
+        spawn_blocking(move || table.block(block_pos, use_cache)).await
     }
+
+    /// Async version of `get`
+    pub async fn get_async(&self, user_key: &[u8], version: u64) -> Result<Option<Value>> {
+        let table = self.clone();
+        let user_key = user_key.to_vec();
+        spawn_blocking(move || table.get(&user_key, version)).await
+    }
+}
+
+/// Run `f` on tokio's blocking threads
+#[cfg(feature = "tokio")]
+async fn spawn_blocking<T, F>(f: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T> + Send + 'static,
+{
+    match tokio::task::spawn_blocking(f).await {
+        Ok(res) => res,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(e) => Err(Error::TableRead(format!("blocking task failed: {}", e))),
+    }
+}
+
+/// I/O statistics of an SST, see `Table::io_stats`.
+#[derive(Debug, Default)]
+pub struct IoStats {
+    /// bytes read from SST, including reads from memory
+    pub read_bytes: AtomicU64,
+    /// number of reads from SST
+    pub read_count: AtomicU64,
+    /// number of blocks found in block cache
+    pub cache_hits: AtomicU64,
+    /// number of blocks not found in block cache
+    pub cache_misses: AtomicU64,
+}
+
+impl IoStats {
+    /// Reset all statistics to 0
+    pub fn reset(&self) {
+        self.read_bytes.store(0, Ordering::Relaxed);
+        self.read_count.store(0, Ordering::Relaxed);
+        self.cache_hits.store(0, Ordering::Relaxed);
+        self.cache_misses.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Statistics of an SST, see `Table::stats`.
+#[derive(Debug, Clone)]
+pub struct TableStats {
+    /// SST id
+    pub id: u64,
+    /// file name of SST, `<memtable>` for in-memory SST
+    pub filename: String,
+    /// size of SST on disk
+    pub size: u64,
+    /// size of data before compression and encryption
+    pub estimated_size: u64,
+    /// number of blocks
+    pub block_count: usize,
+    /// on-disk size of each block, including its checksum
+    pub block_sizes: Vec<u32>,
+    /// number of entries
+    pub key_count: u32,
+    /// size of entries marked stale when building SST
+    pub stale_data_size: usize,
+    /// size of index
+    pub index_size: usize,
+    /// size of bloom filter, which is part of index
+    pub bloom_filter_size: usize,
+    /// smallest key
+    pub smallest: Bytes,
+    /// biggest key
+    pub biggest: Bytes,
+}
+
+/// How much of the layout of an SST `Table::dump` describes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DumpVerbosity {
+    /// blocks as recorded in index, without reading any block
+    Index,
+    /// read and verify every block, and count its entries
+    Blocks,
+    /// describe every entry of every block as well
+    Entries,
+}
+
+/// Physical layout of an SST, see `Table::dump`.
+#[derive(Debug, Clone)]
+pub struct TableDump {
+    /// SST id
+    pub id: u64,
+    /// file name of SST, `<memtable>` for in-memory SST
+    pub filename: String,
+    /// size of SST on disk
+    pub size: u64,
+    /// offset of index
+    pub index_offset: usize,
+    /// size of index
+    pub index_size: usize,
+    /// number of entries recorded in index
+    pub key_count: u32,
+    /// smallest key
+    pub smallest: Bytes,
+    /// biggest key
+    pub biggest: Bytes,
+    /// blocks in the order of offsets
+    pub blocks: Vec<BlockDump>,
+}
+
+/// Layout of a block in `TableDump`
+#[derive(Debug, Clone)]
+pub struct BlockDump {
+    /// offset of block in SST
+    pub offset: u64,
+    /// on-disk length of block, including its checksum
+    pub len: u32,
+    /// first key of block, as recorded in index
+    pub base_key: Bytes,
+    /// compression type recorded in index
+    pub compression: u32,
+    /// number of entries, `None` if blocks are not read or it is corrupt
+    pub entry_count: Option<usize>,
+    /// why the block can't be read or fails checksum verification
+    pub error: Option<String>,
+    /// entries of block, only with `DumpVerbosity::Entries`
+    pub entries: Vec<EntryDump>,
+}
+
+impl BlockDump {
+    /// Check if the block can't be read or fails checksum verification
+    pub fn is_corrupt(&self) -> bool {
+        self.error.is_some()
+    }
+}
+
+/// Layout of an entry in `BlockDump`
+#[derive(Debug, Clone)]
+pub struct EntryDump {
+    /// user key without timestamp
+    pub user_key: Bytes,
+    /// timestamp of key
+    pub ts: u64,
+    /// meta of value
+    pub meta: u8,
+    /// user meta of value
+    pub user_meta: u8,
+    /// expiration time of value, 0 if it never expires
+    pub expires_at: u64,
+    /// length of value
+    pub value_len: usize,
 }
-*/
 
 /// Block contains several entries. It can be obtained from an SST.
 #[derive(Default)]
 pub struct Block {
     offset: usize,
+    /// uncompressed data of entries, entry index and index length
     data: Bytes,
     checksum: Bytes,
     entries_index_start: usize,
     entry_offsets: Vec<u32>,
     checksum_len: usize,
+    /// data on disk if the block is compressed or encrypted, which is what
+    /// checksum is calculated for
+    disk_data: Option<Bytes>,
 }
 
 impl Block {
-    fn size(&self) -> u64 {
+    /// Get an iterator over entries of the block
+    pub fn iter(self: Arc<Self>) -> BlockIterator {
+        BlockIterator::new(self)
+    }
+
+    /// Get memory footprint of the block, used as its weight in block cache
+    pub(crate) fn size(&self) -> u64 {
         3 * std::mem::size_of::<usize>() as u64
             + self.data.len() as u64
             + self.checksum.len() as u64
             + self.entry_offsets.len() as u64 * std::mem::size_of::<u32>() as u64
+            + self.disk_data.as_ref().map_or(0, |d| d.len() as u64)
     }
 
     fn verify_checksum(&self) -> Result<()> {
-        let chksum = prost::Message::decode(self.checksum.clone())?;
-        checksum::verify_checksum(&self.data, &chksum)
+        let data = self.disk_data.as_ref().unwrap_or(&self.data);
+        verify_block_checksum(data, &self.checksum)
     }
 }
 
-fn parse_file_id(name: &str) -> Result<u64> {
-    if !name.ends_with(".sst") {
+/// Number of bytes read from the end of SST when opening it
+const TAIL_READ_SIZE: usize = 64 << 10;
+
+/// Move `read_pos` back by `len` bytes, failing if it would go before the
+/// start of the data.
+fn step_back(read_pos: usize, len: usize, what: &str) -> Result<usize> {
+    read_pos
+        .checked_sub(len)
+        .ok_or_else(|| Error::TableRead(format!("truncated {} at {}", what, read_pos)))
+}
+
+/// Decode bloom filter from index. The filter is only a view into
+/// the index, so no data is copied.
+fn read_bloom_filter(index: &TableIndex) -> Option<Bloom> {
+    if index.bloom_filter.is_empty() {
+        return None;
+    }
+    Some(Bloom::new(&index.bloom_filter))
+}
+
+/// Decrypt index if it is encrypted
+fn decrypt_index(index: TableIndex, data_key: Option<&DataKey>) -> Result<TableIndex> {
+    if index.encrypted_index.is_empty() {
+        return Ok(index);
+    }
+    let data_key = data_key.ok_or_else(|| {
+        Error::InvalidDataKey(format!("no data key {} to decrypt index", index.key_id))
+    })?;
+    let mut data = index.encrypted_index;
+    encryption::xor_block(&mut data, &data_key.data, &index.iv)?;
+    match TableIndex::decode(Bytes::from(data)) {
+        // only an empty SST has no block and no key
+        Ok(index) if !index.offsets.is_empty() || index.key_count == 0 => Ok(index),
+        _ => Err(Error::InvalidDataKey(format!(
+            "failed to decrypt index with data key {}",
+            data_key.key_id
+        ))),
+    }
+}
+
+/// Decrypt block data on disk, which is encrypted data followed by IV
+fn decrypt_block(data: &[u8], data_key: &DataKey) -> Result<Bytes> {
+    if data.len() < encryption::IV_SIZE {
+        return Err(Error::TableRead("encrypted block too short".to_string()));
+    }
+    let (data, iv) = data.split_at(data.len() - encryption::IV_SIZE);
+    let mut data = data.to_vec();
+    encryption::xor_block(&mut data, &data_key.data, iv)?;
+    Ok(Bytes::from(data))
+}
+
+fn verify_block_checksum(data: &[u8], checksum: &Bytes) -> Result<()> {
+    let chksum = Checksum::decode(checksum.clone())?;
+    checksum::verify_checksum(data, &chksum)
+}
+
+/// Add context `msg` to an error from verifying checksum, keeping
+/// corruption distinguishable from failing to read the data
+fn wrap_verify_error(e: Error, msg: String) -> Error {
+    if e.is_corruption() {
+        Error::Corruption(msg)
+    } else {
+        Error::TableRead(msg)
+    }
+}
+
+const SST_FILE_EXT: &str = ".sst";
+const VLOG_FILE_EXT: &str = ".vlog";
+const WAL_FILE_EXT: &str = ".wal";
+
+/// Type of a file in the data directory, told by its extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FileType {
+    Sst,
+    Vlog,
+    Wal,
+}
+
+impl FileType {
+    fn ext(self) -> &'static str {
+        match self {
+            FileType::Sst => SST_FILE_EXT,
+            FileType::Vlog => VLOG_FILE_EXT,
+            FileType::Wal => WAL_FILE_EXT,
+        }
+    }
+}
+
+/// Parse file id and type from file name like `000001.sst`, `000001.vlog`
+/// or `000001.wal`
+pub(crate) fn parse_file_id_with_ext(name: &str) -> Result<(u64, FileType)> {
+    let (id, file_type) = [FileType::Sst, FileType::Vlog, FileType::Wal]
+        .iter()
+        .find_map(|t| name.strip_suffix(t.ext()).map(|id| (id, *t)))
+        .ok_or_else(|| Error::InvalidFilename(name.to_string()))?;
+    // `parse` accepts a leading `+`, and fails if id overflows u64
+    if id.is_empty() || !id.bytes().all(|b| b.is_ascii_digit()) {
         return Err(Error::InvalidFilename(name.to_string()));
     }
-    match name[..name.len() - 4].parse() {
-        Ok(id) => Ok(id),
-        Err(_) => Err(Error::InvalidFilename(name.to_string())),
+    let id = id
+        .parse()
+        .map_err(|_| Error::InvalidFilename(name.to_string()))?;
+    Ok((id, file_type))
+}
+
+/// Parse SST id from file name like `000001.sst`
+fn parse_file_id(name: &str) -> Result<u64> {
+    match parse_file_id_with_ext(name)? {
+        (id, FileType::Sst) => Ok(id),
+        _ => Err(Error::InvalidFilename(name.to_string())),
+    }
+}
+
+/// Sync directory of `path`, so that a file created there won't be lost on crash
+fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => util::sync_dir(dir),
+        _ => util::sync_dir(Path::new(".")),
+    }
+}
+
+/// Get file name of SST with `id`
+pub fn id_to_filename(id: u64) -> String {
+    format!("{:06}{}", id, SST_FILE_EXT)
+}
+
+/// Get path of SST with `id` in `dir`
+pub fn new_filename(id: u64, dir: &Path) -> PathBuf {
+    dir.join(id_to_filename(id))
+}
+
+/// List SST files in `dir` by id. Files not named like SSTs are ignored,
+/// and it's an error if two files have the same id, like `1.sst` and
+/// `000001.sst`.
+pub fn scan_sst_dir(dir: &Path) -> Result<BTreeMap<u64, PathBuf>> {
+    let mut tables = BTreeMap::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        let id = match entry.file_name().to_str().map(parse_file_id) {
+            Some(Ok(id)) => id,
+            _ => continue,
+        };
+        let path = entry.path();
+        if let Some(existing) = tables.insert(id, path.clone()) {
+            return Err(Error::InvalidFilename(format!(
+                "duplicate SST id {}: {} and {}",
+                id,
+                existing.display(),
+                path.display()
+            )));
+        }
     }
+    Ok(tables)
 }
 
 impl Table {
@@ -443,13 +1885,60 @@ impl Table {
         })
     }
 
+    /// Create an SST from a table builder. Tables no larger than
+    /// `flush_threshold` in options keep data in memory after writing,
+    /// so it is never read back from the file.
+    pub fn from_builder(path: &Path, mut builder: Builder, opts: Options) -> Result<Table> {
+        Self::create(path, builder.finish(), opts)
+    }
+
+    /// Create an SST at `path` without keeping it in memory. `build` adds
+    /// entries to a streaming builder, which writes each block to the file
+    /// once it's finished, so memory usage doesn't grow with the size of
+    /// SST. The file is removed if `build` or writing fails.
+    pub fn create_streaming<F>(path: &Path, opts: Options, build: F) -> Result<Table>
+    where
+        F: FnOnce(&mut Builder) -> Result<()>,
+    {
+        Ok(Table {
+            inner: Arc::new(TableInner::create_streaming(path, opts, build)?),
+        })
+    }
+
     /// Open an existing SST on disk
     pub fn open(path: &Path, opts: Options) -> Result<Table> {
+        opts.validate()?;
         Ok(Table {
             inner: Arc::new(TableInner::open(path, opts)?),
         })
     }
 
+    /// Adopt an SST file built out of band, such as for bulk loading, as
+    /// SST `id` in `dir`. The file is hard-linked to the SST path of `id`,
+    /// or moved there if it can't be linked, and the directory is synced.
+    ///
+    /// The footer, index and checksums (per `checksum_mode`) are validated
+    /// before the file is adopted, and files without any key are refused.
+    /// The opened table keeps the id and path in `dir`. Data in memory can
+    /// be written with `create` instead.
+    pub fn ingest(src: &Path, id: u64, dir: &Path, opts: Options) -> Result<Table> {
+        opts.validate()?;
+        Ok(Table {
+            inner: Arc::new(TableInner::ingest(src, id, dir, opts)?),
+        })
+    }
+
+    /// Copy this SST to a new file at `dest_path`, such as to move it to
+    /// another storage tier, and open the copy with the same options. The
+    /// copy has the id in `dest_path`, and `dest_path` must not exist.
+    /// Data of in-memory tables is written to the file. This table is
+    /// left untouched, and the partial file is removed if copying fails.
+    pub fn copy_to(&self, dest_path: &Path) -> Result<Table> {
+        Ok(Table {
+            inner: Arc::new(self.inner.copy_to(dest_path)?),
+        })
+    }
+
     /// Open an existing SST from data in memory
     pub fn open_in_memory(data: Bytes, id: u64, opts: Options) -> Result<Table> {
         Ok(Table {
@@ -457,14 +1946,71 @@ impl Table {
         })
     }
 
+    /// Open an existing SST from data in a `Vec`, which is taken over
+    /// without copying
+    pub fn open_in_memory_from_vec(data: Vec<u8>, id: u64, opts: Options) -> Result<Table> {
+        Self::open_in_memory(Bytes::from(data), id, opts)
+    }
+
+    /// Get the exact bytes of this table if its data is kept in memory,
+    /// such as to persist an in-memory table to disk. Blocks and the
+    /// returned data share the same buffer, so no data is copied.
+    pub fn data(&self) -> Option<Bytes> {
+        self.inner.data()
+    }
+
+    /// Remove the SST file when the last reference to this table is
+    /// dropped. Files of in-memory tables are never removed.
+    pub fn mark_delete(&self) {
+        self.inner
+            .file
+            .delete_on_drop
+            .store(true, Ordering::Release);
+    }
+
+    /// Keep the SST file when this table is dropped, which is the default
+    pub fn mark_save(&self) {
+        self.inner
+            .file
+            .delete_on_drop
+            .store(false, Ordering::Release);
+    }
+
+    /// Delete the SST file of this table. Returns an error if the
+    /// table is still referenced elsewhere, or its file is shared with
+    /// a table from `clone_with_cache`.
+    pub fn delete(self) -> Result<()> {
+        match Arc::try_unwrap(self.inner) {
+            Ok(inner) => inner.delete(),
+            Err(inner) => Err(Error::TableRead(format!(
+                "failed to delete table {}: still referenced",
+                inner.filename()
+            ))),
+        }
+    }
+
+    /// Create a table of the same SST whose blocks are cached in `cache`,
+    /// such as when compaction moves the table to another level. Unlike
+    /// `clone`, which shares everything, the new table only shares the
+    /// file and metadata with this one, and has its own I/O stats.
+    ///
+    /// The file is reference-counted: it stays open until both tables are
+    /// dropped. Marking either table for deletion marks both, and the
+    /// file is removed when the last of them is dropped.
+    pub fn clone_with_cache(&self, cache: Arc<BlockCache>) -> Table {
+        Table {
+            inner: Arc::new(self.inner.clone_with_cache(cache)),
+        }
+    }
+
     /// Get block numbers
     pub(crate) fn offsets_length(&self) -> usize {
         self.inner.offsets_length()
     }
 
-    /// Get all block offsets
-    pub(crate) fn offsets(&self, idx: usize) -> Option<&BlockOffset> {
-        self.inner.offsets(idx)
+    /// Get index of this table
+    pub(crate) fn fetch_index(&self) -> Result<Arc<TableIndex>> {
+        self.inner.fetch_index()
     }
 
     /// Get one block from table
@@ -473,12 +2019,241 @@ impl Table {
     }
 
     /// Get an iterator to this table
-    pub fn new_iterator(&self, opt: usize) -> TableIterator<Arc<TableInner>> {
-        TableIterator::new(self.inner.clone(), opt)
+    pub fn new_iterator(&self, opts: ReadOptions) -> TableIterator<Arc<TableInner>> {
+        let mut it = TableIterator::new_shared(self.inner.clone(), iterator_flags(&opts));
+        it.set_bounds(opts.start_key, opts.end_key);
+        it
+    }
+
+    /// Get size of entries marked stale when building this table, which
+    /// compaction may prefer to rewrite
+    pub fn stale_data_size(&self) -> usize {
+        self.inner.stale_data_size()
     }
 
     /// Get max version of this table
     pub fn max_version(&self) -> u64 {
         self.inner.max_version()
     }
+
+    /// Get number of keys in this table
+    pub fn key_count(&self) -> u32 {
+        self.inner.key_count()
+    }
+
+    /// Get value of user metadata `key` set by `Builder::set_user_metadata`
+    /// when building this table, `None` if it's not set
+    pub fn user_metadata(&self, key: &str) -> Option<&Bytes> {
+        self.inner.user_metadata(key)
+    }
+
+    /// Hint the OS about how this table is going to be read, with
+    /// `posix_fadvise` or with `madvise` if it's memory-mapped. Does
+    /// nothing for tables in memory, or on platforms other than Linux.
+    pub fn set_access_pattern(&self, pattern: AccessPattern) -> Result<()> {
+        self.inner.file.advise(Some(pattern))
+    }
+
+    /// Tell the OS that cached pages of this table can be dropped, such
+    /// as after compaction has consumed it. The table can still be read.
+    pub fn release_cache(&self) -> Result<()> {
+        self.inner.file.advise(None)
+    }
+
+    /// Get up to `n` user keys splitting this table into roughly even
+    /// ranges, which can be scanned in parallel. Only keys starting with
+    /// `prefix` are returned.
+    ///
+    /// ```
+    /// use agatedb::{key_with_ts, Table, TableBuilder, TableOptions, Value};
+    /// use bytes::Bytes;
+    ///
+    /// let opts = TableOptions::default();
+    /// let mut builder = TableBuilder::new(opts.clone());
+    /// for i in 0..10000 {
+    ///     let key = key_with_ts(format!("key{:05}", i).as_str(), 0);
+    ///     builder.add(&key, Value::new(Bytes::from(i.to_string())), 0).unwrap();
+    /// }
+    /// let table = Table::open_in_memory(builder.finish(), 1, opts).unwrap();
+    /// let splits = table.key_splits(4, b"key").unwrap();
+    /// assert!(!splits.is_empty() && splits.len() <= 4);
+    /// assert!(splits.windows(2).all(|w| w[0] < w[1]));
+    /// ```
+    pub fn key_splits(&self, n: usize, prefix: &[u8]) -> Result<Vec<String>> {
+        self.inner.key_splits(n, prefix)
+    }
+
+    /// Split this table at block boundaries into ranges of keys with
+    /// timestamps of about `target` bytes each, such as to divide work
+    /// of compaction, without reading data. A range `(start, end)` holds
+    /// keys in `[start, end)`, where `end` is the start of the next range,
+    /// except the last range, which ends at `biggest` inclusively. A
+    /// remainder smaller than half of `target` is merged into the last
+    /// range. Returns no range if the table is empty.
+    pub fn split_by_size(&self, target: u64) -> Result<Vec<(Bytes, Bytes)>> {
+        self.inner.split_by_size(target)
+    }
+
+    /// Estimate bytes taken by user keys in `[start, end]` in this table
+    /// without reading data. Whole blocks are counted, so the estimate
+    /// may be over by up to one block on each end.
+    pub fn size_of_key_range(&self, start: &[u8], end: &[u8]) -> Result<u64> {
+        self.inner.size_of_key_range(start, end)
+    }
+
+    /// Estimate offset of `key`, a key with timestamp, in this table from
+    /// its index, such as to estimate data to compact before `key`.
+    pub fn approximate_offset_of(&self, key: &[u8]) -> Result<u64> {
+        self.inner.approximate_offset_of(key)
+    }
+
+    /// Get size of this table
+    pub fn size(&self) -> u64 {
+        self.inner.size()
+    }
+
+    /// Get I/O statistics of this table, which are updated as the table is read
+    pub fn io_stats(&self) -> Arc<IoStats> {
+        self.inner.io_stats.clone()
+    }
+
+    /// Get statistics of this table, computed from its index without
+    /// reading any block.
+    pub fn stats(&self) -> Result<TableStats> {
+        self.inner.stats()
+    }
+
+    /// Describe the physical layout of this table, for debugging corrupt
+    /// or unexpectedly large tables. Blocks are described from the index
+    /// with `DumpVerbosity::Index`, and are read and verified at higher
+    /// verbosity. A block which can't be read or fails verification is
+    /// marked corrupt, and the rest of the table is still dumped.
+    pub fn dump(&self, verbosity: DumpVerbosity) -> Result<TableDump> {
+        self.inner.dump(verbosity)
+    }
+
+    /// Get estimated size of data in this table. It's the uncompressed size
+    /// if the table is compressed or encrypted, otherwise the same as `size`.
+    pub fn estimated_size(&self) -> u64 {
+        self.inner.estimated_size()
+    }
+
+    /// Get size of the SST file, the same as `size`
+    pub fn on_disk_size(&self) -> u64 {
+        self.inner.size()
+    }
+
+    /// Get size of data this table holds, which is `estimated_size` plus
+    /// the values in value log its entries refer to. Compaction should use
+    /// it for level sizes, as values in value log are moved along with
+    /// their keys.
+    pub fn logical_size(&self) -> u64 {
+        self.inner.logical_size()
+    }
+
+    /// Get the encoded checksum of this table, which covers its index.
+    /// Each block is covered by its own checksum.
+    pub fn checksum(&self) -> Bytes {
+        self.inner.checksum.clone()
+    }
+
+    /// Verify checksums of the index and blocks of this table in parallel.
+    /// If `sample` is set, only that fraction of blocks chosen at random is
+    /// verified, as a cheap health check. The error tells which block
+    /// failed, and the expected and actual checksums.
+    pub fn verify_checksum(&self, sample: Option<f64>) -> Result<()> {
+        self.inner.verify_checksum(sample)
+    }
+
+    /// Verify checksums of the index and all blocks of this table in
+    /// parallel. Same as `verify_checksum(None)`.
+    pub fn verify_checksum_parallel(&self) -> Result<()> {
+        self.verify_checksum(None)
+    }
+
+    /// Get smallest key of this table
+    pub fn smallest(&self) -> &Bytes {
+        self.inner.smallest()
+    }
+
+    /// Get smallest user key of this table, without timestamp. Returns
+    /// an empty key if the table is empty.
+    pub fn smallest_user_key(&self) -> &[u8] {
+        if self.is_empty() {
+            return &[];
+        }
+        user_key(&self.inner.smallest)
+    }
+
+    /// Get biggest user key of this table, without timestamp. Returns
+    /// an empty key if the table is empty.
+    pub fn biggest_user_key(&self) -> &[u8] {
+        if self.is_empty() {
+            return &[];
+        }
+        user_key(&self.inner.biggest)
+    }
+
+    /// Check if this table has no block, so it holds no key
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Get range of user keys in this table
+    pub fn key_range(&self) -> KeyRange {
+        KeyRange::from_table(self)
+    }
+
+    /// Check if this table contains any user key in `range`, regardless
+    /// of versions
+    pub fn overlaps_with(&self, range: &KeyRange) -> bool {
+        self.key_range().overlaps_with(range)
+    }
+
+    /// Get biggest key of this table
+    pub fn biggest(&self) -> &Bytes {
+        self.inner.biggest()
+    }
+
+    /// Get the latest version of the user key of `key` whose version is not
+    /// newer than the version of `key`, returning the full key and value.
+    ///
+    /// It reads at most two blocks without creating an iterator over the table,
+    /// and returns early if the bloom filter doesn't contain the key.
+    pub fn get_entry(&self, key: &[u8]) -> Result<Option<(Bytes, Value)>> {
+        self.inner.get_entry(key)
+    }
+
+    /// Get value of `user_key` at `version`, or at the latest version older
+    /// than `version` if there is no exact match.
+    pub fn get(&self, user_key: &[u8], version: u64) -> Result<Option<Value>> {
+        let key = key_with_ts(user_key, version);
+        Ok(self.get_entry(&key)?.map(|(_, value)| value))
+    }
+
+    /// Get value of `user_key` visible at `snapshot_ts`. Unlike `get`,
+    /// deleted keys are reported as `None`, and the version of the value
+    /// is set.
+    pub fn get_at_snapshot(&self, user_key: &[u8], snapshot_ts: u64) -> Result<Option<Value>> {
+        self.inner.get_at_snapshot(user_key, snapshot_ts)
+    }
+
+    /// Check if this table contains the user key of `key` at a version no
+    /// newer than the version of `key`. Like `get_entry`, only the candidate
+    /// blocks are read, and the bloom filter is checked first.
+    pub fn contains_key(&self, key: &[u8]) -> Result<bool> {
+        self.inner.contains_key(key)
+    }
+
+    /// Check if the table doesn't contain a user key with bloom filter.
+    /// `hash` is the farmhash fingerprint32 of the user key.
+    pub fn does_not_have(&self, hash: u32) -> Result<bool> {
+        self.inner.does_not_have(hash)
+    }
+
+    /// Check if the table doesn't contain a user key starting with
+    /// `prefix` with prefix bloom filter.
+    pub fn does_not_have_prefix(&self, prefix: &[u8]) -> Result<bool> {
+        self.inner.does_not_have_prefix(prefix)
+    }
 }
@@ -1,9 +1,19 @@
-use crate::opt::Options;
+use crate::bloom::{self, Bloom};
+use crate::compression::CompressedBlock;
+use crate::format::get_ts;
+use crate::opt::{CompressionType, Options};
+use crate::util::{KeyComparator, COMPARATOR};
 use crate::value::Value;
-use crate::{checksum, util};
+use crate::{checksum, encryption, util};
+use crate::{Error, Result};
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use prost::Message;
-use proto::meta::{checksum::Algorithm as ChecksumAlg, BlockOffset, Checksum, TableIndex};
+use proto::meta::{BlockOffset, Checksum, TableIndex};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::{self, Write};
+use std::ops::Range;
 
 /// Entry header stores the difference between current key and block base key.
 /// `overlap` is the common prefix of key and base key, and diff is the length
@@ -17,6 +27,14 @@ pub struct Header {
 
 pub const HEADER_SIZE: usize = std::mem::size_of::<Header>();
 
+/// Magic number at the end of every SST, "AGTB" in ASCII
+pub const MAGIC: u32 = 0x4147_5442;
+/// Version of SST format written by builder
+pub const FORMAT_VERSION: u16 = 1;
+/// Size of the trailer after index checksum, which holds 2 reserved
+/// bytes, 2 bytes of format version and 4 bytes of magic number
+pub const TRAILER_SIZE: usize = 8;
+
 impl Header {
     pub fn encode(&self, bytes: &mut BytesMut) {
         bytes.put_u32_le((self.overlap as u32) << 16 | self.diff as u32);
@@ -29,47 +47,212 @@ impl Header {
     }
 }
 
+/// Stats of a finished block, so that the table can be split at block
+/// boundaries. Counts and sizes include all previous blocks.
+#[derive(Default)]
+struct BlockStats {
+    key_count: usize,
+    prefix_count: usize,
+    estimated_size: u64,
+    stale_data_size: u64,
+    vlog_size: u64,
+    /// max version of entries in this block only
+    max_version: u64,
+}
+
 /// Builder builds an SST.
 pub struct Builder {
     buf: BytesMut,
     base_key: Bytes,
-    base_offset: u32,
+    base_offset: u64,
     entry_offsets: Vec<u32>,
     table_index: TableIndex,
-    key_hashes: Vec<u64>,
+    key_hashes: Vec<u32>,
+    /// hashes of distinct key prefixes of each block, for prefix bloom filter
+    prefix_hashes: Vec<u32>,
+    /// key prefix last hashed in current block
+    last_prefix: Bytes,
+    /// last key added, keys must be added in strictly increasing order
+    last_key: Bytes,
     options: Options,
-    max_version: u64,
+    /// max version of entries in current block
+    block_max_version: u64,
+    block_stats: Vec<BlockStats>,
+    /// upper bound of space taken by the last entry
+    last_entry_size: usize,
+    /// metadata set by users, stored in index of every SST
+    user_metadata: HashMap<String, Bytes>,
+    /// where finished blocks are written to by a streaming builder, which
+    /// only keeps the block being built in `buf`
+    writer: Option<Box<dyn Write + Send>>,
+    /// size of blocks written to `writer`, which is the offset of `buf`
+    /// in SST
+    flushed: u64,
+    /// first error of writing blocks, returned by `finish_streaming`
+    write_error: Option<io::Error>,
 }
 
 impl Builder {
     /// Create new builder from options
+    ///
+    /// # Panics
+    ///
+    /// Panics if `options` are invalid, see `Options::validate`.
     pub fn new(options: Options) -> Builder {
+        // approximately 16MB index + table size
+        let capacity = (16 << 20) + options.table_size as usize;
+        Self::with_writer(options, capacity, None)
+    }
+
+    /// Create a streaming builder, which writes each block to `writer` once
+    /// it's finished instead of keeping the whole SST in memory. The SST
+    /// must be finished with `finish_streaming`, and can't be split.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `options` are invalid, see `Options::validate`.
+    pub fn new_streaming(options: Options, writer: Box<dyn Write + Send>) -> Builder {
+        // only the current block is kept in buffer
+        let capacity = options.block_size * 2;
+        Self::with_writer(options, capacity, Some(writer))
+    }
+
+    fn with_writer(
+        options: Options,
+        capacity: usize,
+        writer: Option<Box<dyn Write + Send>>,
+    ) -> Builder {
+        if let Err(e) = options.validate() {
+            panic!("{}", e);
+        }
         Builder {
-            // approximately 16MB index + table size
-            buf: BytesMut::with_capacity((16 << 20) + options.table_size as usize),
+            buf: BytesMut::with_capacity(capacity),
             table_index: TableIndex::default(),
             key_hashes: Vec::with_capacity(1024),
+            prefix_hashes: vec![],
+            last_prefix: Bytes::new(),
+            last_key: Bytes::new(),
             base_key: Bytes::new(),
             base_offset: 0,
             entry_offsets: vec![],
             options,
-            max_version: 0,
+            block_max_version: 0,
+            block_stats: vec![],
+            last_entry_size: 0,
+            user_metadata: HashMap::new(),
+            writer,
+            flushed: 0,
+            write_error: None,
         }
     }
 
+    /// Check if the builder writes blocks to a writer
+    pub fn is_streaming(&self) -> bool {
+        self.writer.is_some()
+    }
+
+    /// Clear the builder so that it can build another SST with the same
+    /// options, as if it were just created. Allocated buffers are kept
+    /// to be reused.
+    pub fn reset(&mut self) {
+        assert!(!self.is_streaming(), "streaming builder can't be reset");
+        self.buf.clear();
+        let mut offsets = std::mem::take(&mut self.table_index.offsets);
+        offsets.clear();
+        self.table_index = TableIndex {
+            offsets,
+            ..TableIndex::default()
+        };
+        self.key_hashes.clear();
+        self.prefix_hashes.clear();
+        self.last_prefix.clear();
+        self.last_key.clear();
+        self.base_key.clear();
+        self.base_offset = 0;
+        self.entry_offsets.clear();
+        self.block_max_version = 0;
+        self.block_stats.clear();
+        self.last_entry_size = 0;
+        self.user_metadata.clear();
+    }
+
+    /// Set metadata `key` to `value`, which is stored in index of the SST
+    /// and can be read with `Table::user_metadata`. Setting a key again
+    /// overwrites its value.
+    pub fn set_user_metadata(&mut self, key: String, value: Bytes) {
+        self.user_metadata.insert(key, value);
+    }
+
+    /// Get size of user metadata in index
+    fn user_metadata_size(&self) -> usize {
+        self.user_metadata
+            .iter()
+            // tags and lengths of map entry, key and value
+            .map(|(k, v)| k.len() + v.len() + 9)
+            .sum()
+    }
+
     /// Check if the builder is empty
     pub fn is_empty(&self) -> bool {
-        self.buf.is_empty()
+        self.buf.is_empty() && self.flushed == 0
+    }
+
+    /// Get number of entries added
+    pub fn len(&self) -> usize {
+        self.key_hashes.len()
+    }
+
+    /// Get the first key added, or an empty slice if the builder is empty
+    pub fn smallest(&self) -> &[u8] {
+        match self.table_index.offsets.first() {
+            Some(offset) => &offset.key,
+            None => &self.base_key,
+        }
+    }
+
+    /// Get the last key added, or an empty slice if the builder is empty
+    pub fn biggest(&self) -> &[u8] {
+        &self.last_key
+    }
+
+    /// Get size of blocks built so far, including the current one, which
+    /// is what a streaming builder has written or is about to write.
+    /// Unlike `estimated_size`, index, bloom filter and footer are not
+    /// counted, so it's cheap to call after every `add`.
+    pub fn bytes_written_estimate(&self) -> u64 {
+        self.flushed + self.buf.len() as u64
+    }
+
+    /// Write finished blocks in buffer to writer of a streaming builder
+    fn flush_blocks(&mut self) {
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => return,
+        };
+        if self.write_error.is_none() {
+            if let Err(e) = writer.write_all(&self.buf) {
+                self.write_error = Some(e);
+            }
+        }
+        self.flushed += self.buf.len() as u64;
+        self.buf.clear();
     }
 
     fn key_diff<'a>(&self, key: &'a [u8]) -> &'a [u8] {
         util::bytes_diff(&self.base_key, key)
     }
 
-    fn add_helper(&mut self, key: &Bytes, v: Value, vlog_len: u32) {
-        self.key_hashes
-            .push(farmhash::fingerprint64(&key[..key.len() - 8]));
-        // TODO: check ts
+    fn add_helper(&mut self, key: &Bytes, v: Value, vlog_len: u32, is_stale: bool) {
+        self.key_hashes.push(bloom::hash(&key[..key.len() - 8]));
+        if let Some(len) = self.prefix_bloom_len() {
+            // keys shorter than prefix never match a prefix scan which
+            // can use the filter
+            if key.len() - 8 >= len && self.last_prefix != key[..len] {
+                self.prefix_hashes.push(bloom::hash(&key[..len]));
+                self.last_prefix = key.slice(..len);
+            }
+        }
+        self.block_max_version = self.block_max_version.max(get_ts(key));
         let diff_key = if self.base_key.is_empty() {
             self.base_key = key.clone();
             key
@@ -82,17 +265,24 @@ impl Builder {
             overlap: (key.len() - diff_key.len()) as u16,
             diff: diff_key.len() as u16,
         };
-        assert!(self.buf.len() <= u32::MAX as usize);
-        self.entry_offsets
-            .push(self.buf.len() as u32 - self.base_offset);
+        let entry_offset = self.buf.len() as u64 - self.base_offset;
+        debug_assert!(entry_offset <= u32::MAX as u64, "block too large");
+        self.entry_offsets.push(entry_offset as u32);
 
         // Layout: header, diffKey, value.
         h.encode(&mut self.buf);
         self.buf.put_slice(diff_key);
         v.encode(&mut self.buf);
 
+        // as if the entry starts a new block, where the key is not diffed
+        self.last_entry_size =
+            HEADER_SIZE + key.len() + v.encoded_size() as usize + 4 + vlog_len as usize;
         let sst_size = v.encoded_size() as usize + diff_key.len() + 4;
-        self.table_index.estimated_size += sst_size as u32 + vlog_len;
+        self.table_index.estimated_size += sst_size as u64;
+        self.table_index.vlog_size += vlog_len as u64;
+        if is_stale {
+            self.table_index.stale_data_size += sst_size as u64 + vlog_len as u64;
+        }
     }
 
     fn finish_block(&mut self) {
@@ -104,19 +294,77 @@ impl Builder {
         }
         self.buf.put_u32(self.entry_offsets.len() as u32);
 
+        let mut compression = self.options.compression;
+        if compression != CompressionType::None {
+            let raw = &self.buf[self.base_offset as usize..];
+            let compressed = CompressedBlock::compress(raw, compression).unwrap();
+            if compressed.data().len() < raw.len() {
+                self.buf.truncate(self.base_offset as usize);
+                self.buf.put_slice(compressed.data());
+            } else {
+                // store the block raw if it doesn't shrink under compression
+                compression = CompressionType::None;
+            }
+        }
+
+        if let Some(data_key) = &self.options.data_key {
+            let iv = encryption::generate_iv();
+            // length of data key is checked by `Options::validate`
+            encryption::xor_block(
+                &mut self.buf[self.base_offset as usize..],
+                &data_key.data,
+                &iv,
+            )
+            .expect("data key has been validated");
+            self.buf.put_slice(&iv);
+        }
+
+        // checksum is calculated for data on disk, which may be compressed and encrypted
         let cs = self.build_checksum(&self.buf[self.base_offset as usize..]);
-        self.write_checksum(cs);
+        write_checksum(&mut self.buf, cs);
 
-        self.add_block_to_index();
+        self.add_block_to_index(compression);
     }
 
-    fn add_block_to_index(&mut self) {
+    fn add_block_to_index(&mut self, compression: CompressionType) {
+        let len = self.buf.len() as u64 - self.base_offset;
+        debug_assert!(len <= u32::MAX as u64, "block too large");
+        let offset = self.flushed + self.base_offset;
+        // blocks are read back at `usize` offsets
+        debug_assert!(
+            usize::try_from(offset + len).is_ok(),
+            "block at offset {} with length {} is out of range",
+            offset,
+            len
+        );
         let block = BlockOffset {
             key: self.base_key.to_vec(),
-            offset: self.base_offset,
-            len: self.buf.len() as u32 - self.base_offset,
+            offset,
+            len: len as u32,
+            compression: compression.to_u32(),
         };
         self.table_index.offsets.push(block);
+        self.block_stats.push(BlockStats {
+            key_count: self.key_hashes.len(),
+            prefix_count: self.prefix_hashes.len(),
+            estimated_size: self.table_index.estimated_size,
+            stale_data_size: self.table_index.stale_data_size,
+            vlog_size: self.table_index.vlog_size,
+            max_version: self.block_max_version,
+        });
+        self.block_max_version = 0;
+        // prefixes are deduplicated within a block, so that each SST split
+        // at block boundaries has all of its prefixes
+        self.last_prefix.clear();
+    }
+
+    /// Get length of key prefixes in prefix bloom filter, `None` if
+    /// prefix bloom filter is disabled
+    fn prefix_bloom_len(&self) -> Option<usize> {
+        if self.options.bloom_false_positive <= 0.0 {
+            return None;
+        }
+        self.options.prefix_bloom_len.map(|len| len as usize)
     }
 
     fn should_finish_block(&self, key: &[u8], value: &Value) -> bool {
@@ -127,76 +375,325 @@ impl Builder {
             4 + // size of list
             8 + // sum64 in checksum proto
             4; // checksum length
-        assert!(entries_offsets_size < u32::MAX as usize);
-        let estimated_size = (self.buf.len() as u32)
-            - self.base_offset + 6 /* header size for entry */
-            + key.len() as u32
-            + value.encoded_size() as u32
-            + entries_offsets_size as u32;
-        assert!(self.buf.len() + (estimated_size as usize) < u32::MAX as usize);
-        estimated_size > self.options.block_size as u32
+        let estimated_size = self.buf.len() - self.base_offset as usize
+            + 6 /* header size for entry */
+            + key.len()
+            + value.encoded_size() as usize
+            + entries_offsets_size;
+        assert!(estimated_size < u32::MAX as usize);
+        estimated_size > self.options.block_size as usize
     }
 
     /// Add key-value pair to table
-    pub fn add(&mut self, key: &Bytes, value: Value, vlog_len: u32) {
+    ///
+    /// Keys must be added in strictly increasing order of
+    /// `COMPARATOR.compare_key`, i.e. user key ascending and then version
+    /// descending. A key that is not greater than the last one, including
+    /// the same key with the same version, is rejected with
+    /// `Error::KeyOutOfOrder` and the builder is left unchanged.
+    pub fn add(&mut self, key: &Bytes, value: Value, vlog_len: u32) -> Result<()> {
+        self.add_internal(key, value, vlog_len, false)
+    }
+
+    /// Add key-value pair which is stale, i.e. an older version or a
+    /// tombstone that is kept in table, so that its size is counted as
+    /// stale data size of table.
+    /// Keys are checked like in `add`.
+    pub fn add_stale(&mut self, key: &Bytes, value: Value, vlog_len: u32) -> Result<()> {
+        self.add_internal(key, value, vlog_len, true)
+    }
+
+    fn add_internal(
+        &mut self,
+        key: &Bytes,
+        value: Value,
+        vlog_len: u32,
+        is_stale: bool,
+    ) -> Result<()> {
+        if !self.last_key.is_empty()
+            && COMPARATOR.compare_key(key, &self.last_key) != Ordering::Greater
+        {
+            return Err(Error::KeyOutOfOrder(format!(
+                "{:?} is added after {:?}",
+                key, self.last_key
+            )));
+        }
         if self.should_finish_block(&key, &value) {
             self.finish_block();
+            self.flush_blocks();
             self.base_key.clear();
-            assert!(self.buf.len() < u32::MAX as usize);
-            self.base_offset = self.buf.len() as u32;
+            self.base_offset = self.buf.len() as u64;
             self.entry_offsets.clear();
         }
-        self.add_helper(key, value, vlog_len);
+        self.add_helper(key, value, vlog_len, is_stale);
+        self.last_key = key.clone();
+        Ok(())
     }
 
-    /// Check if entries reach its capacity
+    /// Check if the SST would grow beyond `capacity` with another entry
+    /// like the last one, so that it should be finished before adding
+    /// more. The size is estimated by `estimated_size`, with room for one
+    /// more entry and the block it may start, so SSTs end slightly below
+    /// `capacity` rather than above.
     pub fn reach_capacity(&self, capacity: u64) -> bool {
-        let block_size = self.buf.len() as u32 + // length of buffer
-                                 self.entry_offsets.len() as u32 * 4 + // all entry offsets size
-                                 4 + // count of all entry offsets
-                                 8 + // checksum bytes
-                                 4; // checksum length
-        let estimated_size = block_size +
-                                  4 + // index length
-                                  5 * self.table_index.offsets.len() as u32; // TODO: why 5?
-        estimated_size as u64 > capacity
-    }
-
-    /// Finalize the table
+        let headroom = self.last_entry_size + // next entry
+            self.base_key.len() + 12 + // index of a new block
+            4 + // count of entry offsets of a new block
+            8 + // checksum bytes of a new block
+            4; // checksum length of a new block
+        (self.estimated_size() + headroom) as u64 >= capacity
+    }
+
+    /// Estimate size of the SST if it is finished now, including the current
+    /// block, index, bloom filter and footer, and values in value log the
+    /// entries refer to by `vlog_len` of `add`, so that SSTs of entries
+    /// with values in value log are cut by the size of data they hold.
+    pub fn estimated_size(&self) -> usize {
+        let mut size = self.flushed as usize + self.buf.len() + self.table_index.vlog_size as usize;
+        let mut index_size = 0;
+        for offset in &self.table_index.offsets {
+            // key with its length, and tags and varints of the other fields
+            index_size += offset.key.len() + 12;
+        }
+        if !self.entry_offsets.is_empty() {
+            size += self.entry_offsets.len() * 4 + // all entry offsets size
+                4 + // count of all entry offsets
+                8 + // checksum bytes
+                4; // checksum length
+            index_size += self.base_key.len() + 12;
+        }
+        if self.options.bloom_false_positive > 0.0 && !self.key_hashes.is_empty() {
+            let bits_per_key =
+                Bloom::bloom_bits_per_key(self.key_hashes.len(), self.options.bloom_false_positive);
+            let nbits = (self.key_hashes.len() * bits_per_key).max(64);
+            index_size += (nbits + 7) / 8 + 1;
+        }
+        if self.prefix_bloom_len().is_some() && !self.prefix_hashes.is_empty() {
+            let bits_per_key = Bloom::bloom_bits_per_key(
+                self.prefix_hashes.len(),
+                self.options.bloom_false_positive,
+            );
+            let nbits = (self.prefix_hashes.len() * bits_per_key).max(64);
+            index_size += (nbits + 7) / 8 + 1 + 8;
+        }
+        index_size += self.user_metadata_size();
+        size + index_size + // index
+            32 + // other fields of index, like key count and max version
+            4 + // index length
+            12 + // checksum bytes
+            4 + // checksum length
+            TRAILER_SIZE
+    }
+
+    /// Finalize the table. An empty builder makes a well-formed SST
+    /// without any block.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the builder is streaming, see `finish_streaming`.
     pub fn finish(&mut self) -> Bytes {
+        assert!(!self.is_streaming(), "use finish_streaming instead");
+        self.finish_block();
+        self.encode_table(0..self.table_index.offsets.len())
+    }
+
+    /// Finalize the SST of a streaming builder. The rest of blocks, index,
+    /// checksum and footer are written after the blocks written so far,
+    /// and the writer is flushed. Returns size of the SST.
+    pub fn finish_streaming(mut self) -> Result<u64> {
+        if !self.is_streaming() {
+            return Err(Error::TableRead(
+                "builder is not streaming, use finish instead".to_string(),
+            ));
+        }
+        self.finish_block();
+        self.flush_blocks();
+        let index = self.encode_index(0..self.table_index.offsets.len());
+        self.buf.put_slice(&index);
+        self.flush_blocks();
+        if let Some(e) = self.write_error.take() {
+            return Err(e.into());
+        }
+        self.writer.as_mut().unwrap().flush()?;
+        Ok(self.flushed)
+    }
+
+    /// Finalize the table, and split it at block boundaries into SSTs no
+    /// larger than `table_size`, each of which can be opened on its own.
+    /// Returns one SST if `table_size` is 0. A block which can't fit into
+    /// `table_size` with its index makes an SST of its own, which is
+    /// larger than `table_size`. Returns no SST if the builder is empty.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the builder is streaming.
+    pub fn finish_split(&mut self) -> Vec<Bytes> {
+        assert!(!self.is_streaming(), "streaming builder can't be split");
         self.finish_block();
         if self.buf.is_empty() {
-            return Bytes::new();
+            return vec![];
+        }
+        let n = self.table_index.offsets.len();
+        if self.options.table_size == 0 {
+            return vec![self.encode_table(0..n)];
+        }
+        let mut tables = vec![];
+        let mut start = 0;
+        for end in 1..n {
+            if self.max_table_size(start..end + 1) > self.options.table_size {
+                tables.push(self.encode_table(start..end));
+                start = end;
+            }
+        }
+        tables.push(self.encode_table(start..n));
+        tables
+    }
+
+    /// Get an upper bound of size of SST made of `blocks`
+    fn max_table_size(&self, blocks: Range<usize>) -> u64 {
+        let offsets = &self.table_index.offsets[blocks.clone()];
+        let first = &offsets[0];
+        let last = &offsets[offsets.len() - 1];
+        let data_size = last.offset + last.len as u64 - first.offset;
+        // key with its length, and tags and varints of the other fields
+        let offsets_size: usize = offsets.iter().map(|ko| ko.key.len() + 40).sum();
+        let prev = blocks.start.checked_sub(1).map(|i| &self.block_stats[i]);
+        let last = &self.block_stats[blocks.end - 1];
+        let key_count = last.key_count - prev.map_or(0, |s| s.key_count);
+        let prefix_count = last.prefix_count - prev.map_or(0, |s| s.prefix_count);
+        let bloom_size = |n: usize| {
+            let bits_per_key = Bloom::bloom_bits_per_key(n, self.options.bloom_false_positive);
+            (n * bits_per_key).max(64) / 8 + 8
+        };
+        let mut filter_size = 0;
+        if self.options.bloom_false_positive > 0.0 {
+            filter_size += bloom_size(key_count);
+        }
+        if self.prefix_bloom_len().is_some() {
+            // with prefix length
+            filter_size += bloom_size(prefix_count) + 8;
+        }
+        let index_size = offsets_size + filter_size + self.user_metadata_size() +
+            64 + // other fields of index
+            64; // key id and iv if index is encrypted
+        data_size + index_size as u64 +
+            4 + // index length
+            32 + // checksum with its length
+            TRAILER_SIZE as u64
+    }
+
+    /// Encode an SST made of finished `blocks`
+    fn encode_table(&self, blocks: Range<usize>) -> Bytes {
+        let offsets = &self.table_index.offsets[blocks.clone()];
+        let base = offsets.first().map_or(0, |ko| ko.offset);
+        let end = offsets.last().map_or(base, |ko| ko.offset + ko.len as u64);
+        let data = &self.buf[base as usize..end as usize];
+
+        let index = self.encode_index(blocks);
+        let mut buf = BytesMut::with_capacity(data.len() + index.len());
+        buf.put_slice(data);
+        buf.put_slice(&index);
+        buf.freeze()
+    }
+
+    /// Encode index, checksum and footer of SST made of finished `blocks`,
+    /// where offsets of blocks are relative to the first block
+    fn encode_index(&self, blocks: Range<usize>) -> BytesMut {
+        let offsets = &self.table_index.offsets[blocks.clone()];
+        let base = offsets.first().map_or(0, |ko| ko.offset);
+        let stats = &self.block_stats[blocks.clone()];
+        let empty = BlockStats::default();
+        let prev = match blocks.start.checked_sub(1) {
+            Some(i) => &self.block_stats[i],
+            None => &empty,
+        };
+        let last_stats = stats.last().unwrap_or(prev);
+        let key_hashes = &self.key_hashes[prev.key_count..last_stats.key_count];
+        let prefix_hashes = &self.prefix_hashes[prev.prefix_count..last_stats.prefix_count];
+        let mut table_index = TableIndex {
+            offsets: offsets
+                .iter()
+                .map(|ko| BlockOffset {
+                    offset: ko.offset - base,
+                    ..ko.clone()
+                })
+                .collect(),
+            estimated_size: last_stats.estimated_size - prev.estimated_size,
+            stale_data_size: last_stats.stale_data_size - prev.stale_data_size,
+            vlog_size: last_stats.vlog_size - prev.vlog_size,
+            max_version: stats.iter().map(|s| s.max_version).max().unwrap_or(0),
+            key_count: key_hashes.len() as u32,
+            user_metadata: self
+                .user_metadata
+                .iter()
+                .map(|(k, v)| (k.clone(), v.to_vec()))
+                .collect(),
+            ..Default::default()
+        };
+        if self.options.bloom_false_positive > 0.0 && !key_hashes.is_empty() {
+            let bits_per_key =
+                Bloom::bloom_bits_per_key(key_hashes.len(), self.options.bloom_false_positive);
+            let bloom = Bloom::build_from_key_hashes(key_hashes, bits_per_key);
+            table_index.bloom_filter = bloom.to_vec();
+        }
+        if let Some(len) = self.prefix_bloom_len() {
+            if !prefix_hashes.is_empty() {
+                let bits_per_key = Bloom::bloom_bits_per_key(
+                    prefix_hashes.len(),
+                    self.options.bloom_false_positive,
+                );
+                let bloom = Bloom::build_from_key_hashes(prefix_hashes, bits_per_key);
+                table_index.prefix_bloom_filter = bloom.to_vec();
+                table_index.prefix_len = len as u32;
+            }
         }
         let mut bytes = BytesMut::new();
-        // TODO: move boundaries and build index if we need to encrypt or compress
-        // append index to buffer
-        self.table_index.encode(&mut bytes).unwrap();
+        table_index.encode(&mut bytes).unwrap();
+        if let Some(data_key) = &self.options.data_key {
+            let iv = encryption::generate_iv();
+            encryption::xor_block(&mut bytes, &data_key.data, &iv)
+                .expect("data key has been validated");
+            let index = TableIndex {
+                key_id: data_key.key_id,
+                iv: iv.to_vec(),
+                encrypted_index: bytes.to_vec(),
+                ..Default::default()
+            };
+            bytes.clear();
+            index.encode(&mut bytes).unwrap();
+        }
         assert!(bytes.len() < u32::MAX as usize);
-        self.buf.put_slice(&bytes);
-        self.buf.put_u32(bytes.len() as u32);
+
+        let mut buf = BytesMut::with_capacity(bytes.len() + 64);
+        // append index
+        buf.put_slice(&bytes);
+        buf.put_u32(bytes.len() as u32);
         // append checksum
         let cs = self.build_checksum(&bytes);
-        self.write_checksum(cs);
-        // TODO: eliminate clone if we do not need builder any more after finish
-        self.buf.clone().freeze()
+        write_checksum(&mut buf, cs);
+        // append trailer, reserved bytes must be zero in this version
+        buf.put_u16(0);
+        buf.put_u16(FORMAT_VERSION);
+        buf.put_u32(MAGIC);
+        buf
     }
 
     fn build_checksum(&self, data: &[u8]) -> Checksum {
+        let algo = self.options.checksum_algorithm;
         Checksum {
-            sum: checksum::calculate_checksum(data, ChecksumAlg::Crc32c),
-            algo: ChecksumAlg::Crc32c as i32,
+            sum: checksum::calculate_checksum(data, algo),
+            algo: algo as i32,
         }
     }
+}
 
-    fn write_checksum(&mut self, checksum: Checksum) {
-        let mut res = BytesMut::new();
-        checksum.encode(&mut res).unwrap();
-        let len = res.len();
-        assert!(len < u32::MAX as usize);
-        self.buf.put_slice(&res);
-        self.buf.put_u32(len as u32);
-    }
+fn write_checksum(buf: &mut BytesMut, checksum: Checksum) {
+    let mut res = BytesMut::new();
+    checksum.encode(&mut res).unwrap();
+    let len = res.len();
+    assert!(len < u32::MAX as usize);
+    buf.put_slice(&res);
+    buf.put_u32(len as u32);
 }
 #[cfg(test)]
 mod tests {
@@ -207,6 +704,91 @@ mod tests {
 
     const TEST_KEYS_COUNT: usize = 100000;
 
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn test_read_large_block_offsets() {
+        use crate::opt::{ChecksumVerificationMode, ReadOptions};
+        use std::io::{Seek, SeekFrom};
+
+        // blocks are written after a 5GB hole of a sparse file, so that
+        // they are read at offsets above 4GB
+        let hole = 5 << 30;
+        let tmp_dir = TempDir::new("agatedb").unwrap();
+        let path = tmp_dir.path().join("1.sst");
+        let mut file = std::fs::File::create(&path).unwrap();
+        file.set_len(hole).unwrap();
+        file.seek(SeekFrom::Start(hole)).unwrap();
+
+        let opts = Options {
+            block_size: 4 * 1024,
+            checksum_mode: ChecksumVerificationMode::OnTableAndBlockRead,
+            ..Options::default()
+        };
+        let mut builder = Builder::new_streaming(opts.clone(), Box::new(file));
+        builder.flushed = hole;
+        let n = 10000;
+        for i in 0..n {
+            let k = key_with_ts(format!("{:016x}", i).as_str(), 0);
+            builder
+                .add(&k, Value::new(Bytes::from(i.to_string())), 0)
+                .unwrap();
+        }
+        builder.finish_streaming().unwrap();
+
+        for &use_mmap in &[false, true] {
+            let opts = Options {
+                use_mmap,
+                ..opts.clone()
+            };
+            let table = Table::open(&path, opts).unwrap();
+            let index = table.fetch_index().unwrap();
+            assert!(index.offsets.len() > 1);
+            assert!(index.offsets.iter().all(|o| o.offset >= hole));
+
+            let mut it = table.new_iterator(ReadOptions::default());
+            it.seek_to_first();
+            let mut count = 0;
+            while it.valid() {
+                assert_eq!(it.value().value, count.to_string());
+                count += 1;
+                it.next();
+            }
+            assert_eq!(count, n);
+            let last = format!("{:016x}", n - 1);
+            let value = table.get(last.as_bytes(), 0).unwrap().unwrap();
+            assert_eq!(value.value, (n - 1).to_string());
+        }
+    }
+
+    #[test]
+    fn test_large_block_offsets() {
+        let opts = Options {
+            block_size: 4 * 1024,
+            ..Options::default()
+        };
+        let mut builder = Builder::new(opts);
+        for i in 0..10000 {
+            let k = key_with_ts(format!("{:016x}", i).as_str(), 0);
+            builder
+                .add(&k, Value::new(Bytes::from(i.to_string())), 0)
+                .unwrap();
+        }
+        builder.finish_block();
+
+        // fake blocks of a table larger than 4GB
+        let mut index = builder.table_index.clone();
+        for offset in &mut index.offsets {
+            offset.offset += 5 << 30;
+        }
+        index.estimated_size += 5 << 30;
+        let mut buf = BytesMut::new();
+        index.encode(&mut buf).unwrap();
+        let decoded = TableIndex::decode(buf.freeze()).unwrap();
+        assert_eq!(decoded, index);
+        assert!(decoded.offsets.len() > 1);
+        assert!(decoded.offsets.iter().all(|o| o.offset > u32::MAX as u64));
+    }
+
     #[test]
     fn test_table_index() {
         // TODO: use cache
@@ -214,6 +796,7 @@ mod tests {
             block_size: 4 * 1024,
             bloom_false_positive: 0.01,
             table_size: 30 << 20,
+            ..Options::default()
         };
 
         let mut builder = Builder::new(opts.clone());
@@ -231,7 +814,7 @@ mod tests {
             } else if builder.should_finish_block(&k, &vs) {
                 block_first_keys.push(k.clone());
             }
-            builder.add(&k, vs, 0);
+            builder.add(&k, vs, 0).unwrap();
         }
 
         let table = Table::create(&filename, builder.finish(), opts).unwrap();
@@ -246,26 +829,165 @@ mod tests {
             assert_eq!(block_first_keys[i], idx.offsets[i].key);
         }
 
-        // TODO: support max_version
-        // assert_eq!(TEST_KEYS_COUNT, table.max_version());
+        assert_eq!(TEST_KEYS_COUNT as u64, table.max_version());
     }
 
     #[test]
     fn test_bloom_filter() {
-        // TODO: finish this test after finishing iterator and table API
+        let opts = Options {
+            block_size: 4 * 1024,
+            bloom_false_positive: 0.01,
+            table_size: 30 << 20,
+            ..Options::default()
+        };
+
+        let mut builder = Builder::new(opts.clone());
+        let tmp_dir = TempDir::new("agatedb").unwrap();
+        let filename = tmp_dir.path().join("1.sst".to_string());
+
+        let n = 10000;
+        for i in 0..n {
+            let k = key_with_ts(format!("{:016x}", i).as_str(), (i + 1) as u64);
+            let vs = Value::new(Bytes::from(i.to_string()));
+            builder.add(&k, vs, 0).unwrap();
+        }
+
+        let table = Table::create(&filename, builder.finish(), opts.clone()).unwrap();
+        assert!(table.inner.bloom_filter_size() > 0);
+
+        // no false negatives
+        for i in 0..n {
+            let k = format!("{:016x}", i);
+            assert!(!table
+                .inner
+                .does_not_have(bloom::hash(k.as_bytes()))
+                .unwrap());
+        }
+
+        let mut false_positives = 0;
+        for i in n..2 * n {
+            let k = format!("{:016x}", i);
+            if !table
+                .inner
+                .does_not_have(bloom::hash(k.as_bytes()))
+                .unwrap()
+            {
+                false_positives += 1;
+            }
+        }
+        let rate = false_positives as f64 / n as f64;
+        assert!(
+            rate < opts.bloom_false_positive * 2.0,
+            "false positive rate {} exceeds {}",
+            rate,
+            opts.bloom_false_positive
+        );
+    }
+
+    #[test]
+    fn test_estimated_size() {
+        for bloom_false_positive in &[0.0, 0.01] {
+            let opts = Options {
+                block_size: 4 * 1024,
+                bloom_false_positive: *bloom_false_positive,
+                table_size: 0,
+                ..Options::default()
+            };
+            let mut builder = Builder::new(opts);
+            for i in 0..10000 {
+                let k = key_with_ts(format!("{:016x}", i).as_str(), i as u64);
+                builder
+                    .add(&k, Value::new(Bytes::from(i.to_string())), 0)
+                    .unwrap();
+            }
+            let estimated = builder.estimated_size() as f64;
+            let actual = builder.finish().len() as f64;
+            assert!(
+                (estimated - actual).abs() / actual < 0.05,
+                "estimated {}, actual {}",
+                estimated,
+                actual
+            );
+        }
+    }
+
+    #[test]
+    fn test_reach_capacity() {
+        for bloom_false_positive in &[0.0, 0.01] {
+            let opts = Options {
+                block_size: 4 * 1024,
+                bloom_false_positive: *bloom_false_positive,
+                table_size: 0,
+                ..Options::default()
+            };
+            for &capacity in &[16 << 10, 64 << 10, 1 << 20] {
+                let mut builder = Builder::new(opts.clone());
+                let mut i = 0;
+                while !builder.reach_capacity(capacity) {
+                    let k = key_with_ts(format!("{:016x}", i).as_str(), i as u64);
+                    let v = Bytes::from(vec![b'v'; 100]);
+                    builder.add(&k, Value::new(v), 0).unwrap();
+                    i += 1;
+                }
+                let size = builder.finish().len() as u64;
+                assert!(
+                    size <= capacity && size * 10 >= capacity * 9,
+                    "size {}, capacity {}",
+                    size,
+                    capacity
+                );
+            }
+        }
     }
 
     #[test]
     fn test_empty_builder() {
         let opt = Options {
             bloom_false_positive: 0.1,
-            block_size: 0,
+            block_size: 4 * 1024,
             table_size: 0,
+            ..Options::default()
         };
 
-        let mut b = Builder::new(opt);
+        let mut b = Builder::new(opt.clone());
+        assert!(b.is_empty());
 
-        b.finish();
+        let table = Table::open_in_memory(b.finish(), 1, opt).unwrap();
+        assert_eq!(table.offsets_length(), 0);
+    }
+
+    #[test]
+    fn test_builder_reset() {
+        let opt = Options {
+            block_size: 4 * 1024,
+            table_size: 0,
+            prefix_bloom_len: Some(4),
+            ..Options::default()
+        };
+        let build = |b: &mut Builder, prefix: &str, n: usize| {
+            for i in 0..n {
+                let k = key_with_ts(format!("{}{:06}", prefix, i).as_bytes(), i as u64);
+                b.add(&k, Value::new(Bytes::from(i.to_string())), 0)
+                    .unwrap();
+            }
+            b.finish()
+        };
+
+        let mut b = Builder::new(opt.clone());
+        build(&mut b, "key", 5000);
+        let capacity = b.buf.capacity();
+        b.reset();
+        assert!(b.is_empty());
+        assert_eq!(b.buf.capacity(), capacity);
+
+        // a reset builder makes the same SST as a new one
+        for (prefix, n) in &[("abc", 3000), ("xyz", 10), ("", 0)] {
+            let data = build(&mut b, prefix, *n);
+            assert_eq!(data, build(&mut Builder::new(opt.clone()), prefix, *n));
+            let table = Table::open_in_memory(data, 1, opt.clone()).unwrap();
+            assert_eq!(table.key_count(), *n as u32);
+            b.reset();
+        }
     }
 
     #[test]
@@ -281,4 +1003,67 @@ mod tests {
         assert_eq!(header.overlap, 23333);
         assert_eq!(header.diff, 23334);
     }
+
+    #[test]
+    fn test_builder_progress() {
+        let opts = Options {
+            block_size: 1024,
+            ..Options::default()
+        };
+        let mut builder = Builder::new(opts.clone());
+        assert!(builder.is_empty());
+        assert_eq!(builder.len(), 0);
+        assert!(builder.smallest().is_empty());
+        assert!(builder.biggest().is_empty());
+        assert_eq!(builder.bytes_written_estimate(), 0);
+
+        let first = key_with_ts("key00000", 1);
+        let mut written = 0;
+        for i in 0..1000 {
+            let k = key_with_ts(format!("key{:05}", i).as_str(), 1);
+            builder
+                .add(&k, Value::new(Bytes::from(i.to_string())), 0)
+                .unwrap();
+            assert!(!builder.is_empty());
+            assert_eq!(builder.len(), i + 1);
+            assert_eq!(builder.smallest(), &first[..]);
+            assert_eq!(builder.biggest(), &k[..]);
+            assert!(builder.bytes_written_estimate() > written);
+            written = builder.bytes_written_estimate();
+        }
+        assert!(builder.table_index.offsets.len() > 1);
+        assert!(written < builder.estimated_size() as u64);
+
+        let biggest = Bytes::copy_from_slice(builder.biggest());
+        let table = Table::open_in_memory(builder.finish(), 1, opts).unwrap();
+        assert_eq!(table.key_count(), 1000);
+        assert_eq!(table.smallest(), &first);
+        assert_eq!(table.biggest(), &biggest);
+    }
+
+    #[test]
+    fn test_add_out_of_order() {
+        let mut builder = Builder::new(Options::default());
+        let v = || Value::new(Bytes::from_static(b"v"));
+        builder.add(&key_with_ts("b", 5), v(), 0).unwrap();
+        builder.add(&key_with_ts("b", 3), v(), 0).unwrap();
+        // same key and version
+        assert!(matches!(
+            builder.add(&key_with_ts("b", 3), v(), 0),
+            Err(Error::KeyOutOfOrder(_))
+        ));
+        // newer version of the same key
+        assert!(builder.add_stale(&key_with_ts("b", 4), v(), 0).is_err());
+        // smaller key
+        assert!(builder.add(&key_with_ts("a", 9), v(), 0).is_err());
+        builder.add(&key_with_ts("c", 1), v(), 0).unwrap();
+
+        let table = Table::open_in_memory(builder.finish(), 1, Options::default()).unwrap();
+        assert_eq!(table.key_count(), 3);
+
+        builder = Builder::new(Options::default());
+        builder.add(&key_with_ts("c", 1), v(), 0).unwrap();
+        builder.reset();
+        builder.add(&key_with_ts("a", 1), v(), 0).unwrap();
+    }
 }
@@ -1,10 +1,17 @@
 use super::builder::{Header, HEADER_SIZE};
-use super::{Block, TableInner};
+use super::{Block, Table, TableInner};
+use crate::format::{key_with_ts, user_key};
+use crate::iterator_trait::AgateIterator;
+use crate::opt::ReadOptions;
 use crate::util::{self, KeyComparator, COMPARATOR};
 use crate::value::Value;
-use crate::Error;
+use crate::{Error, Result};
 use bytes::{Bytes, BytesMut};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, VecDeque};
+use std::sync::mpsc::{self, Receiver, Sender};
 use std::sync::Arc;
+use std::thread;
 
 /// Errors that may encounter during iterator operation
 #[derive(Clone, Debug)]
@@ -42,7 +49,7 @@ enum SeekPos {
 
 /// Block iterator iterates on an SST block
 // TODO: support custom comparator
-struct BlockIterator {
+pub struct BlockIterator {
     /// current index of iterator
     idx: usize,
     /// base key of the block
@@ -150,8 +157,27 @@ impl BlockIterator {
         self.err.as_ref()
     }
 
-    /// Seek to the first entry that is equal or greater than key
-    pub fn seek(&mut self, key: &Bytes, whence: SeekPos) {
+    /// Key of current entry
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// Value of current entry
+    pub fn value(&self) -> Value {
+        let mut value = Value::default();
+        value.decode(&self.val);
+        value
+    }
+
+    /// Seek to the first entry that is equal or greater than key. Entries
+    /// are binary searched by `entry_offsets`, and versions of the same
+    /// user key are ordered from the newest, so it lands on the newest
+    /// version not newer than the version of `key`.
+    pub fn seek(&mut self, key: &[u8]) {
+        self.seek_from(key, SeekPos::Origin);
+    }
+
+    fn seek_from(&mut self, key: &[u8], whence: SeekPos) {
         self.err = None;
         let start_index = match whence {
             SeekPos::Origin => 0,
@@ -212,6 +238,110 @@ impl BlockIterator {
 // TODO: use `bitfield` if there are too many variants
 pub const ITERATOR_REVERSED: usize = 1 << 1;
 pub const ITERATOR_NOCACHE: usize = 1 << 2;
+/// Read the block after current one on a helper thread. Only takes effect
+/// for iterators created with `Iterator::new_shared`.
+pub const ITERATOR_PREFETCH: usize = 1 << 3;
+
+/// Convert `ReadOptions` to the flags of `Iterator`. Key bounds are not
+/// flags and have to be set separately.
+pub(crate) fn iterator_flags(opts: &ReadOptions) -> usize {
+    let mut opt = 0;
+    if opts.reversed {
+        opt |= ITERATOR_REVERSED;
+    }
+    if opts.no_cache {
+        opt |= ITERATOR_NOCACHE;
+    }
+    if opts.prefetch_size > 0 {
+        opt |= ITERATOR_PREFETCH;
+    }
+    opt
+}
+
+/// Maximum number of blocks requested from the prefetch thread and not
+/// yet taken by the iterator
+const MAX_PENDING_PREFETCH: usize = 2;
+
+/// Requests of block indexes to, and blocks read from, the prefetch thread
+type PrefetchChannel = (Sender<usize>, Receiver<(usize, Result<Arc<Block>>)>);
+
+/// Prefetcher reads blocks of a table on a helper thread, so the next
+/// block is likely ready when the iterator moves to it. The thread is
+/// started on first prefetch, and exits when the prefetcher is dropped.
+struct Prefetcher {
+    table: Arc<TableInner>,
+    use_cache: bool,
+    channel: Option<PrefetchChannel>,
+    /// blocks requested from the helper thread, in order of request
+    pending: VecDeque<usize>,
+}
+
+impl Prefetcher {
+    fn new(table: Arc<TableInner>, use_cache: bool) -> Self {
+        Self {
+            table,
+            use_cache,
+            channel: None,
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn start(&self) -> Option<PrefetchChannel> {
+        let (req_tx, req_rx) = mpsc::channel::<usize>();
+        let (res_tx, res_rx) = mpsc::channel();
+        let table = self.table.clone();
+        let use_cache = self.use_cache;
+        thread::Builder::new()
+            .name("table-prefetch".to_string())
+            .spawn(move || {
+                for idx in req_rx {
+                    if res_tx.send((idx, table.block(idx, use_cache))).is_err() {
+                        break;
+                    }
+                }
+            })
+            .ok()?;
+        Some((req_tx, res_rx))
+    }
+
+    /// Start reading block `idx` in background. Reads which are finished
+    /// but not taken are dropped, as the iterator has moved elsewhere.
+    fn prefetch(&mut self, idx: usize) {
+        if self.pending.contains(&idx) {
+            return;
+        }
+        if self.channel.is_none() {
+            self.channel = self.start();
+        }
+        let (req_tx, res_rx) = match &self.channel {
+            Some(channel) => channel,
+            None => return,
+        };
+        while !self.pending.is_empty() && res_rx.try_recv().is_ok() {
+            self.pending.pop_front();
+        }
+        if self.pending.len() < MAX_PENDING_PREFETCH && req_tx.send(idx).is_ok() {
+            self.pending.push_back(idx);
+        }
+    }
+
+    /// Take block `idx`, waiting for the read to finish. Returns `None`
+    /// if the block hasn't been requested.
+    fn take(&mut self, idx: usize) -> Option<Result<Arc<Block>>> {
+        if !self.pending.contains(&idx) {
+            return None;
+        }
+        let (_, res_rx) = self.channel.as_ref()?;
+        // the helper thread reads blocks in order of request
+        while self.pending.pop_front().is_some() {
+            let (read_idx, block) = res_rx.recv().ok()?;
+            if read_idx == idx {
+                return Some(block);
+            }
+        }
+        None
+    }
+}
 
 /// An iterator over SST.
 ///
@@ -228,6 +358,23 @@ pub struct Iterator<T: AsRef<TableInner>> {
     block_iterator: Option<BlockIterator>,
     err: Option<IteratorError>,
     opt: usize,
+    /// smallest user key to iterate over, inclusive
+    start_key: Option<Bytes>,
+    /// biggest user key to iterate over, exclusive
+    end_key: Option<Bytes>,
+    prefetcher: Option<Prefetcher>,
+}
+
+impl Iterator<Arc<TableInner>> {
+    /// Create an iterator from `Arc<TableInner>`, which reads ahead the
+    /// next block if `ITERATOR_PREFETCH` is set.
+    pub fn new_shared(table: Arc<TableInner>, opt: usize) -> Self {
+        let mut it = Self::new(table.clone(), opt);
+        if opt & ITERATOR_PREFETCH != 0 {
+            it.prefetcher = Some(Prefetcher::new(table, it.use_cache()));
+        }
+        it
+    }
 }
 
 impl<T: AsRef<TableInner>> Iterator<T> {
@@ -239,9 +386,38 @@ impl<T: AsRef<TableInner>> Iterator<T> {
             block_iterator: None,
             err: None,
             opt,
+            start_key: None,
+            end_key: None,
+            prefetcher: None,
         }
     }
 
+    /// Limit the iterator to user keys in `[start_key, end_key)`. The
+    /// iterator becomes invalid once it moves out of the range.
+    pub(crate) fn set_bounds(&mut self, start_key: Option<Bytes>, end_key: Option<Bytes>) {
+        self.start_key = start_key;
+        self.end_key = end_key;
+    }
+
+    fn in_bounds(&self) -> bool {
+        // an iterator not positioned yet has no key to check
+        if (self.start_key.is_none() && self.end_key.is_none()) || self.block_iterator.is_none() {
+            return true;
+        }
+        let key = user_key(self.key());
+        if let Some(start_key) = &self.start_key {
+            if key < &start_key[..] {
+                return false;
+            }
+        }
+        if let Some(end_key) = &self.end_key {
+            if key >= &end_key[..] {
+                return false;
+            }
+        }
+        true
+    }
+
     /// Reset iterator
     ///
     /// This function will only be used in tests outside this mod
@@ -250,16 +426,39 @@ impl<T: AsRef<TableInner>> Iterator<T> {
         self.err = None;
     }
 
-    /// Check if last operation of iterator is error
+    /// Check if last operation of iterator is error, or the iterator has
+    /// moved out of its bounds
     /// TODO: use `Result<()>` for all iterator operation and remove this if possible
     pub fn valid(&self) -> bool {
-        self.err.is_none()
+        self.err.is_none() && self.in_bounds()
     }
 
     pub fn use_cache(&self) -> bool {
         self.opt & ITERATOR_NOCACHE == 0
     }
 
+    /// Get block `idx`, and start reading the block which the iterator
+    /// moves to next if prefetch is enabled.
+    fn load_block(&mut self, idx: usize) -> Result<Arc<Block>> {
+        let table = self.table.as_ref();
+        let prefetched = self.prefetcher.as_mut().and_then(|p| p.take(idx));
+        let block = match prefetched {
+            Some(block) => block?,
+            None => table.block(idx, self.use_cache())?,
+        };
+        if let Some(prefetcher) = &mut self.prefetcher {
+            let next = if self.opt & ITERATOR_REVERSED == 0 {
+                Some(idx + 1).filter(|next| *next < table.offsets_length())
+            } else {
+                idx.checked_sub(1)
+            };
+            if let Some(next) = next {
+                prefetcher.prefetch(next);
+            }
+        }
+        Ok(block)
+    }
+
     fn get_block_iterator(&mut self, block: Arc<Block>) -> &mut BlockIterator {
         if let Some(ref mut iter) = self.block_iterator {
             iter.set_block(block);
@@ -276,7 +475,7 @@ impl<T: AsRef<TableInner>> Iterator<T> {
             return;
         }
         self.bpos = 0;
-        match self.table.as_ref().block(self.bpos, self.use_cache()) {
+        match self.load_block(self.bpos) {
             Ok(block) => {
                 let block_iterator = self.get_block_iterator(block);
                 block_iterator.seek_to_first();
@@ -293,7 +492,7 @@ impl<T: AsRef<TableInner>> Iterator<T> {
             return;
         }
         self.bpos = num_blocks - 1;
-        match self.table.as_ref().block(self.bpos, self.use_cache()) {
+        match self.load_block(self.bpos) {
             Ok(block) => {
                 let block_iterator = self.get_block_iterator(block);
                 block_iterator.seek_to_last();
@@ -305,10 +504,10 @@ impl<T: AsRef<TableInner>> Iterator<T> {
 
     fn seek_helper(&mut self, block_idx: usize, key: &Bytes) {
         self.bpos = block_idx;
-        match self.table.as_ref().block(self.bpos, self.use_cache()) {
+        match self.load_block(self.bpos) {
             Ok(block) => {
                 let block_iterator = self.get_block_iterator(block);
-                block_iterator.seek(key, SeekPos::Origin);
+                block_iterator.seek(key);
                 self.err = block_iterator.err.clone();
             }
             Err(err) => self.err = Some(err.into()),
@@ -321,9 +520,20 @@ impl<T: AsRef<TableInner>> Iterator<T> {
             self.reset();
         }
 
-        let idx = util::search(self.table.as_ref().offsets_length(), |idx| {
+        let index = match self.table.as_ref().fetch_index() {
+            Ok(index) => index,
+            Err(err) => {
+                self.err = Some(err.into());
+                return;
+            }
+        };
+        if index.offsets.is_empty() {
+            self.err = Some(IteratorError::EOF);
+            return;
+        }
+        let idx = util::search(index.offsets.len(), |idx| {
             use std::cmp::Ordering::*;
-            let block_offset = self.table.as_ref().offsets(idx).unwrap();
+            let block_offset = &index.offsets[idx];
             match COMPARATOR.compare_key(&block_offset.key, &key) {
                 Greater => true,
                 _ => false,
@@ -354,6 +564,9 @@ impl<T: AsRef<TableInner>> Iterator<T> {
     /// This function will only be called in tests outside this mod
     pub(crate) fn seek_for_prev(&mut self, key: &Bytes) {
         self.seek_from(key, SeekPos::Origin);
+        if self.table.as_ref().offsets_length() == 0 {
+            return;
+        }
         if self.key() != key {
             self.prev_inner();
         }
@@ -371,7 +584,7 @@ impl<T: AsRef<TableInner>> Iterator<T> {
         }
 
         if BlockIterator::is_ready(&self.block_iterator) {
-            match self.table.as_ref().block(self.bpos, self.use_cache()) {
+            match self.load_block(self.bpos) {
                 Ok(block) => {
                     let block_iterator = self.get_block_iterator(block);
                     block_iterator.seek_to_first();
@@ -402,7 +615,7 @@ impl<T: AsRef<TableInner>> Iterator<T> {
         }
 
         if BlockIterator::is_ready(&self.block_iterator) {
-            match self.table.as_ref().block(self.bpos, self.use_cache()) {
+            match self.load_block(self.bpos) {
                 Ok(block) => {
                     let block_iterator = self.get_block_iterator(block);
                     block_iterator.seek_to_last();
@@ -432,6 +645,19 @@ impl<T: AsRef<TableInner>> Iterator<T> {
         value
     }
 
+    /// Get key and value of current element without moving the iterator,
+    /// or `None` if the iterator is not valid. Unlike `peek` of
+    /// `std::iter::Peekable`, it doesn't look ahead, as the iterator is
+    /// already positioned at the element. The value is decoded on demand
+    /// like `value`, which shares data with the block without copying.
+    pub fn peek(&self) -> Option<(&[u8], Value)> {
+        // the iterator has not been positioned yet
+        if self.block_iterator.is_none() || !self.valid() {
+            return None;
+        }
+        Some((self.key(), self.value()))
+    }
+
     /// `next` points the iterator to next element.
     /// Note that if the iterator becomes invalid after operation,
     /// you must reset the iterator by using `rewind` or `seek`
@@ -444,12 +670,25 @@ impl<T: AsRef<TableInner>> Iterator<T> {
         }
     }
 
-    /// Reset the iterator to first element
+    /// Reset the iterator to first element, which is the first one in
+    /// bounds if bounds are set
     pub fn rewind(&mut self) {
         if self.opt & ITERATOR_REVERSED == 0 {
-            self.seek_to_first();
+            match self.start_key.clone() {
+                Some(start_key) => self.seek_inner(&key_with_ts(&start_key[..], u64::MAX)),
+                None => self.seek_to_first(),
+            }
         } else {
-            self.seek_to_last();
+            match self.end_key.clone() {
+                Some(end_key) => {
+                    self.seek_for_prev(&key_with_ts(&end_key[..], u64::MAX));
+                    // end key is exclusive
+                    if self.err.is_none() && user_key(self.key()) >= &end_key[..] {
+                        self.prev_inner();
+                    }
+                }
+                None => self.seek_to_last(),
+            }
         }
     }
 
@@ -465,6 +704,398 @@ impl<T: AsRef<TableInner>> Iterator<T> {
     pub fn error(&self) -> Option<&IteratorError> {
         self.err.as_ref()
     }
+
+    /// Get the error of last operation, such as a block failing checksum
+    /// verification when it's loaded with `checksum_mode` verifying on
+    /// block read. Reaching the end of the table is not an error.
+    pub fn status(&self) -> Result<()> {
+        match &self.err {
+            Some(IteratorError::Error(msg)) => Err(Error::TableRead(msg.clone())),
+            Some(IteratorError::EOF) | None => Ok(()),
+        }
+    }
+}
+
+/// ConcatIterator iterates over tables with non-overlapping key ranges
+/// one by one. Tables must be sorted by key in ascending order.
+pub struct ConcatIterator {
+    tables: Vec<Table>,
+    /// iterators of tables, created on first use
+    iters: Vec<Option<Iterator<Arc<TableInner>>>>,
+    /// index of current table, `None` if the iterator is out of range
+    cur: Option<usize>,
+    opt: usize,
+}
+
+impl ConcatIterator {
+    /// Create an iterator over sorted tables. Only `reversed`, `no_cache`
+    /// and `prefetch_size` of `opts` are used, key bounds are ignored.
+    pub fn new(tables: Vec<Table>, opts: &ReadOptions) -> Self {
+        let iters = tables.iter().map(|_| None).collect();
+        Self {
+            tables,
+            iters,
+            cur: None,
+            opt: iterator_flags(opts),
+        }
+    }
+
+    fn set_idx(&mut self, idx: Option<usize>) {
+        self.cur = idx.filter(|idx| *idx < self.tables.len());
+        if let Some(idx) = self.cur {
+            if self.iters[idx].is_none() {
+                // the direction is managed by `ConcatIterator`
+                let opt = self.opt & !ITERATOR_REVERSED;
+                self.iters[idx] = Some(Iterator::new_shared(self.tables[idx].inner.clone(), opt));
+            }
+        }
+    }
+
+    fn cur_iter(&self) -> Option<&Iterator<Arc<TableInner>>> {
+        self.cur.and_then(|idx| self.iters[idx].as_ref())
+    }
+
+    fn cur_iter_mut(&mut self) -> Option<&mut Iterator<Arc<TableInner>>> {
+        match self.cur {
+            Some(idx) => self.iters[idx].as_mut(),
+            None => None,
+        }
+    }
+
+    /// Move to the first valid entry of tables starting from current one,
+    /// in ascending order of keys
+    fn forward_to_valid(&mut self) {
+        while let Some(idx) = self.cur {
+            if self.iters[idx].as_ref().unwrap().valid() {
+                return;
+            }
+            self.set_idx(Some(idx + 1));
+            if let Some(iter) = self.cur_iter_mut() {
+                iter.seek_to_first();
+            }
+        }
+    }
+
+    /// Move to the last valid entry of tables starting from current one,
+    /// in descending order of keys
+    fn backward_to_valid(&mut self) {
+        while let Some(idx) = self.cur {
+            if self.iters[idx].as_ref().unwrap().valid() {
+                return;
+            }
+            self.set_idx(idx.checked_sub(1));
+            if let Some(iter) = self.cur_iter_mut() {
+                iter.seek_to_last();
+            }
+        }
+    }
+
+    fn step_forward(&mut self) {
+        if let Some(iter) = self.cur_iter_mut() {
+            iter.next_inner();
+            self.forward_to_valid();
+        }
+    }
+
+    fn step_backward(&mut self) {
+        if let Some(iter) = self.cur_iter_mut() {
+            iter.prev_inner();
+            self.backward_to_valid();
+        }
+    }
+
+    /// Check if the iterator points to an entry
+    pub fn valid(&self) -> bool {
+        self.cur_iter().map_or(false, |iter| iter.valid())
+    }
+
+    pub fn key(&self) -> &[u8] {
+        self.cur_iter().unwrap().key()
+    }
+
+    pub fn value(&self) -> Value {
+        self.cur_iter().unwrap().value()
+    }
+
+    /// Move to next entry in the direction of the iterator
+    pub fn next(&mut self) {
+        if self.opt & ITERATOR_REVERSED == 0 {
+            self.step_forward();
+        } else {
+            self.step_backward();
+        }
+    }
+
+    /// Move to previous entry in the direction of the iterator
+    pub fn prev(&mut self) {
+        if self.opt & ITERATOR_REVERSED == 0 {
+            self.step_backward();
+        } else {
+            self.step_forward();
+        }
+    }
+
+    /// Reset the iterator to first element
+    pub fn rewind(&mut self) {
+        if self.tables.is_empty() {
+            self.set_idx(None);
+            return;
+        }
+        if self.opt & ITERATOR_REVERSED == 0 {
+            self.set_idx(Some(0));
+            self.cur_iter_mut().unwrap().seek_to_first();
+            self.forward_to_valid();
+        } else {
+            self.set_idx(Some(self.tables.len() - 1));
+            self.cur_iter_mut().unwrap().seek_to_last();
+            self.backward_to_valid();
+        }
+    }
+
+    /// Seek to first entry >= key, or first entry <= key if reversed
+    pub fn seek(&mut self, key: &Bytes) {
+        use std::cmp::Ordering::*;
+        let n = self.tables.len();
+        if self.opt & ITERATOR_REVERSED == 0 {
+            let idx = util::search(n, |idx| {
+                COMPARATOR.compare_key(self.tables[idx].biggest(), key) != Less
+            });
+            self.set_idx(Some(idx));
+            if let Some(idx) = self.cur {
+                self.iters[idx].as_mut().unwrap().seek_inner(key);
+                self.forward_to_valid();
+            }
+        } else {
+            let idx = util::search(n, |idx| {
+                COMPARATOR.compare_key(self.tables[n - 1 - idx].smallest(), key) != Greater
+            });
+            self.set_idx(if idx < n { Some(n - 1 - idx) } else { None });
+            if let Some(idx) = self.cur {
+                let iter = self.iters[idx].as_mut().unwrap();
+                if COMPARATOR.compare_key(self.tables[idx].biggest(), key) == Less {
+                    iter.seek_to_last();
+                } else {
+                    iter.seek_for_prev(key);
+                }
+                self.backward_to_valid();
+            }
+        }
+    }
+}
+
+impl<T: AsRef<TableInner>> AgateIterator for Iterator<T> {
+    fn next(&mut self) {
+        Iterator::next(self)
+    }
+
+    fn rewind(&mut self) {
+        Iterator::rewind(self)
+    }
+
+    fn seek(&mut self, key: &Bytes) {
+        Iterator::seek(self, key)
+    }
+
+    fn key(&self) -> &[u8] {
+        Iterator::key(self)
+    }
+
+    fn value(&self) -> Value {
+        Iterator::value(self)
+    }
+
+    fn valid(&self) -> bool {
+        Iterator::valid(self)
+    }
+}
+
+impl AgateIterator for ConcatIterator {
+    fn next(&mut self) {
+        ConcatIterator::next(self)
+    }
+
+    fn rewind(&mut self) {
+        ConcatIterator::rewind(self)
+    }
+
+    fn seek(&mut self, key: &Bytes) {
+        ConcatIterator::seek(self, key)
+    }
+
+    fn key(&self) -> &[u8] {
+        ConcatIterator::key(self)
+    }
+
+    fn value(&self) -> Value {
+        ConcatIterator::value(self)
+    }
+
+    fn valid(&self) -> bool {
+        ConcatIterator::valid(self)
+    }
+}
+
+/// Current key of an iterator in `MergeIterator`
+struct HeapEntry {
+    key: Bytes,
+    idx: usize,
+    reversed: bool,
+}
+
+impl Ord for HeapEntry {
+    /// The entry with smallest user key (or biggest if reversed) is the
+    /// greatest, and on the same user key the newer iterator is greater.
+    fn cmp(&self, other: &Self) -> Ordering {
+        let ord = user_key(&self.key).cmp(user_key(&other.key));
+        let ord = if self.reversed { ord } else { ord.reverse() };
+        ord.then(self.idx.cmp(&other.idx))
+    }
+}
+
+impl PartialOrd for HeapEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for HeapEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for HeapEntry {}
+
+/// MergeIterator merges iterators into one sorted stream. Iterators with
+/// higher index are newer, so if several iterators have the same user
+/// key, only entries of the newest one are visible.
+///
+/// All iterators must iterate in the same direction as `reversed`.
+pub struct MergeIterator {
+    iters: Vec<Box<dyn AgateIterator>>,
+    heap: BinaryHeap<HeapEntry>,
+    reversed: bool,
+}
+
+impl MergeIterator {
+    pub fn new(iters: Vec<Box<dyn AgateIterator>>, reversed: bool) -> Self {
+        Self {
+            iters,
+            heap: BinaryHeap::new(),
+            reversed,
+        }
+    }
+
+    fn push(&mut self, idx: usize) {
+        let iter = &self.iters[idx];
+        if iter.valid() {
+            self.heap.push(HeapEntry {
+                key: Bytes::copy_from_slice(iter.key()),
+                idx,
+                reversed: self.reversed,
+            });
+        }
+    }
+
+    fn rebuild_heap(&mut self) {
+        self.heap.clear();
+        for idx in 0..self.iters.len() {
+            self.push(idx);
+        }
+    }
+}
+
+impl AgateIterator for MergeIterator {
+    fn next(&mut self) {
+        let top = match self.heap.pop() {
+            Some(top) => top,
+            None => return,
+        };
+        // skip all versions of current key in older iterators
+        while let Some(entry) = self.heap.peek() {
+            if !COMPARATOR.same_key(&entry.key, &top.key) {
+                break;
+            }
+            let idx = self.heap.pop().unwrap().idx;
+            let iter = &mut self.iters[idx];
+            while iter.valid() && COMPARATOR.same_key(iter.key(), &top.key) {
+                iter.next();
+            }
+            self.push(idx);
+        }
+        self.iters[top.idx].next();
+        self.push(top.idx);
+    }
+
+    fn rewind(&mut self) {
+        for iter in &mut self.iters {
+            iter.rewind();
+        }
+        self.rebuild_heap();
+    }
+
+    fn seek(&mut self, key: &Bytes) {
+        for iter in &mut self.iters {
+            iter.seek(key);
+        }
+        self.rebuild_heap();
+    }
+
+    fn key(&self) -> &[u8] {
+        self.iters[self.heap.peek().unwrap().idx].key()
+    }
+
+    fn value(&self) -> Value {
+        self.iters[self.heap.peek().unwrap().idx].value()
+    }
+
+    fn valid(&self) -> bool {
+        !self.heap.is_empty()
+    }
+}
+
+/// MultiTableIterator merges a memtable iterator with a `ConcatIterator`
+/// over tables on disk. The memtable is newer, so if both have the same
+/// user key, only entries of the memtable are visible.
+pub struct MultiTableIterator {
+    iter: MergeIterator,
+}
+
+impl MultiTableIterator {
+    /// Create an iterator over `memtable` and `tables`, which must iterate
+    /// in the same direction.
+    pub fn new(memtable: Box<dyn AgateIterator>, tables: ConcatIterator) -> Self {
+        let reversed = tables.opt & ITERATOR_REVERSED != 0;
+        Self {
+            iter: MergeIterator::new(vec![Box::new(tables), memtable], reversed),
+        }
+    }
+}
+
+impl AgateIterator for MultiTableIterator {
+    fn next(&mut self) {
+        self.iter.next()
+    }
+
+    fn rewind(&mut self) {
+        self.iter.rewind()
+    }
+
+    fn seek(&mut self, key: &Bytes) {
+        self.iter.seek(key)
+    }
+
+    fn key(&self) -> &[u8] {
+        self.iter.key()
+    }
+
+    fn value(&self) -> Value {
+        self.iter.value()
+    }
+
+    fn valid(&self) -> bool {
+        self.iter.valid()
+    }
 }
 
 #[cfg(test)]
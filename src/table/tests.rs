@@ -1,7 +1,14 @@
 use super::*;
-use crate::format::{key_with_ts, user_key};
+use crate::bloom;
+use crate::cache::{BlockCache, FileCache, IndexCache};
+use crate::format::{get_ts, key_with_ts, user_key};
+use crate::iterator_trait::AgateIterator;
+use crate::key_registry::KeyRegistry;
+use crate::opt::{ChecksumAlgorithm, ChecksumVerificationMode, CompressionType, ReadOptions};
+use crate::util::{KeyComparator, COMPARATOR};
 use crate::value::Value;
-use builder::Builder;
+use builder::{Builder, HEADER_SIZE};
+use std::time::Duration;
 use tempdir::TempDir;
 
 fn key(prefix: &[u8], i: usize) -> Bytes {
@@ -17,11 +24,27 @@ fn test_generate_key() {
     assert_eq!(key(b"key", 233), Bytes::from("key0233"));
 }
 
+/// Get phase, offset and cause of failing to open a table
+fn open_error<T>(res: Result<T>) -> (OpenPhase, u64, Error) {
+    match res {
+        Err(Error::TableOpen {
+            phase,
+            offset,
+            source,
+            ..
+        }) => (phase, offset, *source),
+        Err(e) => panic!("unexpected error {:?}", e),
+        Ok(_) => panic!("expect table open to fail"),
+    }
+}
+
 fn get_test_table_options() -> Options {
     Options {
         block_size: 4 * 1024,
         table_size: 0,
         bloom_false_positive: 0.01,
+        checksum_mode: ChecksumVerificationMode::OnTableAndBlockRead,
+        ..Options::default()
     }
 }
 
@@ -50,7 +73,9 @@ fn build_table(mut kv_pairs: Vec<(Bytes, Bytes)>, opts: Options) -> Table {
     kv_pairs.sort_by(|x, y| x.0.cmp(&y.0));
 
     for (k, v) in kv_pairs {
-        builder.add(&key_with_ts(&k[..], 0), Value::new_with_meta(v, b'A', 0), 0);
+        builder
+            .add(&key_with_ts(&k[..], 0), Value::new_with_meta(v, b'A', 0), 0)
+            .unwrap();
     }
     let data = builder.finish();
 
@@ -67,7 +92,7 @@ fn test_table_iterator() {
     for n in 99..=101 {
         let opts = get_test_table_options();
         let table = build_test_table(b"key", n, opts);
-        let mut it = table.new_iterator(0);
+        let mut it = table.new_iterator(ReadOptions::default());
         it.rewind();
         let mut count = 0;
         while it.valid() {
@@ -82,12 +107,87 @@ fn test_table_iterator() {
     }
 }
 
+#[test]
+fn test_table_iterator_status() {
+    // one entry in each of 10 blocks
+    let value = |_| Bytes::from(vec![b'v'; 3000]);
+    let data = build_table_data_with_values(10, value, get_test_table_options());
+    let table = Table::open_in_memory(data.clone(), 1, get_test_table_options()).unwrap();
+    assert_eq!(table.offsets_length(), 10);
+    let mut corrupted = data.to_vec();
+    corrupted[table.fetch_index().unwrap().offsets[5].offset as usize + 100] ^= 0xff;
+
+    let opts = Options {
+        checksum_mode: ChecksumVerificationMode::OnBlockRead,
+        ..get_test_table_options()
+    };
+    let table = Table::open_in_memory(Bytes::from(corrupted), 1, opts).unwrap();
+    let mut it = table.new_iterator(ReadOptions::default());
+    it.rewind();
+    let mut count = 0;
+    while it.valid() {
+        assert!(it.status().is_ok());
+        count += 1;
+        it.next();
+    }
+    assert_eq!(count, 5);
+    assert!(it.status().is_err());
+
+    it.seek(&key_with_ts(&key(b"key", 7)[..], 0));
+    assert!(it.valid());
+    assert!(it.status().is_ok());
+    it.seek(&key_with_ts(&key(b"key", 5)[..], 0));
+    assert!(!it.valid());
+    assert!(it.status().is_err());
+
+    // reaching the end is not an error
+    it.seek(&key_with_ts(&key(b"key", 9)[..], 0));
+    it.next();
+    assert!(!it.valid());
+    assert!(it.status().is_ok());
+}
+
+#[test]
+fn test_table_iterator_peek() {
+    let table = build_test_table(b"key", 1000, get_test_table_options());
+    for reversed in vec![false, true] {
+        let mut it = table.new_iterator(ReadOptions {
+            reversed,
+            ..ReadOptions::default()
+        });
+        assert!(it.peek().is_none());
+        it.rewind();
+        let mut count = 0;
+        while let Some((k, v)) = it.peek() {
+            let i = if reversed { 999 - count } else { count };
+            assert_eq!(k, &key_with_ts(&key(b"key", i)[..], 0)[..]);
+            assert_eq!(v.value, i.to_string());
+            // peeking again doesn't move the iterator
+            let (k2, v2) = it.peek().unwrap();
+            assert_eq!(k2, it.key());
+            assert_eq!(v2.value, it.value().value);
+            count += 1;
+            it.next();
+        }
+        assert_eq!(count, 1000);
+        assert!(!it.valid());
+        assert!(it.peek().is_none());
+    }
+
+    // seek positions the iterator for peek
+    let mut it = table.new_iterator(ReadOptions::default());
+    it.seek(&key_with_ts(&key(b"key", 500)[..], 0));
+    assert_eq!(it.peek().unwrap().1.value, "500");
+    it.next();
+    assert_eq!(it.peek().unwrap().1.value, "501");
+}
+
 #[test]
 fn test_seek_to_first() {
     for n in vec![99, 100, 101, 199, 200, 250, 9999, 10000] {
         let opts = get_test_table_options();
         let table = build_test_table(b"key", n, opts);
-        let mut it = table.new_iterator(0);
+        let mut it = table.new_iterator(ReadOptions::default());
         it.seek_to_first();
         assert!(it.valid());
         assert_eq!(it.value().value, "0");
@@ -100,7 +200,7 @@ fn test_seek_to_last() {
     for n in vec![99, 100, 101, 199, 200, 250, 9999, 10000] {
         let opts = get_test_table_options();
         let table = build_test_table(b"key", n, opts);
-        let mut it = table.new_iterator(0);
+        let mut it = table.new_iterator(ReadOptions::default());
         it.seek_to_last();
         assert!(it.valid());
         assert_eq!(it.value().value, (n - 1).to_string());
@@ -116,7 +216,7 @@ fn test_seek_to_last() {
 fn test_seek() {
     let opts = get_test_table_options();
     let table = build_test_table(b"k", 10000, opts);
-    let mut it = table.new_iterator(0);
+    let mut it = table.new_iterator(ReadOptions::default());
 
     let data = vec![
         (b"abc".to_vec(), true, b"k0000".to_vec()),
@@ -139,11 +239,33 @@ fn test_seek() {
     }
 }
 
+#[test]
+fn test_seek_block_boundary() {
+    let opts = get_test_table_options();
+    let table = build_test_table(b"k", 10000, opts);
+    let mut it = table.new_iterator(ReadOptions::default());
+    let index = table.fetch_index().unwrap();
+    assert!(index.offsets.len() > 1);
+
+    for block_offset in &index.offsets {
+        let first = user_key(&block_offset.key);
+        it.seek(&Bytes::copy_from_slice(&block_offset.key));
+        assert!(it.valid());
+        assert_eq!(user_key(it.key()), first);
+
+        // a newer version sorts before the first key of the block, which
+        // must still land on this block rather than the end of the previous one
+        it.seek(&key_with_ts(first, 1));
+        assert!(it.valid());
+        assert_eq!(user_key(it.key()), first);
+    }
+}
+
 #[test]
 fn test_seek_for_prev() {
     let opts = get_test_table_options();
     let table = build_test_table(b"k", 10000, opts);
-    let mut it = table.new_iterator(0);
+    let mut it = table.new_iterator(ReadOptions::default());
 
     let data = vec![
         ("abc", false, ""),
@@ -166,12 +288,100 @@ fn test_seek_for_prev() {
     }
 }
 
+/// Build a table of one block, holding versions 2, 4, ..., 100 of each of
+/// user keys `a`, `b` and `c`
+fn build_multi_version_table() -> Table {
+    let mut opts = get_test_table_options();
+    opts.block_size = 64 << 10;
+    let mut builder = Builder::new(opts.clone());
+    for k in &[b"a", b"b", b"c"] {
+        for ts in (2..=100).rev().step_by(2) {
+            let v = Bytes::from(format!("{}{}", k[0] as char, ts));
+            builder
+                .add(&key_with_ts(&k[..], ts), Value::new(v), 0)
+                .unwrap();
+        }
+    }
+    let table = Table::open_in_memory(builder.finish(), 1, opts).unwrap();
+    assert_eq!(table.offsets_length(), 1);
+    table
+}
+
+#[test]
+fn test_seek_multi_version() {
+    let table = build_multi_version_table();
+    let mut it = table.new_iterator(ReadOptions::default());
+    let mut block_it = table.block(0, false).unwrap().iter();
+
+    let cases: Vec<(&[u8], u64, Option<(&[u8], u64)>)> = vec![
+        // newest version not newer than the requested one
+        (b"a", 100, Some((b"a", 100))),
+        (b"a", u64::MAX, Some((b"a", 100))),
+        (b"b", 51, Some((b"b", 50))),
+        (b"b", 50, Some((b"b", 50))),
+        (b"b", 2, Some((b"b", 2))),
+        // older than all versions, so it moves to the next user key
+        (b"a", 1, Some((b"b", 100))),
+        (b"b", 0, Some((b"c", 100))),
+        (b"c", 1, None),
+        (b"0", 0, Some((b"a", 100))),
+        (b"bb", 100, Some((b"c", 100))),
+    ];
+    for (k, ts, expected) in cases {
+        let key = key_with_ts(k, ts);
+        it.seek(&key);
+        block_it.seek(&key);
+        let expected = expected.map(|(k, ts)| key_with_ts(k, ts));
+        for (valid, actual) in vec![(it.valid(), it.key()), (block_it.valid(), block_it.key())] {
+            match &expected {
+                Some(expected) => {
+                    assert!(valid, "{:?} {}", k, ts);
+                    assert_eq!(actual, &expected[..], "{:?} {}", k, ts);
+                }
+                None => assert!(!valid, "{:?} {}", k, ts),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_seek_for_prev_multi_version() {
+    let table = build_multi_version_table();
+    let mut it = table.new_iterator(ReadOptions::default());
+
+    let cases: Vec<(&[u8], u64, Option<(&[u8], u64)>)> = vec![
+        // oldest version not older than the requested one
+        (b"a", 100, Some((b"a", 100))),
+        (b"b", 51, Some((b"b", 52))),
+        (b"b", 50, Some((b"b", 50))),
+        (b"b", 1, Some((b"b", 2))),
+        (b"b", 0, Some((b"b", 2))),
+        // newer than all versions, so it moves to the previous user key
+        (b"b", 101, Some((b"a", 2))),
+        (b"c", u64::MAX, Some((b"b", 2))),
+        (b"a", 101, None),
+        (b"d", u64::MAX, Some((b"c", 2))),
+        (b"bb", 100, Some((b"b", 2))),
+    ];
+    for (k, ts, expected) in cases {
+        it.seek_for_prev(&key_with_ts(k, ts));
+        match expected {
+            Some((ek, ets)) => {
+                assert!(it.valid(), "{:?} {}", k, ts);
+                assert_eq!(it.key(), &key_with_ts(ek, ets)[..], "{:?} {}", k, ts);
+                assert_eq!(get_ts(it.key()), ets);
+            }
+            None => assert!(!it.valid(), "{:?} {}", k, ts),
+        }
+    }
+}
+
 #[test]
 fn test_iterate_from_start() {
     for n in vec![99, 100, 101, 199, 200, 250, 9999, 10000] {
         let opts = get_test_table_options();
         let table = build_test_table(b"key", n, opts);
-        let mut it = table.new_iterator(0);
+        let mut it = table.new_iterator(ReadOptions::default());
         it.reset();
         it.seek_to_first();
         assert!(it.valid());
@@ -194,7 +404,7 @@ fn test_iterate_from_end() {
     for n in vec![99, 100, 101, 199, 200, 250, 9999, 10000] {
         let opts = get_test_table_options();
         let table = build_test_table(b"key", n, opts);
-        let mut it = table.new_iterator(0);
+        let mut it = table.new_iterator(ReadOptions::default());
         it.reset();
         it.seek(&key_with_ts(b"zzzzzz" as &[u8], 0));
         assert!(!it.valid());
@@ -215,7 +425,7 @@ fn test_iterate_from_end() {
 fn test_table() {
     let opts = get_test_table_options();
     let table = build_test_table(b"key", 10000, opts);
-    let mut it = table.new_iterator(0);
+    let mut it = table.new_iterator(ReadOptions::default());
     let mut kid = 1010;
     let seek = key_with_ts(&key(b"key", kid)[..], 0);
     it.seek(&seek);
@@ -239,7 +449,7 @@ fn test_table() {
 fn test_iterate_back_and_forth() {
     let opts = get_test_table_options();
     let table = build_test_table(b"key", 10000, opts);
-    let mut it = table.new_iterator(0);
+    let mut it = table.new_iterator(ReadOptions::default());
     let seek = key_with_ts(&key(b"key", 1010)[..], 0);
 
     it.seek(&seek);
@@ -274,7 +484,7 @@ fn test_uni_iterator() {
     let opts = get_test_table_options();
     let table = build_test_table(b"key", 10000, opts);
 
-    let mut it = table.new_iterator(0);
+    let mut it = table.new_iterator(ReadOptions::default());
     it.rewind();
     let mut count = 0;
     while it.valid() {
@@ -286,7 +496,10 @@ fn test_uni_iterator() {
     }
     assert_eq!(count, 10000);
 
-    let mut it = table.new_iterator(ITERATOR_REVERSED);
+    let mut it = table.new_iterator(ReadOptions {
+        reversed: true,
+        ..ReadOptions::default()
+    });
     it.rewind();
     let mut count = 0;
     while it.valid() {
@@ -299,48 +512,3048 @@ fn test_uni_iterator() {
     assert_eq!(count, 10000);
 }
 
-// TODO: concat iterators and merge iterators
+#[test]
+fn test_concurrent_iterators() {
+    let opts = get_test_table_options();
+    let table = Arc::new(build_test_table(b"key", 10000, opts));
 
-fn value(i: usize) -> Bytes {
-    Bytes::from(format!("{:01048576}", i)) // 1MB value
+    let handles: Vec<_> = (0..8)
+        .map(|_| {
+            let table = table.clone();
+            std::thread::spawn(move || {
+                let mut it = table.new_iterator(ReadOptions::default());
+                it.rewind();
+                let mut count = 0;
+                while it.valid() {
+                    assert_eq!(count.to_string(), it.value().value);
+                    assert_eq!(key_with_ts(&key(b"key", count)[..], 0), it.key());
+                    count += 1;
+                    it.next();
+                }
+                count
+            })
+        })
+        .collect();
+    for handle in handles {
+        assert_eq!(handle.join().unwrap(), 10000);
+    }
 }
 
 #[test]
-fn test_table_big_values() {
-    let n: usize = 100;
-    let opts = Options {
-        block_size: 4 * 1024,
-        bloom_false_positive: 0.01,
-        table_size: (n as u64) * (1 << 20),
-    };
-    let mut builder = Builder::new(opts.clone());
+fn test_concurrent_open() {
+    let opts = get_test_table_options();
+    let data = build_test_table_data(b"key", 10000, opts.clone());
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    let path = tmp_dir.path().join("1.sst");
+    let expected = collect_table(&Table::create(&path, data, opts.clone()).unwrap());
+    let expected: Vec<_> = expected.into_iter().map(|(k, v)| (k, v.value)).collect();
+    let expected = Arc::new(expected);
 
-    for i in 0..n {
-        let key = key_with_ts(&key(b"", i)[..], i as u64 + 1);
-        let vs = Value::new(value(i));
-        builder.add(&key, vs, 0);
+    let handles: Vec<_> = (0..8)
+        .map(|_| {
+            let path = path.clone();
+            let opts = opts.clone();
+            let expected = expected.clone();
+            std::thread::spawn(move || {
+                let table = Table::open(&path, opts).unwrap();
+                let kvs: Vec<_> = collect_table(&table)
+                    .into_iter()
+                    .map(|(k, v)| (k, v.value))
+                    .collect();
+                assert_eq!(&kvs, expected.as_ref());
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
     }
+}
+
+/// Build `n` tables of 1000 keys each in `dir`, with ids starting from 1
+fn build_tables_in_dir(dir: &Path, n: usize, opts: Options) -> Vec<Table> {
+    (1..=n as u64)
+        .map(|id| {
+            let data = build_test_table_data(b"key", 1000, opts.clone());
+            Table::create(&new_filename(id, dir), data, opts.clone()).unwrap()
+        })
+        .collect()
+}
 
+#[test]
+fn test_table_file_cache() {
     let tmp_dir = TempDir::new("agatedb").unwrap();
-    let filename = tmp_dir.path().join("1.sst".to_string());
+    let cache = Arc::new(FileCache::with_capacity(2));
+    let mut opts = get_test_table_options();
+    opts.use_mmap = false;
+    opts.file_cache = Some(cache.clone());
+    let tables = build_tables_in_dir(tmp_dir.path(), 10, opts);
+    assert_eq!(cache.len(), 2);
 
-    let table = Table::create(&filename, builder.finish(), opts).unwrap();
+    for _ in 0..2 {
+        for table in &tables {
+            let mut it = table.new_iterator(ReadOptions::default());
+            it.rewind();
+            let mut count = 0;
+            while it.valid() {
+                assert_eq!(user_key(it.key()), &key(b"key", count)[..]);
+                count += 1;
+                it.next();
+            }
+            assert_eq!(count, 1000);
+            assert!(cache.len() <= 2);
+        }
+    }
 
-    let mut it = table.new_iterator(0);
-    assert!(it.valid());
+    drop(tables);
+    assert!(cache.is_empty());
+}
 
-    let mut count = 0;
-    it.rewind();
+#[test]
+fn test_table_file_cache_concurrent() {
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    let cache = Arc::new(FileCache::with_capacity(2));
+    let mut opts = get_test_table_options();
+    opts.use_mmap = false;
+    opts.file_cache = Some(cache.clone());
+    let tables = Arc::new(build_tables_in_dir(tmp_dir.path(), 10, opts));
+
+    let handles: Vec<_> = (0..8)
+        .map(|i| {
+            let tables = tables.clone();
+            std::thread::spawn(move || {
+                for j in 0..tables.len() {
+                    let table = &tables[(i + j) % tables.len()];
+                    let mut it = table.new_iterator(ReadOptions::default());
+                    it.rewind();
+                    let mut count = 0;
+                    while it.valid() {
+                        count += 1;
+                        it.next();
+                    }
+                    assert_eq!(count, 1000);
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert!(cache.len() <= 2);
+}
+
+#[test]
+fn test_table_file_cache_reopen_deleted() {
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    let cache = Arc::new(FileCache::with_capacity(1));
+    let mut opts = get_test_table_options();
+    opts.use_mmap = false;
+    opts.file_cache = Some(cache.clone());
+    let tables = build_tables_in_dir(tmp_dir.path(), 2, opts);
+
+    // table 1 has been evicted by table 2
+    let path = new_filename(1, tmp_dir.path());
+    fs::remove_file(&path).unwrap();
+    match tables[0].inner.block(0, false) {
+        Err(Error::TableRead(msg)) => assert!(msg.contains(&path.display().to_string())),
+        Err(e) => panic!("unexpected error: {:?}", e),
+        Ok(_) => panic!("expect reopen to fail"),
+    }
+    // table 2 is still open
+    assert!(tables[1].inner.block(0, false).is_ok());
+}
+
+#[test]
+fn test_table_without_mmap() {
+    let mut opts = get_test_table_options();
+    opts.use_mmap = false;
+    let table = build_test_table(b"key", 10000, opts);
+    assert!(matches!(**table.inner.file, MmapFile::File { .. }));
 
+    let mut it = table.new_iterator(ReadOptions::default());
+    it.seek(&key_with_ts(&key(b"key", 5000)[..], 0));
+    let mut count = 5000;
     while it.valid() {
-        assert_eq!(key(b"", count), user_key(it.key()));
-        assert_eq!(value(count), it.value().value);
+        assert_eq!(count.to_string(), it.value().value);
+        count += 1;
         it.next();
+    }
+    assert_eq!(count, 10000);
+
+    let table = build_test_table(b"key", 10, get_test_table_options());
+    assert!(matches!(**table.inner.file, MmapFile::Mmap { .. }));
+}
+
+#[test]
+fn test_table_iterator_prefetch() {
+    let mut opts = get_test_table_options();
+    opts.use_mmap = false;
+    let table = build_test_table(b"key", 10000, opts);
+    let num_blocks = table.offsets_length();
+    assert!(num_blocks > 2);
+
+    for &reversed in &[false, true] {
+        let read_opts = ReadOptions {
+            reversed,
+            no_cache: true,
+            prefetch_size: 1,
+            ..ReadOptions::default()
+        };
+        table.io_stats().reset();
+        let mut it = table.new_iterator(read_opts.clone());
+        it.rewind();
+        let mut count = 0;
+        while it.valid() {
+            let i = if reversed { 9999 - count } else { count };
+            assert_eq!(it.value().value, i.to_string());
+            count += 1;
+            it.next();
+        }
+        assert_eq!(count, 10000);
+        // every block is read once, and nothing past the last block is read
+        assert_eq!(
+            table.io_stats().read_count.load(Ordering::Relaxed),
+            num_blocks as u64
+        );
+
+        // seek away while the next block is being read
+        let mut it = table.new_iterator(read_opts);
+        it.rewind();
+        for &i in &[5000, 10, 9990, 5000] {
+            it.seek(&key_with_ts(&key(b"key", i)[..], 0));
+            for j in 0..5 {
+                let expected = if reversed { i - j } else { i + j };
+                assert_eq!(it.value().value, expected.to_string());
+                it.next();
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn test_table_async() {
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    let path = tmp_dir.path().join("1.sst");
+    let opts = get_test_table_options();
+    let data = build_test_table_data(b"key", 10000, opts.clone());
+    drop(Table::create(&path, data, opts.clone()).unwrap());
+
+    let table = Table::open_async(&path, opts).await.unwrap();
+    let mut count = 0;
+    for idx in 0..table.offsets_length() {
+        let mut it = table.block_async(idx, true).await.unwrap().iter();
+        it.seek_to_first();
+        while it.valid() {
+            assert_eq!(user_key(it.key()), &key(b"key", count)[..]);
+            count += 1;
+            it.next();
+        }
+    }
+    assert_eq!(count, 10000);
+
+    let value = table.get_async(&key(b"key", 500), 0).await.unwrap();
+    assert_eq!(value.unwrap().value, "500");
+    assert!(table.get_async(b"key", 0).await.unwrap().is_none());
+}
+
+#[test]
+fn test_table_load_to_ram() {
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    let path = new_filename(7, tmp_dir.path());
+    let data = build_test_table_data(b"key", 10000, get_test_table_options());
+    drop(Table::create(&path, data, get_test_table_options()).unwrap());
+
+    let mut opts = get_test_table_options();
+    opts.load_to_ram = true;
+    let table = Table::open(&path, opts).unwrap();
+    assert!(matches!(**table.inner.file, MmapFile::Buffered { .. }));
+    assert_eq!(table.inner.filename(), path.to_string_lossy());
+    assert_eq!(table.inner.id(), 7);
+
+    // reads are served from memory even if the file is gone
+    let copy = tmp_dir.path().join("copy");
+    fs::rename(&path, &copy).unwrap();
+    let mut it = table.new_iterator(ReadOptions::default());
+    it.rewind();
+    let mut count = 0;
+    while it.valid() {
+        assert_eq!(count.to_string(), it.value().value);
         count += 1;
+        it.next();
     }
+    assert_eq!(count, 10000);
 
-    assert!(!it.valid());
-    assert_eq!(n, count);
-    // TODO: support max_version in table
-    // assert_eq!(n, table.max_version());
+    fs::rename(&copy, &path).unwrap();
+    table.mark_delete();
+    drop(table);
+    assert!(!path.exists());
+}
+
+#[test]
+fn test_block_verify_checksum() {
+    let table = build_test_table(b"key", 1000, get_test_table_options());
+    let block = table.block(0, false).unwrap();
+    block.verify_checksum().unwrap();
+
+    // flip one byte of the first entry
+    let mut data = block.data.to_vec();
+    data[HEADER_SIZE] ^= 0xff;
+    let corrupted = Block {
+        offset: block.offset,
+        data: Bytes::from(data),
+        checksum: block.checksum.clone(),
+        entries_index_start: block.entries_index_start,
+        entry_offsets: block.entry_offsets.clone(),
+        checksum_len: block.checksum_len,
+        disk_data: None,
+    };
+    assert!(matches!(
+        corrupted.verify_checksum(),
+        Err(Error::Corruption(_))
+    ));
+}
+
+#[test]
+fn test_table_verify_checksum() {
+    let mut opts = get_test_table_options();
+    opts.checksum_mode = ChecksumVerificationMode::NoVerification;
+    let data = build_test_table_data(b"key", 1000, opts.clone());
+    let table = Table::open_in_memory(data.clone(), 1, opts.clone()).unwrap();
+    assert!(!table.checksum().is_empty());
+    table.verify_checksum(None).unwrap();
+    table.verify_checksum(Some(0.5)).unwrap();
+    table.verify_checksum_parallel().unwrap();
+    let another = Table::open_in_memory(data.clone(), 2, opts.clone()).unwrap();
+    assert_eq!(another.checksum(), table.checksum());
+
+    // flip one byte of the first entry in block 0
+    let mut corrupted = data.to_vec();
+    corrupted[HEADER_SIZE] ^= 0xff;
+    let pools = vec![
+        None,
+        Some(Arc::new(
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(1)
+                .build()
+                .unwrap(),
+        )),
+        Some(Arc::new(
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(2)
+                .build()
+                .unwrap(),
+        )),
+    ];
+    for pool in pools {
+        opts.verify_checksum_pool = pool;
+        let table = Table::open_in_memory(Bytes::from(corrupted.clone()), 1, opts.clone()).unwrap();
+        match table.verify_checksum(None) {
+            Err(Error::Corruption(msg)) => {
+                assert!(msg.contains("block 0 at offset 0 of table 1"), "{}", msg);
+                assert!(
+                    msg.contains("expected") && msg.contains("actual"),
+                    "{}",
+                    msg
+                );
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert!(table.verify_checksum(Some(1.0)).is_err());
+        assert!(table.verify_checksum_parallel().is_err());
+        // no block is verified
+        table.verify_checksum(Some(0.0)).unwrap();
+        assert!(matches!(
+            table.verify_checksum(Some(1.5)),
+            Err(Error::Config(_))
+        ));
+        assert!(matches!(
+            table.verify_checksum(Some(f64::NAN)),
+            Err(Error::Config(_))
+        ));
+    }
+}
+
+#[test]
+fn test_block_prefix_compression() {
+    let n = 10000;
+    let opts = get_test_table_options();
+    let data = build_test_table_data(b"key", n, opts);
+    // size of blocks if every entry stored its full key
+    let full_size: usize = (0..n)
+        .map(|i| {
+            let k = key_with_ts(&key(b"key", i)[..], 0);
+            let v = Value::new_with_meta(Bytes::from(i.to_string()), b'A', 0);
+            HEADER_SIZE + k.len() + v.encoded_size() as usize + 4
+        })
+        .sum();
+    // keys are stored as diffs to the first key of their blocks
+    assert!(data.len() < full_size);
+}
+
+#[test]
+fn test_table_create() {
+    let opts = get_test_table_options();
+    let data = build_test_table_data(b"key", 1000, opts.clone());
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    let path = tmp_dir.path().join("1.sst");
+    let table = Table::create(&path, data.clone(), opts.clone()).unwrap();
+    assert_eq!(fs::metadata(&path).unwrap().len(), data.len() as u64);
+    assert_eq!(table.size(), data.len() as u64);
+    assert_eq!(collect_table(&table).len(), 1000);
+
+    // existing file is never overwritten
+    assert!(Table::create(&path, data, opts).is_err());
+}
+
+#[test]
+fn test_table_create_streaming() {
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    let add = |builder: &mut Builder| {
+        for i in 0..10000 {
+            let k = key_with_ts(&key(b"key", i)[..], i as u64);
+            builder
+                .add(&k, Value::new(Bytes::from(i.to_string())), 0)
+                .unwrap();
+        }
+    };
+    for compression in vec![CompressionType::None, CompressionType::Snappy] {
+        let mut opts = get_test_table_options();
+        opts.compression = compression;
+        opts.prefix_bloom_len = Some(4);
+
+        let path = tmp_dir.path().join("1.sst");
+        let table = Table::create_streaming(&path, opts.clone(), |builder| {
+            assert!(builder.is_streaming());
+            add(builder);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(collect_table(&table).len(), 10000);
+        table.verify_checksum(None).unwrap();
+
+        // the same as SST built in memory
+        let mut builder = Builder::new(opts.clone());
+        add(&mut builder);
+        let data = builder.finish();
+        assert_eq!(fs::read(&path).unwrap(), data);
+        assert_eq!(table.size(), data.len() as u64);
+        drop(table);
+        fs::remove_file(&path).unwrap();
+    }
+
+    // empty SST
+    let opts = get_test_table_options();
+    let path = tmp_dir.path().join("2.sst");
+    let table = Table::create_streaming(&path, opts.clone(), |_| Ok(())).unwrap();
+    assert!(table.is_empty());
+    assert_eq!(
+        fs::read(&path).unwrap(),
+        Builder::new(opts.clone()).finish()
+    );
+
+    // files are removed on errors
+    let path = tmp_dir.path().join("3.sst");
+    let res = Table::create_streaming(&path, opts.clone(), |builder| {
+        add(builder);
+        Err(Error::TableRead("stop".to_string()))
+    });
+    assert!(res.is_err());
+    assert!(!path.exists());
+    // existing files are never overwritten
+    let path = tmp_dir.path().join("2.sst");
+    assert!(Table::create_streaming(&path, opts, |_| Ok(())).is_err());
+    assert!(path.exists());
+}
+
+#[test]
+fn test_table_open_in_memory_from_vec() {
+    let opts = get_test_table_options();
+    let data = build_test_table_data(b"key", 1000, opts.clone()).to_vec();
+    let start = data.as_ptr() as usize;
+    let range = start..start + data.len();
+    let table = Table::open_in_memory_from_vec(data, 1, opts.clone()).unwrap();
+    assert_eq!(collect_table(&table).len(), 1000);
+
+    // the table and its blocks share the buffer of the vec
+    assert_eq!(table.data().unwrap().as_ptr() as usize, start);
+    for idx in 0..table.offsets_length() {
+        let block = table.block(idx, false).unwrap();
+        assert!(range.contains(&(block.data.as_ptr() as usize)));
+    }
+
+    // data of tables on disk is not kept in memory
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    let path = tmp_dir.path().join("1.sst");
+    let table = Table::create(&path, table.data().unwrap(), opts).unwrap();
+    assert!(table.data().is_none());
+    assert_eq!(collect_table(&table).len(), 1000);
+}
+
+#[test]
+fn test_table_ingest() {
+    let opts = get_test_table_options();
+    let data = build_test_table_data(b"key", 1000, opts.clone());
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    let db_dir = tmp_dir.path().join("db");
+    fs::create_dir(&db_dir).unwrap();
+    let src = tmp_dir.path().join("external.tmp");
+    fs::write(&src, &data).unwrap();
+
+    let table = Table::ingest(&src, 5, &db_dir, opts.clone()).unwrap();
+    let path = new_filename(5, &db_dir);
+    assert_eq!(table.inner.id(), 5);
+    assert_eq!(table.inner.filename(), path.to_string_lossy());
+    assert_eq!(fs::read(&path).unwrap(), data);
+    assert_eq!(collect_table(&table).len(), 1000);
+    drop(table);
+    assert_eq!(
+        collect_table(&Table::open(&path, opts.clone()).unwrap()).len(),
+        1000
+    );
+
+    // an existing SST is never overwritten
+    assert!(Table::ingest(&src, 5, &db_dir, opts.clone()).is_err());
+    assert_eq!(fs::read(&path).unwrap(), data);
+
+    // corrupted files are not adopted
+    let mut corrupted = data.to_vec();
+    corrupted[HEADER_SIZE] ^= 0xff;
+    fs::write(&src, &corrupted).unwrap();
+    let err = Table::ingest(&src, 6, &db_dir, opts.clone()).err().unwrap();
+    assert!(err.is_corruption(), "{:?}", err);
+    assert!(!new_filename(6, &db_dir).exists());
+
+    // neither are tables without any key
+    let empty = Builder::new(opts.clone()).finish();
+    fs::write(&src, &empty).unwrap();
+    assert!(Table::ingest(&src, 7, &db_dir, opts).is_err());
+    assert!(!new_filename(7, &db_dir).exists());
+}
+
+#[test]
+fn test_table_copy_to() {
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    let data = build_test_table_data(b"key", 1000, get_test_table_options());
+    let src = tmp_dir.path().join("1.sst");
+    fs::write(&src, &data).unwrap();
+    let mmap_opts = get_test_table_options();
+    let file_opts = Options {
+        use_mmap: false,
+        ..get_test_table_options()
+    };
+    let tables = vec![
+        Table::open_in_memory(data.clone(), 1, get_test_table_options()).unwrap(),
+        Table::open(&src, mmap_opts).unwrap(),
+        Table::open(&src, file_opts).unwrap(),
+    ];
+
+    for (i, table) in tables.into_iter().enumerate() {
+        let dest = tmp_dir.path().join(format!("{}.sst", i + 10));
+        let copied = table.copy_to(&dest).unwrap();
+        assert_eq!(copied.inner.id(), i as u64 + 10);
+        assert_eq!(fs::read(&dest).unwrap(), data);
+
+        // both tables can be read on their own
+        let (kvs, copied_kvs) = (collect_table(&table), collect_table(&copied));
+        assert_eq!(kvs.len(), 1000);
+        assert_eq!(kvs.len(), copied_kvs.len());
+        for ((k1, v1), (k2, v2)) in kvs.iter().zip(&copied_kvs) {
+            assert_eq!(k1, k2);
+            assert_eq!(v1.value, v2.value);
+        }
+        drop(copied);
+        assert_eq!(collect_table(&table).len(), 1000);
+
+        // an existing file is never overwritten
+        assert!(table.copy_to(&dest).is_err());
+        assert_eq!(fs::read(&dest).unwrap(), data);
+    }
+    assert_eq!(fs::read(&src).unwrap(), data);
+}
+
+#[test]
+fn test_table_from_builder() {
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    let expected = build_test_table_data(b"key", 1000, get_test_table_options());
+    for (id, flush_threshold) in [0, 64 << 20].iter().enumerate() {
+        let mut opts = get_test_table_options();
+        opts.flush_threshold = *flush_threshold;
+        let mut builder = Builder::new(opts.clone());
+        for i in 0..1000 {
+            let k = key_with_ts(&key(b"key", i)[..], 0);
+            builder
+                .add(
+                    &k,
+                    Value::new_with_meta(Bytes::from(i.to_string()), b'A', 0),
+                    0,
+                )
+                .unwrap();
+        }
+        let path = tmp_dir.path().join(format!("{}.sst", id + 1));
+        let table = Table::from_builder(&path, builder, opts).unwrap();
+        assert_eq!(
+            matches!(**table.inner.file, MmapFile::Buffered { .. }),
+            *flush_threshold > 0
+        );
+        assert_eq!(fs::read(&path).unwrap(), expected);
+        assert_eq!(table.inner.filename(), path.to_string_lossy());
+        assert_eq!(collect_table(&table).len(), 1000);
+
+        table.delete().unwrap();
+        assert!(!path.exists());
+    }
+}
+
+#[test]
+fn test_table_stats() {
+    let opts = get_test_table_options();
+    let data = build_test_table_data(b"key", 10000, opts.clone());
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    let path = tmp_dir.path().join("1.sst");
+    let tables = vec![
+        Table::create(&path, data.clone(), opts.clone()).unwrap(),
+        Table::open_in_memory(data, 1, opts).unwrap(),
+    ];
+    for table in tables {
+        let stats = table.stats().unwrap();
+        assert_eq!(stats.id, 1);
+        assert_eq!(stats.block_count, table.offsets_length());
+        assert_eq!(stats.block_sizes.len(), stats.block_count);
+        assert_eq!(stats.key_count, 10000);
+        assert_eq!(&stats.smallest, table.smallest());
+        assert_eq!(&stats.biggest, table.biggest());
+        assert!(stats.bloom_filter_size > 0 && stats.bloom_filter_size < stats.index_size);
+
+        // the rest is index, its length, checksum, checksum length and trailer
+        let blocks_size: u64 = stats.block_sizes.iter().map(|len| *len as u64).sum();
+        let footer_size =
+            (stats.index_size + 4 + table.checksum().len() + 4 + builder::TRAILER_SIZE) as u64;
+        assert_eq!(blocks_size + footer_size, table.size());
+        assert_eq!(stats.size, table.size());
+    }
+    assert_eq!(
+        Table::open(&path, get_test_table_options())
+            .unwrap()
+            .stats()
+            .unwrap()
+            .filename,
+        path.to_string_lossy()
+    );
+}
+
+#[test]
+fn test_table_delete() {
+    let opts = get_test_table_options();
+    let data = build_test_table_data(b"key", 1000, opts.clone());
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    let path = tmp_dir.path().join("1.sst");
+    let table = Table::create(&path, data.clone(), opts.clone()).unwrap();
+
+    // table can't be deleted while referenced by an iterator
+    let it = table.new_iterator(ReadOptions::default());
+    let table2 = table.clone();
+    assert!(table.delete().is_err());
+    assert!(path.exists());
+    drop(it);
+
+    table2.delete().unwrap();
+    assert!(!path.exists());
+
+    let table = Table::open_in_memory(data, 1, opts).unwrap();
+    table.delete().unwrap();
+}
+
+#[test]
+fn test_table_mark_delete() {
+    let opts = get_test_table_options();
+    let data = build_test_table_data(b"key", 1000, opts.clone());
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+
+    let path = tmp_dir.path().join("1.sst");
+    let table = Table::create(&path, data.clone(), opts.clone()).unwrap();
+    drop(table);
+    assert!(path.exists());
+
+    let table = Table::open(&path, opts.clone()).unwrap();
+    let it = table.new_iterator(ReadOptions::default());
+    table.mark_delete();
+    drop(table);
+    // the file is kept until the last reference is dropped
+    assert!(path.exists());
+    drop(it);
+    assert!(!path.exists());
+
+    // marked table whose file is already removed
+    let path = tmp_dir.path().join("2.sst");
+    let table = Table::create(&path, data.clone(), opts.clone()).unwrap();
+    table.mark_delete();
+    fs::remove_file(&path).unwrap();
+    drop(table);
+
+    let path = tmp_dir.path().join("3.sst");
+    let table = Table::create(&path, data.clone(), opts.clone()).unwrap();
+    table.mark_delete();
+    table.mark_save();
+    drop(table);
+    assert!(path.exists());
+
+    let table = Table::open_in_memory(data, 4, opts).unwrap();
+    table.mark_delete();
+    drop(table);
+}
+
+#[test]
+fn test_max_version() {
+    let opts = get_test_table_options();
+    let mut kvs = vec![];
+    for i in 0..1000 {
+        for ts in &[1, 5, 3] {
+            let ts = ts * (i % 7 + 1);
+            kvs.push((key_with_ts(&key(b"key", i)[..], ts as u64), ts));
+        }
+    }
+    kvs.sort_by(|x, y| COMPARATOR.compare_key(&x.0, &y.0));
+
+    let mut builder = Builder::new(opts.clone());
+    for (k, ts) in kvs {
+        builder
+            .add(&k, Value::new(Bytes::from(ts.to_string())), 0)
+            .unwrap();
+    }
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    let filename = tmp_dir.path().join("1.sst");
+    let table = Table::create(&filename, builder.finish(), opts.clone()).unwrap();
+    assert_eq!(table.max_version(), 35);
+    assert_eq!(table.key_count(), 3000);
+    assert_eq!(table.fetch_index().unwrap().max_version, 35);
+    drop(table);
+
+    // both are persisted in index
+    let table = Table::open(&filename, opts.clone()).unwrap();
+    assert_eq!(table.max_version(), 35);
+    assert_eq!(table.key_count(), 3000);
+
+    let table = build_test_table(b"key", 10, opts);
+    assert_eq!(table.max_version(), 0);
+    assert_eq!(table.key_count(), 10);
+}
+
+#[test]
+fn test_key_splits() {
+    let opts = get_test_table_options();
+    let table = build_test_table(b"key", 10000, opts);
+    let blocks = table.offsets_length();
+    assert!(blocks > 10);
+
+    assert!(table.key_splits(0, b"").unwrap().is_empty());
+    for &n in &[5, 10, 100] {
+        let splits = table.key_splits(n, b"key").unwrap();
+        assert!(splits.len() <= n);
+        assert!(splits.len() >= n.min(blocks) / 2);
+        assert!(splits.windows(2).all(|w| w[0] < w[1]));
+        assert!(splits.iter().all(|k| k.starts_with("key")));
+    }
+    // more splits than blocks
+    assert_eq!(table.key_splits(blocks * 2, b"").unwrap().len(), blocks);
+    assert_eq!(
+        table.key_splits(blocks, b"").unwrap()[0],
+        String::from_utf8_lossy(&key(b"key", 0)).into_owned()
+    );
+    assert!(table.key_splits(10, b"nonexistent").unwrap().is_empty());
+
+    // a prefix covering a subset of blocks never gets more than n splits,
+    // nor more than the blocks it covers
+    let first_keys: Vec<_> = table
+        .fetch_index()
+        .unwrap()
+        .offsets
+        .iter()
+        .filter(|ko| ko.key.starts_with(b"key1"))
+        .map(|ko| String::from_utf8_lossy(user_key(&ko.key)).into_owned())
+        .collect();
+    for &n in &[1, first_keys.len(), first_keys.len() + 1, blocks] {
+        let splits = table.key_splits(n, b"key1").unwrap();
+        assert_eq!(splits.len(), n.min(first_keys.len()));
+        assert_eq!(splits[0], first_keys[0]);
+        assert!(splits.windows(2).all(|w| w[0] < w[1]));
+        assert!(splits.iter().all(|k| first_keys.contains(k)));
+    }
+}
+
+#[test]
+fn test_key_splits_prefix() {
+    let opts = get_test_table_options();
+    let table = build_test_table(b"key", 10000, opts);
+    // keys from key1000 to key1999 cover about a tenth of blocks
+    let matched = table
+        .fetch_index()
+        .unwrap()
+        .offsets
+        .iter()
+        .filter(|ko| ko.key.starts_with(b"key1"))
+        .count();
+    assert!(matched >= 4 && matched < table.offsets_length() / 2);
+
+    for &n in &[2, matched / 2, matched] {
+        let splits = table.key_splits(n, b"key1").unwrap();
+        assert_eq!(splits.len(), n);
+        assert!(splits.iter().all(|k| k.starts_with("key1")));
+    }
+}
+
+#[test]
+fn test_does_not_have() {
+    let opts = get_test_table_options();
+    let n = 5000;
+    let table = build_test_table(b"key", n, opts.clone());
+
+    for i in 0..n {
+        assert!(!table.does_not_have(bloom::hash(&key(b"key", i))).unwrap());
+    }
+
+    let false_positives = (0..n)
+        .filter(|i| {
+            !table
+                .does_not_have(bloom::hash(&key(b"nokey", *i)))
+                .unwrap()
+        })
+        .count();
+    let rate = false_positives as f64 / n as f64;
+    assert!(
+        rate < opts.bloom_false_positive * 2.0,
+        "false positive rate {} exceeds {}",
+        rate,
+        opts.bloom_false_positive
+    );
+}
+
+#[test]
+fn test_does_not_have_without_bloom_filter() {
+    let mut opts = get_test_table_options();
+    opts.bloom_false_positive = 0.0;
+    let table = build_test_table(b"key", 1000, opts);
+
+    assert_eq!(table.inner.bloom_filter_size(), 0);
+    for i in 0..1000 {
+        assert!(!table.does_not_have(bloom::hash(&key(b"nokey", i))).unwrap());
+    }
+}
+
+#[test]
+fn test_does_not_have_prefix() {
+    let mut opts = get_test_table_options();
+    opts.prefix_bloom_len = Some(4);
+    let mut kv_pairs = vec![];
+    for p in 0..500 {
+        for i in 0..4 {
+            let k = Bytes::from(format!("p{:03}-{}", p, i));
+            kv_pairs.push((k, Bytes::from(i.to_string())));
+        }
+    }
+    // shorter than prefix, and not put into filter
+    kv_pairs.push((Bytes::from("p5"), Bytes::from("short")));
+    let table = build_table(kv_pairs, opts.clone());
+
+    for p in 0..500 {
+        let prefix = format!("p{:03}", p);
+        assert!(!table.does_not_have_prefix(prefix.as_bytes()).unwrap());
+        assert!(!table
+            .does_not_have_prefix(format!("{}-0", prefix).as_bytes())
+            .unwrap());
+    }
+    // prefixes shorter than prefix length can't be checked
+    assert!(!table.does_not_have_prefix(b"q").unwrap());
+
+    let mut false_positives = 0;
+    for p in 0..1000 {
+        if !table
+            .does_not_have_prefix(format!("q{:03}", p).as_bytes())
+            .unwrap()
+        {
+            false_positives += 1;
+        }
+    }
+    let rate = false_positives as f64 / 1000.0;
+    assert!(
+        rate < opts.bloom_false_positive * 5.0,
+        "false positive rate {} is too high",
+        rate
+    );
+
+    opts.prefix_bloom_len = None;
+    let table = build_test_table(b"key", 1000, opts);
+    for i in 0..1000 {
+        assert!(!table.does_not_have_prefix(&key(b"nokey", i)).unwrap());
+    }
+}
+
+#[test]
+fn test_does_not_have_prefix_split() {
+    let opts = Options {
+        table_size: 8 * 1024,
+        prefix_bloom_len: Some(4),
+        ..get_test_table_options()
+    };
+    let mut builder = Builder::new(opts.clone());
+    for p in 0..200 {
+        for i in 0..20 {
+            let k = format!("p{:03}-{:02}", p, i);
+            builder
+                .add(
+                    &key_with_ts(k.as_bytes(), 0),
+                    Value::new(Bytes::from(vec![b'v'; 16])),
+                    0,
+                )
+                .unwrap();
+        }
+    }
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    let mut prefixes_found = 0;
+    for (id, data) in builder.finish_split().into_iter().enumerate() {
+        let filename = tmp_dir.path().join(format!("{}.sst", id + 1));
+        let table = Table::create(&filename, data, opts.clone()).unwrap();
+        let smallest = &table.smallest_user_key()[..4];
+        let biggest = &table.biggest_user_key()[..4];
+        for p in 0..200 {
+            let prefix = format!("p{:03}", p);
+            if (smallest..=biggest).contains(&prefix.as_bytes()) {
+                assert!(
+                    !table.does_not_have_prefix(prefix.as_bytes()).unwrap(),
+                    "{}",
+                    prefix
+                );
+                prefixes_found += 1;
+            }
+        }
+    }
+    assert!(prefixes_found >= 200);
+}
+
+#[test]
+fn test_checksum_algorithm() {
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    for (id, algo) in vec![ChecksumAlgorithm::Crc32c, ChecksumAlgorithm::XxHash64]
+        .into_iter()
+        .enumerate()
+    {
+        let mut opts = get_test_table_options();
+        opts.checksum_algorithm = algo;
+        let data = build_test_table_data(b"key", 1000, opts);
+
+        // tables are verified with the recorded algorithm, not the one in options
+        let filename = tmp_dir.path().join(format!("{}.sst", id + 1));
+        let table = Table::create(&filename, data.clone(), get_test_table_options()).unwrap();
+        let chksum = Checksum::decode(table.checksum()).unwrap();
+        assert_eq!(chksum.algo, algo as i32);
+        for idx in 0..table.offsets_length() {
+            let block = table.block(idx, false).unwrap();
+            assert_eq!(
+                Checksum::decode(block.checksum.clone()).unwrap().algo,
+                algo as i32
+            );
+        }
+        table.verify_checksum(None).unwrap();
+        assert_eq!(collect_table(&table).len(), 1000);
+        drop(table);
+        let mut opts = get_test_table_options();
+        opts.checksum_algorithm = ChecksumAlgorithm::XxHash64;
+        assert_eq!(
+            collect_table(&Table::open(&filename, opts).unwrap()).len(),
+            1000
+        );
+
+        // corruption is still detected
+        let mut corrupted = data.to_vec();
+        corrupted[HEADER_SIZE] ^= 0xff;
+        let table =
+            Table::open_in_memory(Bytes::from(corrupted), 1, get_test_table_options()).unwrap();
+        assert!(table.verify_checksum(None).unwrap_err().is_corruption());
+    }
+}
+
+#[test]
+fn test_checksum_verification_mode() {
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    let filename = tmp_dir.path().join("1.sst");
+
+    let opts = get_test_table_options();
+    let mut builder = Builder::new(opts.clone());
+    for i in 0..1000 {
+        let k = key_with_ts(&key(b"key", i)[..], 0);
+        builder
+            .add(&k, Value::new(Bytes::from(i.to_string())), 0)
+            .unwrap();
+    }
+    drop(Table::create(&filename, builder.finish(), opts).unwrap());
+
+    // flip one byte of a key inside the first block
+    let mut data = fs::read(&filename).unwrap();
+    data[10] ^= 0xff;
+    fs::write(&filename, &data).unwrap();
+
+    let open = |mode| {
+        let mut opts = get_test_table_options();
+        opts.checksum_mode = mode;
+        Table::open(&filename, opts)
+    };
+
+    let table = open(ChecksumVerificationMode::NoVerification).unwrap();
+    let mut it = table.new_iterator(ReadOptions::default());
+    it.rewind();
+    assert!(it.valid());
+
+    let table = open(ChecksumVerificationMode::OnBlockRead).unwrap();
+    let mut it = table.new_iterator(ReadOptions::default());
+    it.rewind();
+    assert!(!it.valid());
+
+    assert!(matches!(
+        open_error(open(ChecksumVerificationMode::OnTableRead)),
+        (OpenPhase::Checksum, 0, Error::Corruption(_))
+    ));
+    assert!(matches!(
+        open_error(open(ChecksumVerificationMode::OnTableAndBlockRead)),
+        (OpenPhase::Checksum, 0, Error::Corruption(_))
+    ));
+}
+
+#[test]
+fn test_block_cache_same_id() {
+    let cache = Arc::new(BlockCache::with_capacity(64 << 20));
+    let mut opts = get_test_table_options();
+    opts.block_cache = Some(cache.clone());
+    // tables with the same id don't read blocks cached by each other
+    let t1 = Table::open_in_memory(
+        build_test_table_data(b"a", 1000, opts.clone()),
+        1,
+        opts.clone(),
+    )
+    .unwrap();
+    let t2 =
+        Table::open_in_memory(build_test_table_data(b"b", 1000, opts.clone()), 1, opts).unwrap();
+    for _ in 0..2 {
+        for (table, prefix) in &[(&t1, "a"), (&t2, "b")] {
+            let kvs = collect_table(table);
+            assert_eq!(kvs.len(), 1000);
+            assert!(kvs.iter().all(|(k, _)| k.starts_with(prefix.as_bytes())));
+        }
+    }
+    assert_eq!(cache.len(), t1.offsets_length() + t2.offsets_length());
+}
+
+#[test]
+fn test_block_cache() {
+    let cache = Arc::new(BlockCache::with_capacity(64 << 20));
+    let mut opts = get_test_table_options();
+    opts.block_cache = Some(cache.clone());
+    let table = build_test_table(b"key", 10000, opts);
+    let blocks = table.offsets_length();
+    // blocks read when opening table are not cached
+    assert!(cache.is_empty());
+
+    for _ in 0..2 {
+        let mut it = table.new_iterator(ReadOptions::default());
+        it.rewind();
+        let mut count = 0;
+        while it.valid() {
+            assert_eq!(count.to_string(), it.value().value);
+            count += 1;
+            it.next();
+        }
+        assert_eq!(count, 10000);
+        assert_eq!(cache.len(), blocks);
+    }
+    assert_eq!(cache.misses(), blocks as u64);
+    assert_eq!(cache.hits(), blocks as u64);
+
+    let mut it = table.new_iterator(ReadOptions {
+        no_cache: true,
+        ..ReadOptions::default()
+    });
+    it.seek(&key_with_ts(&key(b"key", 5000)[..], 0));
+    assert!(it.valid());
+    assert_eq!(cache.len(), blocks);
+    assert_eq!(cache.hits() + cache.misses(), 2 * blocks as u64);
+}
+
+#[test]
+fn test_table_clone_with_cache() {
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    let filename = tmp_dir.path().join("1.sst");
+    let cache = Arc::new(BlockCache::with_capacity(64 << 20));
+    let mut opts = get_test_table_options();
+    opts.block_cache = Some(cache.clone());
+    let data = build_test_table_data(b"key", 1000, opts.clone());
+    let table = Table::create(&filename, data, opts).unwrap();
+
+    let other_cache = Arc::new(BlockCache::with_capacity(64 << 20));
+    let cloned = table.clone_with_cache(other_cache.clone());
+    assert_eq!(cloned.inner.id(), table.inner.id());
+    assert_eq!(cloned.smallest(), table.smallest());
+    assert_eq!(cloned.biggest(), table.biggest());
+    assert_eq!(cloned.checksum(), table.checksum());
+    let kvs = |table: &Table| -> Vec<(Bytes, Bytes)> {
+        collect_table(table)
+            .into_iter()
+            .map(|(k, v)| (k, v.value))
+            .collect()
+    };
+    assert_eq!(kvs(&cloned), kvs(&table));
+    assert_eq!(kvs(&cloned).len(), 1000);
+    // each table caches blocks in its own cache
+    assert_eq!(cache.len(), table.offsets_length());
+    assert_eq!(other_cache.len(), table.offsets_length());
+    assert_eq!(cache.misses(), other_cache.misses());
+
+    // the file can't be deleted while it's shared
+    table.mark_delete();
+    assert!(table.delete().is_err());
+    assert!(filename.exists());
+    assert_eq!(kvs(&cloned).len(), 1000);
+    // the clone is marked too, and removes the file when dropped
+    drop(cloned);
+    assert!(!filename.exists());
+}
+
+#[test]
+fn test_table_clone_with_cache_concurrent_drop() {
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    let opts = get_test_table_options();
+    let data = build_test_table_data(b"key", 100, opts.clone());
+    for i in 0..20 {
+        let filename = tmp_dir.path().join(format!("{}.sst", i + 1));
+        let table = Table::create(&filename, data.clone(), opts.clone()).unwrap();
+        let cloned = table.clone_with_cache(Arc::new(BlockCache::with_capacity(1 << 20)));
+        table.mark_delete();
+
+        // whichever table is dropped last removes the file
+        let barrier = Arc::new(std::sync::Barrier::new(2));
+        let handles: Vec<_> = vec![table, cloned]
+            .into_iter()
+            .map(|table| {
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    drop(table);
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert!(!filename.exists());
+    }
+}
+
+#[test]
+fn test_block_cache_eviction() {
+    let capacity = 16 * 1024;
+    let cache = Arc::new(BlockCache::with_capacity(capacity));
+    let mut opts = get_test_table_options();
+    opts.block_cache = Some(cache.clone());
+    let table = build_test_table(b"key", 10000, opts);
+
+    let mut it = table.new_iterator(ReadOptions::default());
+    it.rewind();
+    while it.valid() {
+        it.next();
+    }
+    assert!(!cache.is_empty());
+    assert!(cache.len() < table.offsets_length());
+    assert!(cache.size() <= capacity);
+}
+
+fn build_test_table_data(prefix: &[u8], n: usize, opts: Options) -> Bytes {
+    let mut builder = Builder::new(opts);
+    for i in 0..n {
+        let k = key_with_ts(&key(prefix, i)[..], 0);
+        builder
+            .add(
+                &k,
+                Value::new_with_meta(Bytes::from(i.to_string()), b'A', 0),
+                0,
+            )
+            .unwrap();
+    }
+    builder.finish()
+}
+
+#[test]
+fn test_index_cache() {
+    let cache = Arc::new(IndexCache::with_capacity(64 << 20));
+    let mut opts = get_test_table_options();
+    opts.index_cache = Some(cache.clone());
+    let table = build_test_table(b"key", 10000, opts);
+    assert!(matches!(table.inner.index, IndexHandle::Cached(_)));
+    assert_eq!(cache.len(), 1);
+    assert_eq!(cache.size(), table.inner.index_size() as u64);
+
+    let mut it = table.new_iterator(ReadOptions::default());
+    it.seek(&key_with_ts(&key(b"key", 5000)[..], 0));
+    let mut count = 5000;
+    while it.valid() {
+        assert_eq!(count.to_string(), it.value().value);
+        count += 1;
+        it.next();
+    }
+    assert_eq!(count, 10000);
+    assert_eq!(table.inner.key_count(), 10000);
+}
+
+#[test]
+fn test_index_cache_same_id() {
+    let cache = Arc::new(IndexCache::with_capacity(64 << 20));
+    let mut opts = get_test_table_options();
+    opts.index_cache = Some(cache.clone());
+    // tables with the same id don't read the index cached by each other
+    let t1 = Table::open_in_memory(
+        build_test_table_data(b"a", 1000, opts.clone()),
+        1,
+        opts.clone(),
+    )
+    .unwrap();
+    let t2 =
+        Table::open_in_memory(build_test_table_data(b"b", 2000, opts.clone()), 1, opts).unwrap();
+    assert_eq!(cache.len(), 2);
+    for (table, prefix, n) in &[(&t1, "a", 1000), (&t2, "b", 2000)] {
+        let index = table.fetch_index().unwrap();
+        assert!(index
+            .offsets
+            .iter()
+            .all(|ko| ko.key.starts_with(prefix.as_bytes())));
+        assert_eq!(index.key_count, *n);
+        assert_eq!(collect_table(table).len(), *n as usize);
+    }
+}
+
+#[test]
+fn test_index_cache_eviction() {
+    let opts = get_test_table_options();
+    let data = build_test_table_data(b"key", 10000, opts.clone());
+    let index_size = Table::open_in_memory(data.clone(), 0, opts.clone())
+        .unwrap()
+        .inner
+        .index_size() as u64;
+
+    // only one index fits in cache
+    let cache = Arc::new(IndexCache::with_capacity(index_size));
+    let mut opts = opts;
+    opts.index_cache = Some(cache.clone());
+    let tables: Vec<_> = (1..=3)
+        .map(|id| Table::open_in_memory(data.clone(), id, opts.clone()).unwrap())
+        .collect();
+    assert_eq!(cache.len(), 1);
+
+    // indexes evicted from cache are read again
+    for table in &tables {
+        assert_eq!(collect_table(table).len(), 10000);
+        assert_eq!(table.inner.key_count(), 10000);
+        assert_eq!(cache.len(), 1);
+        assert!(cache.size() <= index_size);
+    }
+}
+
+#[test]
+fn test_index_cache_read_error() {
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    let mut opts = get_test_table_options();
+    opts.use_mmap = false;
+    let data = build_test_table_data(b"key", 10000, opts.clone());
+    let index_size = Table::open_in_memory(data.clone(), 0, opts.clone())
+        .unwrap()
+        .inner
+        .index_size() as u64;
+    opts.index_cache = Some(Arc::new(IndexCache::with_capacity(index_size)));
+    let path = tmp_dir.path().join("1.sst");
+    let table = Table::create(&path, data.clone(), opts.clone()).unwrap();
+    // evict the index of the first table
+    let _other = Table::create(&tmp_dir.path().join("2.sst"), data, opts).unwrap();
+
+    // the index can't be read again from a truncated file
+    fs::OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_len(0)
+        .unwrap();
+    assert!(table.fetch_index().is_err());
+    assert!(table.key_splits(4, b"").is_err());
+    assert!(table.get(&key(b"key", 7), 0).is_err());
+    let mut it = table.new_iterator(ReadOptions::default());
+    it.seek(&key_with_ts(&key(b"key", 7)[..], 0));
+    assert!(!it.valid());
+    assert!(it.status().is_err());
+    // fields of index kept in table are still available
+    assert_eq!(table.key_count(), 10000);
+}
+
+fn collect_table(table: &Table) -> Vec<(Bytes, Value)> {
+    let mut it = table.new_iterator(ReadOptions::default());
+    it.rewind();
+    let mut kvs = vec![];
+    while it.valid() {
+        kvs.push((Bytes::copy_from_slice(it.key()), it.value()));
+        it.next();
+    }
+    kvs
+}
+
+#[test]
+fn test_table_compression() {
+    let n = 10000;
+    let expected = collect_table(&build_test_table(b"key", n, get_test_table_options()));
+    assert_eq!(expected.len(), n);
+
+    for compression in &[
+        CompressionType::None,
+        CompressionType::Snappy,
+        CompressionType::Zstd(3),
+    ] {
+        let mut opts = get_test_table_options();
+        opts.compression = *compression;
+        let table = build_test_table(b"key", n, opts);
+        let kvs = collect_table(&table);
+        assert_eq!(kvs.len(), expected.len(), "{:?}", compression);
+        for ((k, v), (ek, ev)) in kvs.iter().zip(expected.iter()) {
+            assert_eq!(k, ek, "{:?}", compression);
+            assert_eq!(v.value, ev.value, "{:?}", compression);
+            assert_eq!(v.meta, ev.meta, "{:?}", compression);
+        }
+
+        let mut it = table.new_iterator(ReadOptions::default());
+        it.seek(&key_with_ts(&key(b"key", 5000)[..], 0));
+        assert!(it.valid());
+        assert_eq!(it.value().value, "5000");
+    }
+}
+
+#[test]
+fn test_table_zstd_reopen() {
+    let n = 50000;
+    let mut opts = get_test_table_options();
+    opts.compression = CompressionType::Zstd(3);
+    let mut builder = Builder::new(opts.clone());
+    for i in 0..n {
+        let k = key_with_ts(format!("key{:08}", i).as_bytes(), 0);
+        builder
+            .add(&k, Value::new(Bytes::from(i.to_string())), 0)
+            .unwrap();
+    }
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    let path = tmp_dir.path().join("1.sst");
+    drop(Table::create(&path, builder.finish(), opts.clone()).unwrap());
+
+    let table = Table::open(&path, opts).unwrap();
+    let kvs = collect_table(&table);
+    assert_eq!(kvs.len(), n);
+    for (i, (k, v)) in kvs.iter().enumerate() {
+        assert_eq!(user_key(k), format!("key{:08}", i).as_bytes());
+        assert_eq!(v.value, i.to_string());
+    }
+}
+
+#[test]
+fn test_table_read_snappy_with_zstd_configured() {
+    let n = 10000;
+    let mut opts = get_test_table_options();
+    opts.compression = CompressionType::Snappy;
+    let data = build_test_table_data(b"key", n, opts.clone());
+
+    // compression of each block is recorded in the index, so the configured
+    // compression only matters when writing
+    opts.compression = CompressionType::Zstd(3);
+    let table = Table::open_in_memory(data, 1, opts).unwrap();
+    let kvs = collect_table(&table);
+    assert_eq!(kvs.len(), n);
+    for (i, (_, v)) in kvs.iter().enumerate() {
+        assert_eq!(v.value, i.to_string());
+    }
+}
+
+fn build_table_data_with_values(n: usize, value: impl Fn(usize) -> Bytes, opts: Options) -> Bytes {
+    let mut builder = Builder::new(opts);
+    for i in 0..n {
+        let k = key_with_ts(&key(b"key", i)[..], 0);
+        builder.add(&k, Value::new(value(i)), 0).unwrap();
+    }
+    builder.finish()
+}
+
+#[test]
+fn test_table_estimated_size() {
+    let n = 10000;
+    let value = |_| Bytes::from("agatedb".repeat(100));
+    let data = build_table_data_with_values(n, value, get_test_table_options());
+    let table = Table::open_in_memory(data, 1, get_test_table_options()).unwrap();
+    assert_eq!(table.estimated_size(), table.size());
+
+    for compression in &[CompressionType::Snappy, CompressionType::Zstd(3)] {
+        let mut opts = get_test_table_options();
+        opts.compression = *compression;
+        let data = build_table_data_with_values(n, value, opts);
+        // reopen without compression configured, the size should still be detected
+        let table = Table::open_in_memory(data, 1, get_test_table_options()).unwrap();
+        assert!(
+            table.estimated_size() > table.size(),
+            "{:?}: {} <= {}",
+            compression,
+            table.estimated_size(),
+            table.size()
+        );
+    }
+}
+
+#[test]
+fn test_table_compression_ratio() {
+    let n = 10000;
+    let value = |_| Bytes::from("agatedb".repeat(100));
+    let raw = build_table_data_with_values(n, value, get_test_table_options());
+
+    for compression in &[CompressionType::Snappy, CompressionType::Zstd(3)] {
+        let mut opts = get_test_table_options();
+        opts.compression = *compression;
+        let data = build_table_data_with_values(n, value, opts.clone());
+        assert!(
+            data.len() * 4 < raw.len(),
+            "{:?}: {} vs {}",
+            compression,
+            data.len(),
+            raw.len()
+        );
+
+        let table = Table::open_in_memory(data, 1, opts).unwrap();
+        let kvs = collect_table(&table);
+        assert_eq!(kvs.len(), n);
+        assert!(kvs.iter().all(|(_, v)| v.value == value(0)));
+        assert!(table.inner.estimated_size as usize > table.size() as usize);
+    }
+}
+
+#[test]
+fn test_table_compression_incompressible() {
+    use rand::RngCore;
+
+    let n = 1000;
+    let mut rng = rand::thread_rng();
+    let values: Vec<Bytes> = (0..n)
+        .map(|_| {
+            let mut v = vec![0; 100];
+            rng.fill_bytes(&mut v);
+            Bytes::from(v)
+        })
+        .collect();
+    let mut opts = get_test_table_options();
+    opts.compression = CompressionType::Snappy;
+    let data = build_table_data_with_values(n, |i| values[i].clone(), opts.clone());
+
+    let table = Table::open_in_memory(data, 1, opts).unwrap();
+    let index = table.fetch_index().unwrap();
+    // random blocks grow under compression and are stored raw
+    assert!(index
+        .offsets
+        .iter()
+        .all(|ko| ko.compression == CompressionType::None.to_u32()));
+    let kvs = collect_table(&table);
+    assert_eq!(kvs.len(), n);
+    for (i, (_, v)) in kvs.iter().enumerate() {
+        assert_eq!(v.value, values[i]);
+    }
+}
+
+fn encryption_options(registry: &Arc<KeyRegistry>) -> Options {
+    let mut opts = get_test_table_options();
+    opts.data_key = registry.latest_data_key();
+    opts.key_registry = Some(registry.clone());
+    opts
+}
+
+#[test]
+fn test_table_encryption() {
+    let n = 10000;
+    let registry =
+        Arc::new(KeyRegistry::new(Bytes::from(vec![1; 32]), Duration::from_secs(3600)).unwrap());
+    let expected = collect_table(&build_test_table(b"key", n, get_test_table_options()));
+
+    for compression in &[CompressionType::None, CompressionType::Snappy] {
+        let mut opts = encryption_options(&registry);
+        opts.compression = *compression;
+        let data = build_test_table_data(b"key", n, opts.clone());
+        assert!(!data.windows(4).any(|w| w == b"key0"), "{:?}", compression);
+
+        let table = Table::open_in_memory(data.clone(), 1, opts.clone()).unwrap();
+        let kvs = collect_table(&table);
+        assert_eq!(kvs.len(), expected.len(), "{:?}", compression);
+        for ((k, v), (ek, ev)) in kvs.iter().zip(expected.iter()) {
+            assert_eq!(k, ek, "{:?}", compression);
+            assert_eq!(v.value, ev.value, "{:?}", compression);
+        }
+
+        // index evicted from index cache is decrypted when read again
+        opts.index_cache = Some(Arc::new(IndexCache::with_capacity(0)));
+        let table = Table::open_in_memory(data, 1, opts).unwrap();
+        assert_eq!(table.inner.key_count(), n as u32);
+        assert_eq!(collect_table(&table).len(), n);
+    }
+}
+
+#[test]
+fn test_table_encryption_invalid_key() {
+    let registry =
+        Arc::new(KeyRegistry::new(Bytes::from(vec![1; 16]), Duration::from_secs(3600)).unwrap());
+    let opts = encryption_options(&registry);
+    let data = build_test_table_data(b"key", 1000, opts.clone());
+
+    let mut opts = get_test_table_options();
+    let res = Table::open_in_memory(data.clone(), 1, opts.clone());
+    assert!(matches!(
+        open_error(res),
+        (OpenPhase::Index, _, Error::InvalidDataKey(_))
+    ));
+
+    // another registry has a different data key with the same id
+    let other =
+        Arc::new(KeyRegistry::new(Bytes::from(vec![1; 16]), Duration::from_secs(3600)).unwrap());
+    other.latest_data_key().unwrap();
+    opts.key_registry = Some(other);
+    let res = Table::open_in_memory(data, 1, opts);
+    assert!(matches!(
+        open_error(res),
+        (OpenPhase::Index, _, Error::InvalidDataKey(_))
+    ));
+}
+
+#[test]
+fn test_open_unencrypted_table_with_encryption() {
+    let registry =
+        Arc::new(KeyRegistry::new(Bytes::from(vec![1; 16]), Duration::from_secs(3600)).unwrap());
+    let data = build_test_table_data(b"key", 1000, get_test_table_options());
+    let table = Table::open_in_memory(data, 1, encryption_options(&registry)).unwrap();
+    assert_eq!(collect_table(&table).len(), 1000);
+}
+
+fn build_concat_tables() -> Vec<Table> {
+    [b"a", b"b", b"c"]
+        .iter()
+        .map(|prefix| build_test_table(&prefix[..], 1000, get_test_table_options()))
+        .collect()
+}
+
+fn concat_keys() -> Vec<Bytes> {
+    let mut keys = vec![];
+    for prefix in &[b"a", b"b", b"c"] {
+        for i in 0..1000 {
+            keys.push(key_with_ts(&key(&prefix[..], i)[..], 0));
+        }
+    }
+    keys
+}
+
+#[test]
+fn test_concat_iterator() {
+    let expected = concat_keys();
+    let mut it = ConcatIterator::new(build_concat_tables(), &ReadOptions::default());
+    it.rewind();
+    let mut count = 0;
+    while it.valid() {
+        assert_eq!(it.key(), &expected[count][..]);
+        assert_eq!(it.value().value, (count % 1000).to_string());
+        count += 1;
+        it.next();
+    }
+    assert_eq!(count, expected.len());
+
+    it.seek(&key_with_ts(&key(b"b", 500)[..], 0));
+    assert!(it.valid());
+    assert_eq!(it.key(), &expected[1500][..]);
+    // seek to a key between tables
+    it.seek(&key_with_ts(&b"az"[..], 0));
+    assert!(it.valid());
+    assert_eq!(it.key(), &expected[1000][..]);
+    it.seek(&key_with_ts(&b"d"[..], 0));
+    assert!(!it.valid());
+
+    // step back across tables
+    it.seek(&key_with_ts(&key(b"c", 0)[..], 0));
+    it.prev();
+    assert!(it.valid());
+    assert_eq!(it.key(), &expected[1999][..]);
+    it.next();
+    assert_eq!(it.key(), &expected[2000][..]);
+}
+
+#[test]
+fn test_concat_iterator_reversed() {
+    let expected = concat_keys();
+    let mut it = ConcatIterator::new(
+        build_concat_tables(),
+        &ReadOptions {
+            reversed: true,
+            ..ReadOptions::default()
+        },
+    );
+    it.rewind();
+    let mut count = 0;
+    while it.valid() {
+        assert_eq!(it.key(), &expected[expected.len() - 1 - count][..]);
+        count += 1;
+        it.next();
+    }
+    assert_eq!(count, expected.len());
+
+    it.seek(&key_with_ts(&key(b"b", 500)[..], 0));
+    assert!(it.valid());
+    assert_eq!(it.key(), &expected[1500][..]);
+    it.seek(&key_with_ts(&b"az"[..], 0));
+    assert!(it.valid());
+    assert_eq!(it.key(), &expected[999][..]);
+    it.seek(&key_with_ts(&b"0"[..], 0));
+    assert!(!it.valid());
+
+    it.seek(&key_with_ts(&key(b"b", 999)[..], 0));
+    it.prev();
+    assert!(it.valid());
+    assert_eq!(it.key(), &expected[2000][..]);
+}
+
+#[test]
+fn test_concat_iterator_empty() {
+    let mut it = ConcatIterator::new(vec![], &ReadOptions::default());
+    it.rewind();
+    assert!(!it.valid());
+    it.seek(&key_with_ts(&b"a"[..], 0));
+    assert!(!it.valid());
+}
+
+fn build_versioned_table(range: std::ops::Range<usize>, ts: u64) -> Table {
+    let opts = get_test_table_options();
+    let mut builder = Builder::new(opts.clone());
+    for i in range {
+        let k = key_with_ts(&key(b"key", i)[..], ts);
+        builder
+            .add(&k, Value::new(Bytes::from(format!("{}-{}", i, ts))), 0)
+            .unwrap();
+    }
+    Table::open_in_memory(builder.finish(), ts, opts).unwrap()
+}
+
+fn build_merge_iterator(opt: usize) -> MergeIterator {
+    // tables are ordered from oldest to newest
+    let tables = vec![
+        build_versioned_table(0..100, 1),
+        build_versioned_table(50..150, 2),
+        build_versioned_table(100..200, 3),
+    ];
+    let iters: Vec<Box<dyn AgateIterator>> = tables
+        .iter()
+        .map(|t| {
+            let opts = ReadOptions {
+                reversed: opt & ITERATOR_REVERSED != 0,
+                ..ReadOptions::default()
+            };
+            Box::new(t.new_iterator(opts)) as Box<dyn AgateIterator>
+        })
+        .collect();
+    MergeIterator::new(iters, opt & ITERATOR_REVERSED != 0)
+}
+
+fn latest_version(i: usize) -> u64 {
+    match i {
+        0..=49 => 1,
+        50..=99 => 2,
+        _ => 3,
+    }
+}
+
+#[test]
+fn test_merge_iterator() {
+    let mut it = build_merge_iterator(0);
+    it.rewind();
+    let mut count = 0;
+    while it.valid() {
+        let ts = latest_version(count);
+        assert_eq!(it.key(), &key_with_ts(&key(b"key", count)[..], ts)[..]);
+        assert_eq!(it.value().value, format!("{}-{}", count, ts));
+        count += 1;
+        it.next();
+    }
+    assert_eq!(count, 200);
+
+    it.seek(&key_with_ts(&key(b"key", 75)[..], u64::MAX));
+    assert!(it.valid());
+    assert_eq!(it.value().value, "75-2");
+    it.next();
+    assert_eq!(it.value().value, "76-2");
+}
+
+#[test]
+fn test_merge_iterator_reversed() {
+    let mut it = build_merge_iterator(ITERATOR_REVERSED);
+    it.rewind();
+    let mut count = 0;
+    while it.valid() {
+        let i = 199 - count;
+        assert_eq!(it.value().value, format!("{}-{}", i, latest_version(i)));
+        count += 1;
+        it.next();
+    }
+    assert_eq!(count, 200);
+}
+
+#[test]
+fn test_merge_iterator_same_table_versions() {
+    let opts = get_test_table_options();
+    let mut builder = Builder::new(opts.clone());
+    for ts in &[3, 2] {
+        let k = key_with_ts(&key(b"key", 0)[..], *ts);
+        builder
+            .add(&k, Value::new(Bytes::from(ts.to_string())), 0)
+            .unwrap();
+    }
+    let newer = Table::open_in_memory(builder.finish(), 2, opts).unwrap();
+    let older = build_versioned_table(0..2, 1);
+
+    let iters: Vec<Box<dyn AgateIterator>> = vec![
+        Box::new(older.new_iterator(ReadOptions::default())),
+        Box::new(newer.new_iterator(ReadOptions::default())),
+    ];
+    let mut it = MergeIterator::new(iters, false);
+    it.rewind();
+    let mut values = vec![];
+    while it.valid() {
+        values.push(it.value().value);
+        it.next();
+    }
+    // all versions in the newest iterator are visible
+    assert_eq!(values, vec!["3", "2", "1-1"]);
+}
+
+fn build_multi_table_iterator(reversed: bool) -> MultiTableIterator {
+    // the memtable overwrites a key in each table
+    let opts = get_test_table_options();
+    let mut builder = Builder::new(opts.clone());
+    for k in &[key(b"a", 5), key(b"b", 500), key(b"c", 999)] {
+        let v = Bytes::from([&b"mem-"[..], k].concat());
+        builder
+            .add(&key_with_ts(&k[..], 1), Value::new(v), 0)
+            .unwrap();
+    }
+    let memtable = Table::open_in_memory(builder.finish(), 100, opts).unwrap();
+    let opts = ReadOptions {
+        reversed,
+        ..ReadOptions::default()
+    };
+    let memtable = memtable.new_iterator(opts.clone());
+    MultiTableIterator::new(
+        Box::new(memtable),
+        ConcatIterator::new(build_concat_tables(), &opts),
+    )
+}
+
+#[test]
+fn test_multi_table_iterator() {
+    for &reversed in &[false, true] {
+        let mut it = build_multi_table_iterator(reversed);
+        it.rewind();
+        let mut keys = concat_keys();
+        if reversed {
+            keys.reverse();
+        }
+        let mut overwrites = 0;
+        for k in keys {
+            assert!(it.valid());
+            assert_eq!(user_key(it.key()), user_key(&k));
+            if it.value().value.starts_with(b"mem-") {
+                assert_eq!(&it.value().value[4..], user_key(&k));
+                assert_eq!(get_ts(it.key()), 1);
+                overwrites += 1;
+            }
+            it.next();
+        }
+        assert!(!it.valid());
+        assert_eq!(overwrites, 3);
+    }
+
+    let mut it = build_multi_table_iterator(false);
+    it.seek(&key_with_ts(&key(b"b", 500)[..], u64::MAX));
+    assert_eq!(it.value().value, "mem-b0500");
+    it.next();
+    assert_eq!(it.value().value, "501");
+}
+
+fn value(i: usize) -> Bytes {
+    Bytes::from(format!("{:01048576}", i)) // 1MB value
+}
+
+#[test]
+fn test_table_big_values() {
+    let n: usize = 100;
+    let opts = Options {
+        block_size: 4 * 1024,
+        bloom_false_positive: 0.01,
+        table_size: (n as u64) * (1 << 20),
+        ..Options::default()
+    };
+    let mut builder = Builder::new(opts.clone());
+
+    for i in 0..n {
+        let key = key_with_ts(&key(b"", i)[..], i as u64 + 1);
+        let vs = Value::new(value(i));
+        builder.add(&key, vs, 0).unwrap();
+    }
+
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    let filename = tmp_dir.path().join("1.sst".to_string());
+
+    let table = Table::create(&filename, builder.finish(), opts).unwrap();
+
+    let mut it = table.new_iterator(ReadOptions::default());
+    assert!(it.valid());
+
+    let mut count = 0;
+    it.rewind();
+
+    while it.valid() {
+        assert_eq!(key(b"", count), user_key(it.key()));
+        assert_eq!(value(count), it.value().value);
+        it.next();
+        count += 1;
+    }
+
+    assert!(!it.valid());
+    assert_eq!(n, count);
+    assert_eq!(n as u64, table.max_version());
+}
+
+#[test]
+fn test_open_truncated_table() {
+    use rand::{Rng, SeedableRng};
+
+    let mut opts = get_test_table_options();
+    opts.checksum_mode = ChecksumVerificationMode::NoVerification;
+    let data = build_test_table_data(b"key", 1000, opts.clone());
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+
+    let mut lens: Vec<usize> = (0..100).map(|_| rng.gen_range(0, data.len())).collect();
+    lens.extend_from_slice(&[0, 1, 3, 4, 7, 8, data.len() - 1]);
+    for (i, len) in lens.into_iter().enumerate() {
+        let path = tmp_dir.path().join(format!("{}.sst", i + 1));
+        fs::write(&path, &data[..len]).unwrap();
+        assert!(
+            Table::open(&path, opts.clone()).is_err(),
+            "table truncated to {} bytes",
+            len
+        );
+    }
+}
+
+#[test]
+fn test_open_corrupted_table() {
+    use rand::{Rng, SeedableRng};
+
+    for mode in &[
+        ChecksumVerificationMode::OnTableRead,
+        ChecksumVerificationMode::OnTableAndBlockRead,
+    ] {
+        let mut opts = get_test_table_options();
+        opts.checksum_mode = *mode;
+        let data = build_test_table_data(b"key", 1000, opts.clone());
+        let tmp_dir = TempDir::new("agatedb").unwrap();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+
+        // always flip some bits in the footer, where the length fields are
+        let mut positions: Vec<usize> = (0..100).map(|_| rng.gen_range(0, data.len())).collect();
+        positions.extend(data.len() - 16..data.len());
+        for (i, pos) in positions.into_iter().enumerate() {
+            let mut corrupted = data.to_vec();
+            corrupted[pos] ^= 1 << rng.gen_range(0, 8);
+            let path = tmp_dir.path().join(format!("{}.sst", i + 1));
+            fs::write(&path, &corrupted).unwrap();
+            assert!(
+                Table::open(&path, opts.clone()).is_err(),
+                "bit flipped at {} with {:?}",
+                pos,
+                mode
+            );
+        }
+    }
+}
+
+#[test]
+fn test_table_footer_magic() {
+    let opts = get_test_table_options();
+    let data = build_test_table_data(b"key", 1000, opts.clone());
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    let open = |id: u64, data: &[u8], opts: Options| {
+        let path = new_filename(id, tmp_dir.path());
+        fs::write(&path, data).unwrap();
+        Table::open(&path, opts)
+    };
+
+    let table = open(1, &data, opts.clone()).unwrap();
+    assert_eq!(collect_table(&table).len(), 1000);
+
+    // random bytes aren't taken as an SST
+    let garbage: Vec<u8> = (0..4096).map(|i| i as u8).collect();
+    assert!(matches!(
+        open_error(open(2, &garbage, opts.clone())),
+        (OpenPhase::Footer, 4088, Error::BadMagic(_))
+    ));
+
+    // a newer format version can't be read
+    let mut newer = data.to_vec();
+    let pos = newer.len() - 6;
+    newer[pos..pos + 2].copy_from_slice(&(FORMAT_VERSION + 1).to_be_bytes());
+    match open_error(open(3, &newer, opts.clone())) {
+        (OpenPhase::Footer, _, Error::TableRead(msg)) => {
+            assert!(msg.contains("format version"), "{}", msg)
+        }
+        res => panic!("unexpected result {:?}", res),
+    }
+
+    // legacy SSTs without trailer only open with `legacy_format`
+    let legacy = &data[..data.len() - TRAILER_SIZE];
+    assert!(matches!(
+        open_error(open(4, legacy, opts.clone())),
+        (OpenPhase::Footer, _, Error::BadMagic(_))
+    ));
+    let mut legacy_opts = opts;
+    legacy_opts.legacy_format = true;
+    let table = open(5, legacy, legacy_opts.clone()).unwrap();
+    assert_eq!(collect_table(&table).len(), 1000);
+    let table = open(6, &data, legacy_opts).unwrap();
+    assert_eq!(collect_table(&table).len(), 1000);
+}
+
+#[test]
+fn test_open_table_with_large_index() {
+    let mut opts = get_test_table_options();
+    opts.block_size = 512;
+    let n = 100000;
+    let mut builder = Builder::new(opts.clone());
+    for i in 0..n {
+        let k = key_with_ts(format!("key{:08}", i).as_bytes(), 0);
+        builder
+            .add(&k, Value::new(Bytes::from(i.to_string())), 0)
+            .unwrap();
+    }
+    let data = builder.finish();
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    let path = tmp_dir.path().join("1.sst");
+    drop(Table::create(&path, data, opts.clone()).unwrap());
+
+    // index doesn't fit in the tail read when opening
+    let table = Table::open(&path, opts).unwrap();
+    assert!(table.inner.index_len > TAIL_READ_SIZE);
+    let kvs = collect_table(&table);
+    assert_eq!(kvs.len(), n);
+    for (i, (_, v)) in kvs.iter().enumerate() {
+        assert_eq!(v.value, i.to_string());
+    }
+}
+
+#[test]
+fn test_table_get_entry() {
+    let cache = Arc::new(BlockCache::with_capacity(64 << 20));
+    let mut opts = get_test_table_options();
+    opts.block_cache = Some(cache.clone());
+    let mut builder = Builder::new(opts.clone());
+    // versions 3, 2 and 1 of every even key
+    for i in (0..2000).step_by(2) {
+        for ts in (1..=3).rev() {
+            let k = key_with_ts(&key(b"key", i)[..], ts);
+            builder
+                .add(&k, Value::new(Bytes::from(format!("{}-{}", i, ts))), 0)
+                .unwrap();
+        }
+    }
+    let table = Table::open_in_memory(builder.finish(), 1, opts).unwrap();
+    assert!(table.offsets_length() > 1);
+
+    let get = |i: usize, ts: u64| {
+        table
+            .get_entry(&key_with_ts(&key(b"key", i)[..], ts))
+            .unwrap()
+            .map(|(k, v)| (get_ts(&k), v.value))
+    };
+    for i in (0..2000).step_by(2) {
+        assert_eq!(get(i, u64::MAX), Some((3, Bytes::from(format!("{}-3", i)))));
+        assert_eq!(get(i, 3), Some((3, Bytes::from(format!("{}-3", i)))));
+        assert_eq!(get(i, 2), Some((2, Bytes::from(format!("{}-2", i)))));
+        assert_eq!(get(i, 1), Some((1, Bytes::from(format!("{}-1", i)))));
+        // older than all versions
+        assert_eq!(get(i, 0), None);
+        // miss inside range
+        assert_eq!(get(i + 1, u64::MAX), None);
+    }
+    assert!(!cache.is_empty());
+
+    // miss outside range
+    assert!(table
+        .get_entry(&key_with_ts(&b"a"[..], u64::MAX))
+        .unwrap()
+        .is_none());
+    assert!(table
+        .get_entry(&key_with_ts(&b"z"[..], u64::MAX))
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn test_table_overlaps_with() {
+    let t1 = build_versioned_table(0..100, 2);
+    assert_eq!(t1.smallest_user_key(), &b"key0000"[..]);
+    assert_eq!(t1.biggest_user_key(), &b"key0099"[..]);
+
+    // t1 ends with a newer version of key0099 than t2 starts with, so its
+    // biggest key sorts before the smallest key of t2
+    let t2 = build_versioned_table(99..200, 1);
+    assert_eq!(
+        COMPARATOR.compare_key(t1.biggest(), t2.smallest()),
+        std::cmp::Ordering::Less
+    );
+    assert!(t1.overlaps_with(&t2.key_range()));
+    assert!(t2.overlaps_with(&t1.key_range()));
+
+    let t3 = build_versioned_table(100..200, 1);
+    assert!(!t1.overlaps_with(&t3.key_range()));
+    assert!(!t3.overlaps_with(&t1.key_range()));
+
+    assert!(t1.overlaps_with(&KeyRange::new("key0050", "key0060")));
+    assert!(t1.overlaps_with(&KeyRange::new("a", "z")));
+    assert!(!t1.overlaps_with(&KeyRange::new("key0100", "z")));
+    assert!(!t1.overlaps_with(&KeyRange::default()));
+}
+
+#[test]
+fn test_block_iterator() {
+    let table = build_test_table(b"key", 10000, get_test_table_options());
+    let block = table.block(1, false).unwrap();
+    let n = block.entry_offsets.len();
+    assert!(n > 2);
+    // keys of the block are consecutive
+    let mut it = block.clone().iter();
+    it.seek_to_first();
+    let first: usize = std::str::from_utf8(&user_key(it.key())[3..])
+        .unwrap()
+        .parse()
+        .unwrap();
+    let expected_key = |i: usize| key_with_ts(&key(b"key", first + i)[..], 0);
+
+    // forward scan
+    for i in 0..n {
+        assert!(it.valid());
+        assert_eq!(it.key(), &expected_key(i)[..]);
+        assert_eq!(it.value().value, (first + i).to_string());
+        it.next();
+    }
+    assert!(!it.valid());
+
+    // backward scan
+    it.seek_to_last();
+    for i in (0..n).rev() {
+        assert!(it.valid());
+        assert_eq!(it.key(), &expected_key(i)[..]);
+        it.prev();
+    }
+    assert!(!it.valid());
+
+    // seek to middle, then move both ways
+    let mid = n / 2;
+    it.seek(&expected_key(mid));
+    assert!(it.valid());
+    assert_eq!(it.key(), &expected_key(mid)[..]);
+    it.prev();
+    assert_eq!(it.key(), &expected_key(mid - 1)[..]);
+    it.next();
+    it.next();
+    assert_eq!(it.key(), &expected_key(mid + 1)[..]);
+
+    // seek before and past all entries of the block
+    it.seek(&key_with_ts(&b"a"[..], 0));
+    assert!(it.valid());
+    assert_eq!(it.key(), &expected_key(0)[..]);
+    it.seek(&key_with_ts(&b"z"[..], 0));
+    assert!(!it.valid());
+    assert!(it.error().unwrap().is_eof());
+}
+
+#[test]
+fn test_table_contains_key() {
+    let table = build_test_table(b"key", 10000, get_test_table_options());
+    let contains = |k: &[u8], ts: u64| table.contains_key(&key_with_ts(k, ts)).unwrap();
+
+    for i in (0..10000).step_by(7) {
+        assert!(contains(&key(b"key", i), 0));
+        assert!(contains(&key(b"key", i), u64::MAX));
+    }
+    assert!(!contains(b"key0000a", 0));
+    assert!(!contains(b"a", 0));
+    assert!(!contains(b"z", 0));
+
+    // first and last keys of every block
+    let index = table.fetch_index().unwrap();
+    for idx in 0..index.offsets.len() {
+        let first = user_key(&index.offsets[idx].key);
+        assert!(contains(first, 0));
+        let mut it = table.block(idx, false).unwrap().iter();
+        it.seek_to_last();
+        let last = Bytes::copy_from_slice(user_key(it.key()));
+        assert!(contains(&last, 0));
+        // right after the last key of the block
+        assert!(!contains(&[&last[..], &b"a"[..]].concat(), 0));
+    }
+}
+
+#[test]
+fn test_table_stale_data_size() {
+    let opts = get_test_table_options();
+    let table = build_test_table(b"key", 10000, opts.clone());
+    assert_eq!(table.stale_data_size(), 0);
+
+    let mut builder = Builder::new(opts.clone());
+    for i in 0..10000 {
+        let k = key_with_ts(&key(b"key", i)[..], 0);
+        let v = Value::new(Bytes::from(format!("{:08}", i)));
+        if i % 2 == 0 {
+            builder.add_stale(&k, v, 0).unwrap();
+        } else {
+            builder.add(&k, v, 0).unwrap();
+        }
+    }
+    let table = Table::open_in_memory(builder.finish(), 1, opts).unwrap();
+    let stale = table.stale_data_size() as f64;
+    let total = table.fetch_index().unwrap().estimated_size as f64;
+    assert!(
+        (stale / total - 0.5).abs() < 0.01,
+        "stale {}, total {}",
+        stale,
+        total
+    );
+    assert_eq!(
+        table.stats().unwrap().stale_data_size,
+        table.stale_data_size()
+    );
+}
+
+#[test]
+fn test_table_vlog_size() {
+    let opts = get_test_table_options();
+    let table = build_test_table(b"key", 1000, opts.clone());
+    assert_eq!(table.on_disk_size(), table.size());
+    assert_eq!(table.logical_size(), table.on_disk_size());
+
+    // every value is 1MB in value log
+    let vlog_len = 1 << 20;
+    let mut builder = Builder::new(opts.clone());
+    for i in 0..1000 {
+        let k = key_with_ts(&key(b"key", i)[..], 0);
+        builder
+            .add(&k, Value::new(Bytes::from(i.to_string())), vlog_len)
+            .unwrap();
+    }
+    assert!(builder.estimated_size() > 1000 << 20);
+    let data = builder.finish();
+    let table = Table::open_in_memory(data.clone(), 1, opts.clone()).unwrap();
+    assert_eq!(table.on_disk_size(), data.len() as u64);
+    assert!(table.on_disk_size() < 1 << 20);
+    assert_eq!(table.inner.vlog_size(), 1000 << 20);
+    assert_eq!(
+        table.logical_size(),
+        table.estimated_size() + table.inner.vlog_size()
+    );
+
+    // SSTs are cut by logical size
+    let mut builder = Builder::new(opts);
+    let mut n = 0;
+    while !builder.reach_capacity(64 << 20) {
+        let k = key_with_ts(&key(b"key", n)[..], 0);
+        builder
+            .add(&k, Value::new(Bytes::from(n.to_string())), vlog_len)
+            .unwrap();
+        n += 1;
+    }
+    assert!(n > 60 && n < 64, "{}", n);
+}
+
+#[test]
+fn test_table_get() {
+    let opts = get_test_table_options();
+    let mut builder = Builder::new(opts.clone());
+    for i in 0..1000 {
+        // versions 30, 20 and 10 of every key
+        for ts in &[30, 20, 10] {
+            let k = key_with_ts(&key(b"key", i)[..], *ts);
+            builder
+                .add(&k, Value::new(Bytes::from(format!("{}-{}", i, ts))), 0)
+                .unwrap();
+        }
+    }
+    let table = Table::open_in_memory(builder.finish(), 1, opts).unwrap();
+    let get = |i: usize, version: u64| {
+        table
+            .get(&key(b"key", i), version)
+            .unwrap()
+            .map(|v| v.value)
+    };
+
+    for i in (0..1000).step_by(3) {
+        // exact version match
+        assert_eq!(get(i, 20), Some(Bytes::from(format!("{}-20", i))));
+        // falls through to an older version
+        assert_eq!(get(i, 25), Some(Bytes::from(format!("{}-20", i))));
+        assert_eq!(get(i, u64::MAX), Some(Bytes::from(format!("{}-30", i))));
+        // older than all versions
+        assert_eq!(get(i, 5), None);
+    }
+    // keys not in the table
+    assert!(table.get(b"key0000a", u64::MAX).unwrap().is_none());
+    assert!(table.get(b"zzz", u64::MAX).unwrap().is_none());
+}
+
+#[test]
+fn test_parse_file_id() {
+    assert_eq!(parse_file_id("1.sst").unwrap(), 1);
+    assert_eq!(parse_file_id("000233.sst").unwrap(), 233);
+    assert_eq!(parse_file_id(&id_to_filename(u64::MAX)).unwrap(), u64::MAX);
+    for name in &[
+        "",
+        ".sst",
+        "1",
+        "1.log",
+        "+1.sst",
+        "-1.sst",
+        "a.sst",
+        "1 .sst",
+        "18446744073709551616.sst",
+    ] {
+        assert!(
+            matches!(parse_file_id(name), Err(Error::InvalidFilename(_))),
+            "{}",
+            name
+        );
+    }
+}
+
+#[test]
+fn test_parse_file_id_with_ext() {
+    assert_eq!(
+        parse_file_id_with_ext("000001.sst").unwrap(),
+        (1, FileType::Sst)
+    );
+    assert_eq!(
+        parse_file_id_with_ext("233.vlog").unwrap(),
+        (233, FileType::Vlog)
+    );
+    assert_eq!(
+        parse_file_id_with_ext("000042.wal").unwrap(),
+        (42, FileType::Wal)
+    );
+    for name in &[
+        "",
+        ".wal",
+        ".vlog",
+        "1.log",
+        "1.sst.wal.bak",
+        "1.vlog.sst",
+        "+1.wal",
+        "a.vlog",
+        "1.wal ",
+        "18446744073709551616.vlog",
+    ] {
+        assert!(
+            matches!(parse_file_id_with_ext(name), Err(Error::InvalidFilename(_))),
+            "{}",
+            name
+        );
+    }
+
+    // only SSTs are accepted by `parse_file_id`
+    assert!(matches!(
+        parse_file_id("1.vlog"),
+        Err(Error::InvalidFilename(_))
+    ));
+    assert!(matches!(
+        parse_file_id("1.wal"),
+        Err(Error::InvalidFilename(_))
+    ));
+}
+
+#[test]
+fn test_sst_filename() {
+    assert_eq!(id_to_filename(1), "000001.sst");
+    assert_eq!(id_to_filename(1234567), "1234567.sst");
+    let dir = Path::new("/tmp/agatedb");
+    assert_eq!(new_filename(1, dir), dir.join("000001.sst"));
+    for id in &[0, 1, 233, 1 << 40] {
+        assert_eq!(parse_file_id(&id_to_filename(*id)).unwrap(), *id);
+    }
+}
+
+#[test]
+fn test_scan_sst_dir() {
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    let dir = tmp_dir.path();
+    assert!(scan_sst_dir(dir).unwrap().is_empty());
+
+    for id in &[3, 1, 20] {
+        fs::write(new_filename(*id, dir), b"").unwrap();
+    }
+    fs::write(dir.join("MANIFEST"), b"").unwrap();
+    fs::write(dir.join("1.vlog"), b"").unwrap();
+    fs::create_dir(dir.join("2.sst")).unwrap();
+
+    let tables = scan_sst_dir(dir).unwrap();
+    assert_eq!(tables.keys().copied().collect::<Vec<_>>(), vec![1, 3, 20]);
+    assert_eq!(tables[&3], new_filename(3, dir));
+
+    fs::write(dir.join("3.sst"), b"").unwrap();
+    assert!(matches!(scan_sst_dir(dir), Err(Error::InvalidFilename(_))));
+}
+
+#[test]
+fn test_read_buffer_reuse() {
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    let path = tmp_dir.path().join("1.sst");
+    let data: Vec<u8> = (0..READ_BUF_SIZE * 3).map(|i| i as u8).collect();
+    fs::write(&path, &data).unwrap();
+    let file = fs::File::open(&path).unwrap();
+
+    // reads stay valid after the buffer is reused
+    let mut reads = vec![];
+    for offset in (0..data.len() - 4096).step_by(1000) {
+        reads.push((offset, read_at(&file, offset, 4096).unwrap()));
+    }
+    for (offset, read) in &reads {
+        assert_eq!(&read[..], &data[*offset..*offset + 4096]);
+    }
+
+    let large = read_at(&file, 0, READ_BUF_SIZE + 1).unwrap();
+    assert_eq!(&large[..], &data[..READ_BUF_SIZE + 1]);
+    assert!(read_at(&file, data.len() - 10, 20).is_err());
+    assert_eq!(&read_at(&file, 10, 20).unwrap()[..], &data[10..30]);
+}
+
+#[test]
+fn test_cached_block_owns_buffer() {
+    let opts = Options {
+        use_mmap: false,
+        block_cache: Some(Arc::new(BlockCache::with_capacity(64 << 20))),
+        ..get_test_table_options()
+    };
+    let table = build_test_table(b"key", 10000, opts);
+    let len = table.fetch_index().unwrap().offsets[1].len as usize;
+    let read_buf_end = || READ_BUF.with(|buf| buf.borrow().as_ptr() as usize);
+
+    // blocks not cached are split off the per-thread read buffer
+    let block = table.inner.block(1, false).unwrap();
+    assert_eq!(block.data.as_ptr() as usize + len, read_buf_end());
+
+    // cached blocks don't pin the read buffer
+    let block = table.inner.block(1, true).unwrap();
+    assert_ne!(block.data.as_ptr() as usize + len, read_buf_end());
+    assert!(Arc::ptr_eq(&block, &table.inner.block(1, true).unwrap()));
+}
+
+#[test]
+fn test_table_io_stats() {
+    let cache = Arc::new(BlockCache::with_capacity(64 << 20));
+    let mut opts = get_test_table_options();
+    opts.block_cache = Some(cache);
+    let data = build_test_table_data(b"key", 10000, opts.clone());
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    let path = tmp_dir.path().join("1.sst");
+    let table = Table::create(&path, data, opts).unwrap();
+
+    // opening reads the index and blocks
+    let io_stats = table.io_stats();
+    assert!(io_stats.read_count.load(Ordering::Relaxed) > 0);
+    io_stats.reset();
+    assert_eq!(io_stats.read_bytes.load(Ordering::Relaxed), 0);
+
+    // a full scan reads every block once, which is everything but the
+    // index and footer
+    let stats = table.stats().unwrap();
+    let blocks_size: u64 = stats.block_sizes.iter().map(|len| *len as u64).sum();
+    let footer_size =
+        (stats.index_size + 4 + table.checksum().len() + 4 + builder::TRAILER_SIZE) as u64;
+    assert_eq!(collect_table(&table).len(), 10000);
+    assert_eq!(io_stats.read_bytes.load(Ordering::Relaxed), blocks_size);
+    assert_eq!(blocks_size + footer_size, table.size());
+    assert_eq!(
+        io_stats.read_count.load(Ordering::Relaxed),
+        stats.block_count as u64
+    );
+    assert_eq!(
+        io_stats.cache_misses.load(Ordering::Relaxed),
+        stats.block_count as u64
+    );
+    assert_eq!(io_stats.cache_hits.load(Ordering::Relaxed), 0);
+
+    // blocks are cached by the first scan
+    io_stats.reset();
+    collect_table(&table);
+    assert_eq!(io_stats.read_bytes.load(Ordering::Relaxed), 0);
+    assert_eq!(
+        io_stats.cache_hits.load(Ordering::Relaxed),
+        stats.block_count as u64
+    );
+}
+
+#[test]
+fn test_table_iterator_bounds() {
+    let table = build_test_table(b"key", 10000, get_test_table_options());
+    let collect = |opts: ReadOptions| {
+        let mut it = table.new_iterator(opts);
+        it.rewind();
+        let mut values = vec![];
+        while it.valid() {
+            values.push(it.value().value);
+            it.next();
+        }
+        values
+    };
+    let values = |range: &mut dyn std::iter::Iterator<Item = usize>| {
+        range
+            .map(|i| Bytes::from(i.to_string()))
+            .collect::<Vec<_>>()
+    };
+    let bounds = |start: Option<usize>, end: Option<usize>, reversed| ReadOptions {
+        reversed,
+        start_key: start.map(|i| key(b"key", i)),
+        end_key: end.map(|i| key(b"key", i)),
+        ..ReadOptions::default()
+    };
+
+    assert_eq!(
+        collect(bounds(Some(1000), Some(2000), false)),
+        values(&mut (1000..2000))
+    );
+    assert_eq!(
+        collect(bounds(Some(1000), Some(2000), true)),
+        values(&mut (1000..2000).rev())
+    );
+    assert_eq!(
+        collect(bounds(Some(9990), None, false)),
+        values(&mut (9990..10000))
+    );
+    assert_eq!(
+        collect(bounds(None, Some(10), true)),
+        values(&mut (0..10).rev())
+    );
+    assert!(collect(bounds(Some(2000), Some(1000), false)).is_empty());
+
+    // bounds between keys
+    let opts = ReadOptions {
+        start_key: Some(Bytes::from("key0099a")),
+        end_key: Some(Bytes::from("key0200a")),
+        ..ReadOptions::default()
+    };
+    assert_eq!(collect(opts.clone()), values(&mut (100..201)));
+    assert_eq!(
+        collect(ReadOptions {
+            reversed: true,
+            ..opts
+        }),
+        values(&mut (100..201).rev())
+    );
+
+    // checking an iterator before it's positioned doesn't panic
+    assert!(table.new_iterator(ReadOptions::default()).valid());
+    assert!(table
+        .new_iterator(bounds(Some(1000), Some(2000), false))
+        .valid());
+
+    // seeking out of bounds makes the iterator invalid
+    let mut it = table.new_iterator(bounds(Some(1000), Some(2000), false));
+    it.seek(&key_with_ts(&key(b"key", 1500)[..], 0));
+    assert!(it.valid());
+    it.seek(&key_with_ts(&key(b"key", 2500)[..], 0));
+    assert!(!it.valid());
+}
+
+#[test]
+fn test_table_size_of_key_range() {
+    let n = 10000;
+    let table = build_test_table(b"key", n, get_test_table_options());
+    let blocks_size: u64 = table
+        .stats()
+        .unwrap()
+        .block_sizes
+        .iter()
+        .map(|len| *len as u64)
+        .sum();
+    assert_eq!(
+        table.size_of_key_range(b"key", b"kez").unwrap(),
+        blocks_size
+    );
+    assert!(blocks_size <= table.size());
+
+    let entry_size = |i: usize| {
+        let v = Value::new_with_meta(Bytes::from(i.to_string()), b'A', 0);
+        (HEADER_SIZE + v.encoded_size() as usize + 4) as u64
+    };
+    for &(start, end) in &[(0, 0), (1000, 2000), (3333, 9999), (5000, 5001)] {
+        let estimate = table
+            .size_of_key_range(&key(b"key", start), &key(b"key", end))
+            .unwrap();
+        let actual: u64 = (start..=end).map(entry_size).sum();
+        assert!(actual <= estimate, "{} > {}", actual, estimate);
+        assert!(estimate <= table.size());
+    }
+
+    // ranges outside of the table
+    assert_eq!(table.size_of_key_range(b"a", b"b").unwrap(), 0);
+    assert_eq!(table.size_of_key_range(b"kez", b"z").unwrap(), 0);
+    assert_eq!(table.size_of_key_range(b"key9", b"key1").unwrap(), 0);
+}
+
+#[test]
+fn test_table_approximate_offset_of() {
+    let table = build_test_table(b"key", 10000, get_test_table_options());
+    let index = table.inner.fetch_index().unwrap();
+    assert!(index.offsets.len() > 2);
+
+    for (i, ko) in index.offsets.iter().enumerate() {
+        assert_eq!(table.approximate_offset_of(&ko.key).unwrap(), ko.offset);
+        // a newer version of the first key sorts before the block
+        let newer = key_with_ts(user_key(&ko.key), get_ts(&ko.key) + 1);
+        let expected = i.checked_sub(1).map_or(0, |i| index.offsets[i].offset);
+        assert_eq!(table.approximate_offset_of(&newer).unwrap(), expected);
+        if let Some(next) = index.offsets.get(i + 1) {
+            let key = key_with_ts(user_key(&next.key), get_ts(&next.key) + 1);
+            assert_eq!(table.approximate_offset_of(&key).unwrap(), ko.offset);
+        }
+    }
+
+    // before the first key and after the last key
+    assert_eq!(
+        table
+            .approximate_offset_of(&key_with_ts(&b"a"[..], 0))
+            .unwrap(),
+        0
+    );
+    let last = index.offsets.last().unwrap().offset;
+    assert_eq!(table.approximate_offset_of(table.biggest()).unwrap(), last);
+    assert_eq!(
+        table
+            .approximate_offset_of(&key_with_ts(&b"kez"[..], 0))
+            .unwrap(),
+        table.size()
+    );
+
+    let empty = Builder::new(get_test_table_options()).finish();
+    let table = Table::open_in_memory(empty, 1, get_test_table_options()).unwrap();
+    assert_eq!(
+        table
+            .approximate_offset_of(&key_with_ts(&b"a"[..], 0))
+            .unwrap(),
+        0
+    );
+}
+
+#[test]
+fn test_table_split_by_size() {
+    let opts = Options {
+        block_size: 1024,
+        ..get_test_table_options()
+    };
+    let n = 4000;
+    let table = build_test_table(b"key", n, opts.clone());
+    let index = table.inner.fetch_index().unwrap();
+    assert!(index.offsets.len() >= 100);
+    let keys: Vec<_> = (0..n)
+        .map(|i| key_with_ts(&key(b"key", i)[..], 0))
+        .collect();
+    let cmp = |a: &[u8], b: &[u8]| COMPARATOR.compare_key(a, b);
+
+    for &target in &[0, 1, 10 * 1024, 33 * 1024, table.size() / 2] {
+        let ranges = table.split_by_size(target).unwrap();
+        assert_eq!(&ranges[0].0, table.smallest());
+        assert_eq!(&ranges.last().unwrap().1, table.biggest());
+        // ranges touch each other without overlapping
+        for w in ranges.windows(2) {
+            assert_eq!(w[0].1, w[1].0);
+            assert_eq!(cmp(&w[0].0, &w[0].1), std::cmp::Ordering::Less);
+        }
+        // every key is in exactly one range
+        for k in &keys {
+            let count = ranges
+                .iter()
+                .enumerate()
+                .filter(|(i, (start, end))| {
+                    let before_end = if *i + 1 == ranges.len() {
+                        cmp(k, end) != std::cmp::Ordering::Greater
+                    } else {
+                        cmp(k, end) == std::cmp::Ordering::Less
+                    };
+                    cmp(k, start) != std::cmp::Ordering::Less && before_end
+                })
+                .count();
+            assert_eq!(count, 1);
+        }
+        // sizes are within 2x of target
+        let sizes: Vec<u64> = ranges
+            .iter()
+            .map(|(start, _)| {
+                let first = index
+                    .offsets
+                    .iter()
+                    .position(|ko| ko.key == *start)
+                    .unwrap();
+                index.offsets[first..]
+                    .iter()
+                    .enumerate()
+                    .take_while(|(i, ko)| *i == 0 || !ranges.iter().any(|r| r.0 == ko.key))
+                    .map(|(_, ko)| ko.len as u64)
+                    .sum()
+            })
+            .collect();
+        assert_eq!(
+            sizes.iter().sum::<u64>(),
+            index.offsets.iter().map(|ko| ko.len as u64).sum::<u64>()
+        );
+        if target >= opts.block_size as u64 * 2 {
+            for size in &sizes {
+                assert!(
+                    *size >= target / 2 && *size <= target * 2,
+                    "{} {}",
+                    size,
+                    target
+                );
+            }
+        }
+    }
+    assert_eq!(table.split_by_size(0).unwrap().len(), index.offsets.len());
+    assert_eq!(table.split_by_size(table.size() * 2).unwrap().len(), 1);
+
+    let empty = Builder::new(opts.clone()).finish();
+    let table = Table::open_in_memory(empty, 1, opts).unwrap();
+    assert!(table.split_by_size(1024).unwrap().is_empty());
+}
+
+#[test]
+fn test_table_get_at_snapshot() {
+    let opts = get_test_table_options();
+    let mut builder = Builder::new(opts.clone());
+    let put = |v: &str| Value::new(Bytes::from(v.to_string()));
+    let delete = || Value::new_with_meta(Bytes::new(), crate::entry::DELETE, 0);
+    builder
+        .add(&key_with_ts(&b"a"[..], 10), put("a10"), 0)
+        .unwrap();
+    builder
+        .add(&key_with_ts(&b"b"[..], 5), put("b5"), 0)
+        .unwrap();
+    builder
+        .add(&key_with_ts(&b"c"[..], 7), delete(), 0)
+        .unwrap();
+    builder
+        .add(&key_with_ts(&b"c"[..], 3), put("c3"), 0)
+        .unwrap();
+    let table = Table::open_in_memory(builder.finish(), 1, opts).unwrap();
+    let get = |k: &[u8], ts: u64| {
+        table
+            .get_at_snapshot(k, ts)
+            .unwrap()
+            .map(|v| (v.value, v.version))
+    };
+
+    // exact version
+    assert_eq!(get(b"a", 10), Some((Bytes::from("a10"), 10)));
+    // earlier version
+    assert_eq!(get(b"a", 20), Some((Bytes::from("a10"), 10)));
+    assert_eq!(get(b"b", 10), Some((Bytes::from("b5"), 5)));
+    // no version visible
+    assert_eq!(get(b"a", 9), None);
+    assert_eq!(get(b"d", 10), None);
+    // deleted before snapshot
+    assert_eq!(get(b"c", 7), None);
+    assert_eq!(get(b"c", 10), None);
+    assert_eq!(get(b"c", 6), Some((Bytes::from("c3"), 3)));
+}
+
+#[test]
+fn test_builder_finish_split() {
+    // the same ratio as 200MB of data split into 64MB tables
+    let mut opts = get_test_table_options();
+    opts.table_size = 64 << 10;
+    let mut builder = Builder::new(opts.clone());
+    let value = Bytes::from(vec![b'v'; 100]);
+    let mut keys = vec![];
+    while builder.estimated_size() < 200 << 10 {
+        let k = key_with_ts(&key(b"key", keys.len())[..], keys.len() as u64);
+        builder.add(&k, Value::new(value.clone()), 0).unwrap();
+        keys.push(k);
+    }
+    let tables = builder.finish_split();
+    assert_eq!(tables.len(), 4);
+
+    let mut kvs = vec![];
+    let mut max_version = 0;
+    for (i, data) in tables.into_iter().enumerate() {
+        assert!(data.len() as u64 <= opts.table_size, "{}", data.len());
+        let table = Table::open_in_memory(data, i as u64 + 1, opts.clone()).unwrap();
+        table.verify_checksum(None).unwrap();
+        let table_kvs = collect_table(&table);
+        assert_eq!(table.stats().unwrap().key_count as usize, table_kvs.len());
+        assert_eq!(
+            table.max_version(),
+            get_ts(&table_kvs[table_kvs.len() - 1].0)
+        );
+        assert!(table.max_version() > max_version);
+        max_version = table.max_version();
+        kvs.extend(table_kvs);
+    }
+    assert_eq!(kvs.into_iter().map(|(k, _)| k).collect::<Vec<_>>(), keys);
+
+    // a single table without table size
+    opts.table_size = 0;
+    let mut builder = Builder::new(opts);
+    builder.add(&keys[0], Value::new(value), 0).unwrap();
+    assert_eq!(builder.finish_split().len(), 1);
+}
+
+#[test]
+fn test_table_empty() {
+    let opts = get_test_table_options();
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    let filename = tmp_dir.path().join("1.sst");
+    let mut builder = Builder::new(opts.clone());
+    assert!(builder.is_empty());
+    let data = builder.finish();
+    let tables = vec![
+        Table::create(&filename, data.clone(), opts.clone()).unwrap(),
+        Table::open_in_memory(data, 2, opts.clone()).unwrap(),
+    ];
+    for table in tables {
+        assert!(table.is_empty());
+        assert!(table.smallest().is_empty());
+        assert!(table.biggest().is_empty());
+        assert!(table.key_range().is_empty());
+        assert_eq!(table.stats().unwrap().block_count, 0);
+        assert_eq!(table.stats().unwrap().key_count, 0);
+        assert_eq!(table.size_of_key_range(b"a", b"z").unwrap(), 0);
+        assert!(table.key_splits(4, b"").unwrap().is_empty());
+        table.verify_checksum(None).unwrap();
+        assert!(table.get(b"key0001", u64::MAX).unwrap().is_none());
+
+        for &reversed in &[false, true] {
+            let mut it = table.new_iterator(ReadOptions {
+                reversed,
+                ..Default::default()
+            });
+            it.rewind();
+            assert!(!it.valid());
+            it.seek(&key_with_ts(&b"key0001"[..], 0));
+            assert!(!it.valid());
+        }
+
+        // bounded iterators
+        let mut it = table.new_iterator(ReadOptions {
+            start_key: Some(Bytes::from("a")),
+            end_key: Some(Bytes::from("z")),
+            reversed: true,
+            ..Default::default()
+        });
+        it.rewind();
+        assert!(!it.valid());
+    }
+}
+
+#[test]
+fn test_table_user_meta_and_expires_at() {
+    let opts = get_test_table_options();
+    let n = 1000;
+    let value = |i: usize| Value {
+        meta: 0,
+        user_meta: i as u8,
+        // cover varints of different lengths
+        expires_at: if i % 10 == 0 {
+            0
+        } else {
+            (i as u64) << (i % 64)
+        },
+        value: Bytes::from(i.to_string()),
+        version: 0,
+    };
+    let mut builder = Builder::new(opts.clone());
+    for i in 0..n {
+        builder
+            .add(&key_with_ts(&key(b"key", i)[..], 0), value(i), 0)
+            .unwrap();
+    }
+    let table = Table::open_in_memory(builder.finish(), 1, opts).unwrap();
+
+    let mut it = table.new_iterator(ReadOptions::default());
+    it.rewind();
+    for i in 0..n {
+        assert!(it.valid());
+        let expected = value(i);
+        let v = it.value();
+        assert_eq!(v.user_meta, expected.user_meta);
+        assert_eq!(v.expires_at, expected.expires_at);
+        assert_eq!(v.value, expected.value);
+        it.next();
+    }
+    assert!(!it.valid());
+
+    let v = table.get(&key(b"key", 7), 0).unwrap().unwrap();
+    assert_eq!(v.user_meta, 7);
+    assert_eq!(v.expires_at, 7 << 7);
+}
+
+#[test]
+fn test_table_user_metadata() {
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    let path = tmp_dir.path().join("1.sst");
+    let mut opts = get_test_table_options();
+    opts.index_cache = Some(Arc::new(IndexCache::with_capacity(1 << 20)));
+    let mut builder = Builder::new(opts.clone());
+    for i in 0..1000 {
+        let k = key_with_ts(&key(b"key", i)[..], 0);
+        builder
+            .add(&k, Value::new(Bytes::from(i.to_string())), 0)
+            .unwrap();
+    }
+    builder.set_user_metadata("origin_level".to_string(), Bytes::from_static(b"3"));
+    builder.set_user_metadata("created_at".to_string(), Bytes::from_static(b"0"));
+    builder.set_user_metadata("created_at".to_string(), Bytes::from_static(b"1620000000"));
+    builder.set_user_metadata("empty".to_string(), Bytes::new());
+    let table = Table::create(&path, builder.finish(), opts.clone()).unwrap();
+
+    let check = |table: &Table| {
+        assert_eq!(table.user_metadata("origin_level").unwrap(), "3");
+        assert_eq!(table.user_metadata("created_at").unwrap(), "1620000000");
+        assert_eq!(table.user_metadata("empty").unwrap(), "");
+        assert!(table.user_metadata("missing").is_none());
+    };
+    check(&table);
+    drop(table);
+    check(&Table::open(&path, opts.clone()).unwrap());
+
+    // user metadata is in every split SST
+    let mut builder = Builder::new(Options {
+        table_size: 8 * 1024,
+        ..opts.clone()
+    });
+    builder.set_user_metadata("origin_level".to_string(), Bytes::from_static(b"3"));
+    for i in 0..1000 {
+        let k = key_with_ts(&key(b"key", i)[..], 0);
+        builder
+            .add(&k, Value::new(Bytes::from(i.to_string())), 0)
+            .unwrap();
+    }
+    let tables = builder.finish_split();
+    assert!(tables.len() > 1);
+    for data in tables {
+        let table = Table::open_in_memory(data, 1, opts.clone()).unwrap();
+        assert_eq!(table.user_metadata("origin_level").unwrap(), "3");
+        assert!(table.user_metadata("created_at").is_none());
+    }
+
+    // tables without user metadata
+    let table = build_test_table(b"key", 10, opts);
+    assert!(table.user_metadata("origin_level").is_none());
+}
+
+#[test]
+fn test_table_dump() {
+    let mut opts = get_test_table_options();
+    opts.checksum_mode = ChecksumVerificationMode::NoVerification;
+    let data = build_test_table_data(b"key", 1000, opts.clone());
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    let path = tmp_dir.path().join("1.sst");
+    let on_disk = Table::create(&path, data.clone(), opts.clone()).unwrap();
+    let in_memory = Table::open_in_memory(data.clone(), 1, opts.clone()).unwrap();
+
+    for table in &[on_disk, in_memory] {
+        let dump = table.dump(DumpVerbosity::Index).unwrap();
+        let stats = table.stats().unwrap();
+        assert_eq!(dump.filename, stats.filename);
+        assert_eq!(dump.size, data.len() as u64);
+        assert_eq!(dump.key_count, 1000);
+        assert_eq!(dump.blocks.len(), stats.block_count);
+        assert!(dump.blocks.len() > 1);
+        let mut offset = 0;
+        for block in &dump.blocks {
+            assert_eq!(block.offset, offset);
+            offset += block.len as u64;
+            assert_eq!(block.entry_count, None);
+            assert!(block.entries.is_empty());
+        }
+        assert_eq!(offset as usize, dump.index_offset);
+        assert_eq!(&dump.blocks[0].base_key, table.smallest());
+
+        let dump = table.dump(DumpVerbosity::Blocks).unwrap();
+        let counts: Vec<_> = dump.blocks.iter().map(|b| b.entry_count.unwrap()).collect();
+        assert_eq!(counts.iter().sum::<usize>(), 1000);
+        assert!(dump
+            .blocks
+            .iter()
+            .all(|b| !b.is_corrupt() && b.entries.is_empty()));
+
+        let dump = table.dump(DumpVerbosity::Entries).unwrap();
+        let entries: Vec<_> = dump.blocks.iter().flat_map(|b| &b.entries).collect();
+        assert_eq!(entries.len(), 1000);
+        for (i, entry) in entries.iter().enumerate() {
+            assert_eq!(entry.user_key, key(b"key", i));
+            assert_eq!(entry.ts, 0);
+            assert_eq!(entry.meta, b'A');
+            assert_eq!(entry.value_len, i.to_string().len());
+        }
+        for block in &dump.blocks {
+            assert_eq!(block.entries.len(), block.entry_count.unwrap());
+            assert_eq!(user_key(&block.base_key), &block.entries[0].user_key[..]);
+        }
+    }
+
+    // a corrupted block is marked, and the other blocks are still dumped
+    let mut corrupted = data.to_vec();
+    corrupted[HEADER_SIZE] ^= 0xff;
+    let table = Table::open_in_memory(Bytes::from(corrupted), 1, opts).unwrap();
+    let dump = table.dump(DumpVerbosity::Entries).unwrap();
+    assert!(dump.blocks[0].is_corrupt());
+    assert_eq!(dump.blocks[0].entry_count, None);
+    assert!(dump.blocks[0].entries.is_empty());
+    assert!(dump.blocks[1..].iter().all(|b| !b.is_corrupt()));
+    assert!(!table.dump(DumpVerbosity::Index).unwrap().blocks[0].is_corrupt());
+}
+
+#[test]
+fn test_table_corruption() {
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    let filename = tmp_dir.path().join("1.sst");
+    let mut opts = get_test_table_options();
+    opts.checksum_mode = ChecksumVerificationMode::NoVerification;
+    let data = build_test_table_data(b"key", 1000, opts.clone());
+    let index_start = Table::open_in_memory(data.clone(), 1, opts.clone())
+        .unwrap()
+        .inner
+        .index_start;
+
+    // a missing file is not corruption
+    let err = Table::open(&filename, opts.clone()).err().unwrap();
+    assert!(!err.is_corruption(), "{:?}", err);
+
+    // flip one byte of the index
+    let mut corrupted = data.to_vec();
+    corrupted[index_start] ^= 0xff;
+    fs::write(&filename, &corrupted).unwrap();
+    match open_error(Table::open(&filename, opts.clone())) {
+        (OpenPhase::Checksum, _, Error::Corruption(msg)) => {
+            assert!(msg.contains("checksum mismatch"), "{}", msg)
+        }
+        res => panic!("unexpected result {:?}", res),
+    }
+
+    // flip one byte of a key inside the first block
+    let mut corrupted = data.to_vec();
+    corrupted[HEADER_SIZE] ^= 0xff;
+    fs::write(&filename, &corrupted).unwrap();
+    let table = Table::open(&filename, opts.clone()).unwrap();
+    assert!(table.verify_checksum(None).unwrap_err().is_corruption());
+    assert!(table
+        .block(0, false)
+        .unwrap()
+        .verify_checksum()
+        .unwrap_err()
+        .is_corruption());
+    // blocks other than the corrupted one are fine
+    table.block(1, false).unwrap().verify_checksum().unwrap();
+
+    opts.checksum_mode = ChecksumVerificationMode::OnBlockRead;
+    let table = Table::open(&filename, opts.clone()).unwrap();
+    assert!(matches!(table.block(0, false), Err(Error::Corruption(_))));
+
+    opts.checksum_mode = ChecksumVerificationMode::OnTableRead;
+    assert!(Table::open(&filename, opts).err().unwrap().is_corruption());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_table_access_pattern() {
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    let filename = tmp_dir.path().join("1.sst");
+    let opts = get_test_table_options();
+    let data = build_test_table_data(b"key", 1000, opts.clone());
+    drop(Table::create(&filename, data.clone(), opts.clone()).unwrap());
+
+    let mut tables = vec![Table::open_in_memory(data, 2, opts.clone()).unwrap()];
+    for &(use_mmap, load_to_ram) in &[(true, false), (false, false), (false, true)] {
+        let mut opts = opts.clone();
+        opts.use_mmap = use_mmap;
+        opts.load_to_ram = load_to_ram;
+        tables.push(Table::open(&filename, opts).unwrap());
+    }
+    for table in tables {
+        for &pattern in &[
+            AccessPattern::Sequential,
+            AccessPattern::Random,
+            AccessPattern::Normal,
+        ] {
+            table.set_access_pattern(pattern).unwrap();
+        }
+        table.release_cache().unwrap();
+        // the table is still readable after dropping its cached pages
+        assert_eq!(collect_table(&table).len(), 1000);
+    }
+}
+
+#[test]
+fn test_table_open_error_phase() {
+    let mut opts = get_test_table_options();
+    opts.checksum_mode = ChecksumVerificationMode::NoVerification;
+    let data = build_test_table_data(b"key", 1000, opts.clone());
+    let table = Table::open_in_memory(data.clone(), 1, opts.clone()).unwrap();
+    let index_start = table.inner.index_start;
+    let last = table.fetch_index().unwrap().offsets.last().unwrap().clone();
+    drop(table);
+
+    let tmp_dir = TempDir::new("agatedb").unwrap();
+    let path = tmp_dir.path().join("1.sst");
+    let open = |data: &[u8]| {
+        fs::write(&path, data).unwrap();
+        Table::open(&path, opts.clone())
+    };
+
+    // missing file
+    let (phase, _, e) = open_error(Table::open(&tmp_dir.path().join("2.sst"), opts.clone()));
+    assert_eq!(phase, OpenPhase::File);
+    assert!(matches!(e, Error::Io(_)));
+
+    // too short to hold a footer
+    let (phase, offset, _) = open_error(open(&data[..4]));
+    assert_eq!((phase, offset), (OpenPhase::Footer, 0));
+
+    // checksum length larger than the file
+    let mut corrupted = data.to_vec();
+    let pos = data.len() - TRAILER_SIZE - 4;
+    corrupted[pos..pos + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+    let (phase, _, _) = open_error(open(&corrupted));
+    assert_eq!(phase, OpenPhase::Checksum);
+
+    // index doesn't match its checksum
+    let mut corrupted = data.to_vec();
+    corrupted[index_start] ^= 0xff;
+    let err = open(&corrupted).err().unwrap();
+    let msg = err.to_string();
+    assert!(msg.contains(&path.display().to_string()), "{}", msg);
+    assert!(msg.contains(&format!("offset {}", index_start)), "{}", msg);
+    let (phase, offset, e) = open_error(Err::<(), _>(err));
+    assert_eq!((phase, offset), (OpenPhase::Checksum, index_start as u64));
+    assert!(e.is_corruption());
+
+    // entry count of the last block is too large
+    let mut corrupted = data.to_vec();
+    let end = (last.offset + last.len as u64) as usize;
+    let checksum_len = (&data[end - 4..end]).get_u32() as usize;
+    let pos = end - 4 - checksum_len - 4;
+    corrupted[pos..pos + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+    let (phase, offset, _) = open_error(open(&corrupted));
+    assert_eq!((phase, offset), (OpenPhase::LastBlock, last.offset));
+
+    // the same block fails checksum verification if it's enabled
+    opts.checksum_mode = ChecksumVerificationMode::OnTableRead;
+    let (phase, offset, _) = open_error(open(&corrupted));
+    assert_eq!((phase, offset), (OpenPhase::Checksum, last.offset));
 }
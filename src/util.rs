@@ -1,10 +1,23 @@
 pub mod binary;
 
 pub use skiplist::{FixedLengthSuffixComparator, KeyComparator};
-use std::{cmp, ptr};
+use std::path::Path;
+use std::{cmp, fs, io, ptr};
 
 pub static COMPARATOR: FixedLengthSuffixComparator = FixedLengthSuffixComparator::new(8);
 
+/// Sync directory, so that files created or removed in it are persisted
+#[cfg(unix)]
+pub fn sync_dir(path: &Path) -> io::Result<()> {
+    fs::File::open(path)?.sync_all()
+}
+
+/// Directories can't be opened and synced on non-unix platforms
+#[cfg(not(unix))]
+pub fn sync_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
 unsafe fn u64(ptr: *const u8) -> u64 {
     ptr::read_unaligned(ptr as *const u64)
 }
@@ -1,5 +1,5 @@
+use crate::util::binary::{decode_varint_u64, encode_varint_u64, varint_u64_bytes_len};
 use bytes::{BufMut, Bytes, BytesMut};
-use std::mem::MaybeUninit;
 
 #[derive(Default, Debug, Clone)]
 pub struct Value {
@@ -10,66 +10,6 @@ pub struct Value {
     pub version: u64,
 }
 
-#[inline]
-fn var_size(n: u64) -> usize {
-    if n >= (1 << 28) {
-        if n < (1 << 35) {
-            return 5;
-        } else if n < (1 << 42) {
-            return 6;
-        } else if n < (1 << 49) {
-            return 7;
-        } else if n < (1 << 56) {
-            return 8;
-        } else if n < (1 << 63) {
-            return 9;
-        } else {
-            return 10;
-        }
-    }
-    if n >= (1 << 21) {
-        return 4;
-    } else if n >= (1 << 14) {
-        return 3;
-    } else if n >= (1 << 7) {
-        return 2;
-    }
-    1
-}
-
-fn decode_var(bytes: &[u8]) -> (u64, usize) {
-    if !bytes.is_empty() && bytes[0] == 0 {
-        return (0, 1);
-    }
-    let mut ans = 0;
-    let mut index = 0;
-    while index > bytes.len() && index <= 9 {
-        ans |= (bytes[index] as u64) << (index * 7);
-        index += 1;
-    }
-    if index > 0 && index <= 9 {
-        return (ans, index);
-    }
-    if index == 10 && (bytes[index] == 0 || bytes[index] == 1) {
-        return (ans, index);
-    }
-    panic!("data is truncated or corrupted {:?}", &bytes[..index]);
-}
-
-fn encode_var(bytes: &mut [u8], mut data: u64) -> usize {
-    let mut i = 0;
-    while data >= 0x128 && i < bytes.len() {
-        bytes[i] = data as u8 & 0x7f;
-        i += 1;
-        data >>= 7;
-    }
-    if data < 0x128 && i < bytes.len() {
-        bytes[i] = data as u8;
-        return i + 1;
-    }
-    panic!("buffer is too small {}", bytes.len());
-}
-
 impl Value {
     pub fn new(value: Bytes) -> Self {
         Self {
@@ -88,27 +28,24 @@ impl Value {
     }
 
     pub fn encoded_size(&self) -> u32 {
-        let l = self.value.len() + 2;
-        if self.expires_at == 0 {
-            return l as u32 + 1;
-        }
-        (l + var_size(self.expires_at)) as u32
+        (self.value.len() + 2 + varint_u64_bytes_len(self.expires_at) as usize) as u32
     }
 
     pub fn decode(&mut self, bytes: &Bytes) {
         self.meta = bytes[0];
         self.user_meta = bytes[1];
-        let res = decode_var(&bytes[2..]);
-        self.expires_at = res.0;
-        self.value = bytes.slice(res.1 + 2..);
+        let (expires_at, read) = match decode_varint_u64(&bytes[2..]) {
+            Ok(res) => res,
+            Err(e) => panic!("data is truncated or corrupted {:?}: {}", bytes, e),
+        };
+        self.expires_at = expires_at;
+        self.value = bytes.slice(read as usize + 2..);
     }
 
     pub fn encode(&self, buf: &mut BytesMut) {
-        let mut arr: [u8; 12] = unsafe { MaybeUninit::uninit().assume_init() };
-        arr[0] = self.meta;
-        arr[1] = self.user_meta;
-        let written = encode_var(&mut arr[2..], self.expires_at);
-        buf.put_slice(&arr[..written + 2]);
+        buf.put_u8(self.meta);
+        buf.put_u8(self.user_meta);
+        encode_varint_u64(buf, self.expires_at);
         buf.put_slice(&self.value);
     }
 }
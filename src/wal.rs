@@ -1,10 +1,14 @@
-use super::Result;
+use super::{Error, Result};
+use crate::entry::Entry;
 use crate::util::binary::{
     decode_varint_u32, decode_varint_u64, encode_varint_u32_to_array, encode_varint_u64_to_array,
     varint_u32_bytes_len, varint_u64_bytes_len,
 };
-use bytes::{BufMut, Bytes, BytesMut};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use crc::crc32;
+use std::collections::VecDeque;
 use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
 use std::path::PathBuf;
 
 /// `Header` stores metadata of an entry in WAL and in value log.
@@ -76,21 +80,402 @@ impl Header {
     }
 }
 
+/// Length of the fixed part of a WAL record: key length (4 bytes),
+/// value length (4 bytes), meta (1 byte) and version (8 bytes).
+const RECORD_HEADER_SIZE: usize = 4 + 4 + 1 + 8;
+/// Length of the CRC32 checksum at the end of a WAL record.
+const RECORD_CHECKSUM_SIZE: usize = 4;
+/// Key length in the header of a batch record, which never appears in
+/// the record of a single entry as keys can't be that long.
+const BATCH_MARKER: u32 = u32::MAX;
+
+/// Encode an entry as a WAL record.
+///
+/// Layout: key length, value length, meta, version, key, value, and a
+/// CRC32 checksum over all previous fields of the record.
+///
+/// Fails if the key or value is too long for its `u32` length. Key length
+/// of `u32::MAX` is the batch marker, so the key must be shorter.
+fn encode_record(entry: &Entry, buf: &mut BytesMut) -> Result<()> {
+    if entry.key.len() >= BATCH_MARKER as usize || entry.value.len() > u32::MAX as usize {
+        return Err(Error::TooLong(format!(
+            "entry with key of {} bytes and value of {} bytes exceeds limit {}",
+            entry.key.len(),
+            entry.value.len(),
+            u32::MAX
+        )));
+    }
+    let start = buf.len();
+    buf.reserve(RECORD_HEADER_SIZE + entry.key.len() + entry.value.len() + RECORD_CHECKSUM_SIZE);
+    buf.put_u32(entry.key.len() as u32);
+    buf.put_u32(entry.value.len() as u32);
+    buf.put_u8(entry.meta);
+    buf.put_u64(entry.version);
+    buf.put_slice(&entry.key);
+    buf.put_slice(&entry.value);
+    let crc = crc32::checksum_castagnoli(&buf[start..]);
+    buf.put_u32(crc);
+    Ok(())
+}
+
+/// Encode entries as a single WAL record of a batch.
+///
+/// Layout: batch marker, length of records, meta (always 0), number of
+/// entries, records of the entries, and a CRC32 checksum over all
+/// previous fields. The header has the same layout as that of a single
+/// entry, with the batch marker in place of key length.
+///
+/// Fails if the records don't fit in a batch, whose length is a `u32`.
+fn encode_batch(entries: &[Entry], buf: &mut BytesMut) -> Result<()> {
+    let start = buf.len();
+    let records_len: u64 = entries
+        .iter()
+        .map(|e| (RECORD_HEADER_SIZE + e.key.len() + e.value.len() + RECORD_CHECKSUM_SIZE) as u64)
+        .sum();
+    if records_len > u32::MAX as u64 {
+        return Err(Error::TooLong(format!(
+            "batch of {} entries takes {} bytes, exceeding limit {}",
+            entries.len(),
+            records_len,
+            u32::MAX
+        )));
+    }
+    buf.reserve(RECORD_HEADER_SIZE + records_len as usize + RECORD_CHECKSUM_SIZE);
+    buf.put_u32(BATCH_MARKER);
+    buf.put_u32(records_len as u32);
+    buf.put_u8(0);
+    buf.put_u64(entries.len() as u64);
+    for entry in entries {
+        encode_record(entry, buf)?;
+    }
+    let crc = crc32::checksum_castagnoli(&buf[start..]);
+    buf.put_u32(crc);
+    Ok(())
+}
+
+/// Decode a WAL record at the start of `data` into `entries`, which is
+/// either a single entry or all entries of a batch.
+///
+/// Returns the length of the record, or `None` if `data` doesn't start
+/// with a complete record with a valid checksum, in which case nothing
+/// is added to `entries`.
+fn decode_records(data: &Bytes, entries: &mut Vec<Entry>) -> Option<usize> {
+    if data.len() < RECORD_HEADER_SIZE {
+        return None;
+    }
+    let mut header = &data[..RECORD_HEADER_SIZE];
+    if header.get_u32() != BATCH_MARKER {
+        let (entry, len) = decode_record(data)?;
+        entries.push(entry);
+        return Some(len);
+    }
+    let records_len = header.get_u32() as usize;
+    let _meta = header.get_u8();
+    let count = header.get_u64();
+
+    let records_end = RECORD_HEADER_SIZE + records_len;
+    let batch_len = records_end + RECORD_CHECKSUM_SIZE;
+    if data.len() < batch_len {
+        return None;
+    }
+    let crc = (&data[records_end..batch_len]).get_u32();
+    if crc32::checksum_castagnoli(&data[..records_end]) != crc {
+        return None;
+    }
+
+    let start = entries.len();
+    let mut records = data.slice(RECORD_HEADER_SIZE..records_end);
+    while !records.is_empty() {
+        match decode_record(&records) {
+            Some((entry, len)) => {
+                entries.push(entry);
+                records.advance(len);
+            }
+            None => break,
+        }
+    }
+    if !records.is_empty() || (entries.len() - start) as u64 != count {
+        entries.truncate(start);
+        return None;
+    }
+    Some(batch_len)
+}
+
+/// Decode a WAL record of a single entry at the start of `data`.
+///
+/// Returns the entry and the length of the record, or `None` if `data`
+/// doesn't start with a complete record with a valid checksum.
+fn decode_record(data: &Bytes) -> Option<(Entry, usize)> {
+    if data.len() < RECORD_HEADER_SIZE {
+        return None;
+    }
+    let mut header = &data[..RECORD_HEADER_SIZE];
+    let key_len = header.get_u32() as usize;
+    let value_len = header.get_u32() as usize;
+    let meta = header.get_u8();
+    let version = header.get_u64();
+
+    let key_end = RECORD_HEADER_SIZE + key_len;
+    let value_end = key_end + value_len;
+    let record_len = value_end + RECORD_CHECKSUM_SIZE;
+    if data.len() < record_len {
+        return None;
+    }
+    let crc = (&data[value_end..record_len]).get_u32();
+    if crc32::checksum_castagnoli(&data[..value_end]) != crc {
+        return None;
+    }
+
+    let entry = Entry {
+        key: data.slice(RECORD_HEADER_SIZE..key_end),
+        value: data.slice(key_end..value_end),
+        meta,
+        version,
+    };
+    Some((entry, record_len))
+}
+
 pub struct Wal {
     f: File,
     path: PathBuf,
+    /// whether the WAL has been rotated, after which it can only be read
+    closed: bool,
 }
 
 impl Wal {
     pub fn open(path: PathBuf) -> Result<Wal> {
-        let f = OpenOptions::new().append(true).create(true).open(&path)?;
-        Ok(Wal { f, path })
+        let f = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+        Ok(Wal {
+            f,
+            path,
+            closed: false,
+        })
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.closed {
+            return Err(Error::WalClosed(self.path.display().to_string()));
+        }
+        Ok(())
+    }
+
+    /// Append an entry to the end of WAL
+    pub fn write_entry(&mut self, entry: &Entry) -> Result<()> {
+        self.check_writable()?;
+        let mut buf = BytesMut::new();
+        encode_record(entry, &mut buf)?;
+        self.f.write_all(&buf)?;
+        Ok(())
+    }
+
+    /// Append entries to the end of WAL as a single record with one write,
+    /// and sync it to disk. A batch partially written before a crash is
+    /// an incomplete record, which is dropped on recovery like that of a
+    /// single entry, so either all entries of a batch are recovered or
+    /// none of them are.
+    pub fn write_batch(&mut self, entries: &[Entry]) -> Result<()> {
+        self.check_writable()?;
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let mut buf = BytesMut::new();
+        encode_batch(entries, &mut buf)?;
+        self.f.write_all(&buf)?;
+        self.sync()
+    }
+
+    /// Flush written data to disk
+    pub fn sync(&self) -> Result<()> {
+        self.f.sync_data()?;
+        Ok(())
+    }
+
+    /// Sync this WAL and continue writing in a new WAL at `new_path`.
+    /// This WAL is closed afterwards, and rejects writes with
+    /// `Error::WalClosed`, while its entries can still be read.
+    pub fn rotate(&mut self, new_path: PathBuf) -> Result<Wal> {
+        self.check_writable()?;
+        self.sync()?;
+        let wal = Wal::open(new_path)?;
+        self.closed = true;
+        Ok(wal)
+    }
+
+    /// Get size of WAL file in bytes
+    pub fn file_size(&self) -> Result<u64> {
+        Ok(self.f.metadata()?.len())
+    }
+
+    /// Replay WAL from the start and return all valid entries.
+    ///
+    /// Reading stops at the first incomplete or corrupted record, which
+    /// is what a partial write leaves behind after a crash. Entries of
+    /// such a batch are all skipped.
+    pub fn read_entries(&self) -> Result<Vec<Entry>> {
+        let mut data = self.read_all()?;
+        let mut entries = vec![];
+        while let Some(len) = decode_records(&data, &mut entries) {
+            data.advance(len);
+        }
+        Ok(entries)
+    }
+
+    /// Remove everything after the last valid record, so that new entries
+    /// are appended right after it during recovery. Returns the number of
+    /// bytes removed.
+    pub fn truncate_to_valid(&mut self) -> Result<usize> {
+        self.check_writable()?;
+        let mut data = self.read_all()?;
+        let total_len = data.len();
+        let mut valid_len = 0;
+        let mut entries = vec![];
+        while let Some(len) = decode_records(&data, &mut entries) {
+            valid_len += len;
+            data.advance(len);
+            entries.clear();
+        }
+        if valid_len < total_len {
+            self.f.set_len(valid_len as u64)?;
+            self.f.sync_all()?;
+        }
+        Ok(total_len - valid_len)
+    }
+
+    /// Iterate entries of WAL from the start, reading one record at a time.
+    ///
+    /// Unlike `read_entries`, an incomplete or corrupted record is reported
+    /// as an error, after which the iterator stops.
+    pub fn iter(&self) -> Result<WalIterator> {
+        // a cloned handle would share the file cursor with `self.f`
+        let f = File::open(&self.path)?;
+        let len = f.metadata()?.len();
+        Ok(WalIterator {
+            reader: BufReader::new(f),
+            offset: 0,
+            len,
+            pending: VecDeque::new(),
+            pending_record_len: 0,
+            done: false,
+        })
+    }
+
+    fn read_all(&self) -> Result<Bytes> {
+        let mut f = &self.f;
+        f.seek(SeekFrom::Start(0))?;
+        let mut buf = vec![];
+        f.read_to_end(&mut buf)?;
+        Ok(Bytes::from(buf))
+    }
+}
+
+/// Iterator over entries of a WAL, created by `Wal::iter`.
+pub struct WalIterator {
+    reader: BufReader<File>,
+    /// offset of the next record
+    offset: u64,
+    /// length of WAL when the iterator is created
+    len: u64,
+    /// entries of the last batch record not returned yet
+    pending: VecDeque<Entry>,
+    /// length of the record `pending` entries are decoded from, which
+    /// they keep in memory
+    pending_record_len: usize,
+    done: bool,
+}
+
+impl WalIterator {
+    fn truncated(&self) -> Error {
+        Error::Io(Box::new(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("truncated WAL record at {}", self.offset),
+        )))
+    }
+
+    fn read_record(&mut self) -> Result<Option<Entry>> {
+        loop {
+            if let Some(entry) = self.pending.pop_front() {
+                return Ok(Some(entry));
+            }
+            if self.offset == self.len {
+                return Ok(None);
+            }
+            // a batch may have no entry
+            self.read_next_record()?;
+        }
+    }
+
+    /// Read the record at `offset` and put its entries into `pending`.
+    fn read_next_record(&mut self) -> Result<()> {
+        if self.offset + RECORD_HEADER_SIZE as u64 > self.len {
+            return Err(self.truncated());
+        }
+        let mut buf = BytesMut::new();
+        buf.resize(RECORD_HEADER_SIZE, 0);
+        self.reader.read_exact(&mut buf)?;
+        let mut header = &buf[..];
+        let key_len = header.get_u32();
+        let value_len = header.get_u32() as u64;
+        // the length of records in a batch takes place of value length
+        let key_len = if key_len == BATCH_MARKER {
+            0
+        } else {
+            key_len as u64
+        };
+
+        // check against length of WAL before allocating, in case lengths are corrupted
+        let record_len =
+            RECORD_HEADER_SIZE as u64 + key_len + value_len + RECORD_CHECKSUM_SIZE as u64;
+        if self.offset + record_len > self.len {
+            return Err(self.truncated());
+        }
+        buf.resize(record_len as usize, 0);
+        self.reader.read_exact(&mut buf[RECORD_HEADER_SIZE..])?;
+
+        let mut entries = vec![];
+        decode_records(&buf.freeze(), &mut entries).ok_or_else(|| {
+            Error::InvalidChecksum(format!("corrupted WAL record at {}", self.offset))
+        })?;
+        self.pending.extend(entries);
+        self.pending_record_len = record_len as usize;
+        self.offset += record_len;
+        Ok(())
+    }
+
+    /// Get bytes held by the iterator between calls to `next`, which are
+    /// the read buffer and the record of pending entries.
+    fn buffered_size(&self) -> usize {
+        let pending = if self.pending.is_empty() {
+            0
+        } else {
+            self.pending_record_len
+        };
+        self.reader.capacity() + pending
+    }
+}
+
+impl Iterator for WalIterator {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Result<Entry>> {
+        if self.done {
+            return None;
+        }
+        let res = self.read_record().transpose();
+        if !matches!(res, Some(Ok(_))) {
+            self.done = true;
+        }
+        res
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use tempdir::TempDir;
 
     #[test]
     fn test_header_encode() {
@@ -110,4 +495,356 @@ mod tests {
         new_header.decode(&mut buf).unwrap();
         assert_eq!(new_header, header);
     }
+
+    fn test_entry(i: usize) -> Entry {
+        let mut entry = Entry::new(
+            Bytes::from(format!("key{:04}", i)),
+            Bytes::from(format!("value{}", i)),
+        );
+        entry.version = i as u64;
+        if i % 3 == 0 {
+            entry.mark_delete();
+        }
+        entry
+    }
+
+    #[test]
+    fn test_wal_write_read() {
+        let tmp_dir = TempDir::new("agatedb").unwrap();
+        let path = tmp_dir.path().join("WAL");
+
+        let mut wal = Wal::open(path.clone()).unwrap();
+        for i in 0..100 {
+            wal.write_entry(&test_entry(i)).unwrap();
+        }
+        wal.sync().unwrap();
+        assert_eq!(
+            wal.read_entries().unwrap(),
+            (0..100).map(test_entry).collect::<Vec<_>>()
+        );
+        drop(wal);
+
+        let wal = Wal::open(path).unwrap();
+        let entries = wal.read_entries().unwrap();
+        assert_eq!(entries, (0..100).map(test_entry).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_wal_rotate() {
+        let tmp_dir = TempDir::new("agatedb").unwrap();
+        let mut wal = Wal::open(tmp_dir.path().join("1.wal")).unwrap();
+        assert_eq!(wal.file_size().unwrap(), 0);
+
+        let mut n = 0;
+        while wal.file_size().unwrap() < 4096 {
+            wal.write_entry(&test_entry(n)).unwrap();
+            n += 1;
+        }
+        let mut new_wal = wal.rotate(tmp_dir.path().join("2.wal")).unwrap();
+        new_wal.write_entry(&test_entry(n)).unwrap();
+        new_wal.sync().unwrap();
+        assert_eq!(new_wal.read_entries().unwrap(), vec![test_entry(n)]);
+
+        // the old WAL is intact and read-only
+        let size = wal.file_size().unwrap();
+        assert!(matches!(
+            wal.write_entry(&test_entry(n)),
+            Err(Error::WalClosed(_))
+        ));
+        assert!(matches!(
+            wal.rotate(tmp_dir.path().join("3.wal")),
+            Err(Error::WalClosed(_))
+        ));
+        assert!(matches!(wal.truncate_to_valid(), Err(Error::WalClosed(_))));
+        assert_eq!(wal.file_size().unwrap(), size);
+        assert_eq!(
+            wal.read_entries().unwrap(),
+            (0..n).map(test_entry).collect::<Vec<_>>()
+        );
+        assert!(!tmp_dir.path().join("3.wal").exists());
+    }
+
+    #[test]
+    fn test_wal_recover_truncated() {
+        let tmp_dir = TempDir::new("agatedb").unwrap();
+        let path = tmp_dir.path().join("WAL");
+
+        let mut wal = Wal::open(path.clone()).unwrap();
+        for i in 0..10 {
+            wal.write_entry(&test_entry(i)).unwrap();
+        }
+        wal.sync().unwrap();
+        drop(wal);
+
+        // cut the last record in the middle
+        let f = OpenOptions::new().write(true).open(&path).unwrap();
+        let len = f.metadata().unwrap().len();
+        f.set_len(len - 5).unwrap();
+        drop(f);
+
+        let wal = Wal::open(path).unwrap();
+        let entries = wal.read_entries().unwrap();
+        assert_eq!(entries, (0..9).map(test_entry).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_wal_write_batch() {
+        let tmp_dir = TempDir::new("agatedb").unwrap();
+        let path = tmp_dir.path().join("WAL");
+
+        let mut wal = Wal::open(path.clone()).unwrap();
+        wal.write_entry(&test_entry(0)).unwrap();
+        let batch: Vec<_> = (1..50).map(test_entry).collect();
+        wal.write_batch(&batch).unwrap();
+        // an empty batch writes nothing
+        let size = wal.file_size().unwrap();
+        wal.write_batch(&[]).unwrap();
+        assert_eq!(wal.file_size().unwrap(), size);
+        wal.write_entry(&test_entry(50)).unwrap();
+        wal.write_batch(&[test_entry(51)]).unwrap();
+
+        let expected: Vec<_> = (0..52).map(test_entry).collect();
+        assert_eq!(wal.read_entries().unwrap(), expected);
+        let entries: Vec<_> = wal.iter().unwrap().map(|e| e.unwrap()).collect();
+        assert_eq!(entries, expected);
+        drop(wal);
+
+        let mut wal = Wal::open(path).unwrap();
+        assert_eq!(wal.truncate_to_valid().unwrap(), 0);
+        assert_eq!(wal.read_entries().unwrap(), expected);
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn test_wal_write_batch_too_large() {
+        let tmp_dir = TempDir::new("agatedb").unwrap();
+        let mut wal = Wal::open(tmp_dir.path().join("WAL")).unwrap();
+
+        // zeroed pages are never touched, as the batch is rejected
+        // before being encoded
+        let value = Bytes::from(vec![0; 1 << 30]);
+        let batch: Vec<_> = (0..4)
+            .map(|i| Entry::new(Bytes::from(format!("key{}", i)), value.clone()))
+            .collect();
+        assert!(matches!(wal.write_batch(&batch), Err(Error::TooLong(_))));
+        assert_eq!(wal.file_size().unwrap(), 0);
+        // the WAL is still writable
+        wal.write_batch(&[test_entry(0)]).unwrap();
+        assert_eq!(wal.read_entries().unwrap(), vec![test_entry(0)]);
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn test_wal_write_entry_too_large() {
+        let tmp_dir = TempDir::new("agatedb").unwrap();
+        let mut wal = Wal::open(tmp_dir.path().join("WAL")).unwrap();
+        wal.write_entry(&test_entry(0)).unwrap();
+        let size = wal.file_size().unwrap();
+
+        // zeroed pages are never touched, as the entry is rejected
+        // before being encoded
+        let large = Bytes::from(vec![0; u32::MAX as usize + 1]);
+        let entry = Entry::new(Bytes::from("key"), large.clone());
+        assert!(matches!(wal.write_entry(&entry), Err(Error::TooLong(_))));
+        let entry = Entry::new(large.slice(..u32::MAX as usize), Bytes::from("value"));
+        assert!(matches!(wal.write_entry(&entry), Err(Error::TooLong(_))));
+        assert_eq!(wal.file_size().unwrap(), size);
+
+        // entries written afterwards are recovered
+        wal.write_entry(&test_entry(1)).unwrap();
+        wal.sync().unwrap();
+        assert_eq!(
+            wal.read_entries().unwrap(),
+            vec![test_entry(0), test_entry(1)]
+        );
+        assert_eq!(wal.truncate_to_valid().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_wal_recover_partial_batch() {
+        let tmp_dir = TempDir::new("agatedb").unwrap();
+        let path = tmp_dir.path().join("WAL");
+
+        let mut wal = Wal::open(path.clone()).unwrap();
+        for i in 0..10 {
+            wal.write_entry(&test_entry(i)).unwrap();
+        }
+        wal.sync().unwrap();
+        let valid_len = wal.file_size().unwrap();
+        let batch: Vec<_> = (10..20).map(test_entry).collect();
+        wal.write_batch(&batch).unwrap();
+        drop(wal);
+
+        // crash in the middle of writing the batch, after some of its
+        // records have been written completely
+        let f = OpenOptions::new().write(true).open(&path).unwrap();
+        let len = f.metadata().unwrap().len();
+        f.set_len(valid_len + (len - valid_len) / 2).unwrap();
+        drop(f);
+
+        let mut wal = Wal::open(path.clone()).unwrap();
+        let expected: Vec<_> = (0..10).map(test_entry).collect();
+        assert_eq!(wal.read_entries().unwrap(), expected);
+
+        let mut it = wal.iter().unwrap();
+        for i in 0..10 {
+            assert_eq!(it.next().unwrap().unwrap(), test_entry(i));
+        }
+        assert!(matches!(it.next(), Some(Err(Error::Io(_)))));
+        assert!(it.next().is_none());
+
+        // the whole batch is removed
+        assert!(wal.truncate_to_valid().unwrap() > 0);
+        assert_eq!(wal.file_size().unwrap(), valid_len);
+        wal.write_batch(&batch).unwrap();
+        let expected: Vec<_> = (0..20).map(test_entry).collect();
+        assert_eq!(wal.read_entries().unwrap(), expected);
+
+        // so is a batch with a corrupted record
+        let mut data = std::fs::read(&path).unwrap();
+        data[valid_len as usize + RECORD_HEADER_SIZE * 2] ^= 0xff;
+        std::fs::write(&path, &data).unwrap();
+        let wal = Wal::open(path).unwrap();
+        assert_eq!(
+            wal.read_entries().unwrap(),
+            (0..10).map(test_entry).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_wal_truncate_to_valid() {
+        let tmp_dir = TempDir::new("agatedb").unwrap();
+        let path = tmp_dir.path().join("WAL");
+
+        let mut wal = Wal::open(path.clone()).unwrap();
+        for i in 0..100 {
+            wal.write_entry(&test_entry(i)).unwrap();
+        }
+        wal.sync().unwrap();
+        assert_eq!(wal.truncate_to_valid().unwrap(), 0);
+        let valid_len = std::fs::metadata(&path).unwrap().len();
+        drop(wal);
+
+        let mut f = OpenOptions::new().append(true).open(&path).unwrap();
+        f.write_all(&[0xff; 20]).unwrap();
+        drop(f);
+
+        let mut wal = Wal::open(path.clone()).unwrap();
+        assert_eq!(wal.truncate_to_valid().unwrap(), 20);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), valid_len);
+        assert_eq!(
+            wal.read_entries().unwrap(),
+            (0..100).map(test_entry).collect::<Vec<_>>()
+        );
+
+        // new entries follow the valid ones
+        wal.write_entry(&test_entry(100)).unwrap();
+        assert_eq!(
+            wal.read_entries().unwrap(),
+            (0..101).map(test_entry).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_wal_iter() {
+        let tmp_dir = TempDir::new("agatedb").unwrap();
+        let path = tmp_dir.path().join("WAL");
+
+        let mut wal = Wal::open(path.clone()).unwrap();
+        assert!(wal.iter().unwrap().next().is_none());
+        for i in 0..100 {
+            wal.write_entry(&test_entry(i)).unwrap();
+        }
+        wal.sync().unwrap();
+        let entries: Vec<_> = wal.iter().unwrap().map(|e| e.unwrap()).collect();
+        assert_eq!(entries, (0..100).map(test_entry).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_wal_iter_truncated() {
+        let tmp_dir = TempDir::new("agatedb").unwrap();
+        let path = tmp_dir.path().join("WAL");
+
+        let mut wal = Wal::open(path.clone()).unwrap();
+        for i in 0..10 {
+            wal.write_entry(&test_entry(i)).unwrap();
+        }
+        wal.sync().unwrap();
+        let f = OpenOptions::new().write(true).open(&path).unwrap();
+        let len = f.metadata().unwrap().len();
+        f.set_len(len - 5).unwrap();
+
+        let mut it = wal.iter().unwrap();
+        for i in 0..9 {
+            assert_eq!(it.next().unwrap().unwrap(), test_entry(i));
+        }
+        assert!(matches!(it.next(), Some(Err(Error::Io(_)))));
+        assert!(it.next().is_none());
+    }
+
+    #[test]
+    fn test_wal_iter_corrupted() {
+        let tmp_dir = TempDir::new("agatedb").unwrap();
+        let path = tmp_dir.path().join("WAL");
+
+        let mut wal = Wal::open(path.clone()).unwrap();
+        let first_len = {
+            wal.write_entry(&test_entry(0)).unwrap();
+            std::fs::metadata(&path).unwrap().len()
+        };
+        for i in 1..10 {
+            wal.write_entry(&test_entry(i)).unwrap();
+        }
+        wal.sync().unwrap();
+
+        // flip a byte in the key of the second record
+        let mut data = std::fs::read(&path).unwrap();
+        data[first_len as usize + RECORD_HEADER_SIZE] ^= 0xff;
+        std::fs::write(&path, &data).unwrap();
+
+        let mut it = wal.iter().unwrap();
+        assert_eq!(it.next().unwrap().unwrap(), test_entry(0));
+        assert!(matches!(it.next(), Some(Err(Error::InvalidChecksum(_)))));
+        assert!(it.next().is_none());
+    }
+
+    /// Iterating a large WAL only keeps one record in memory at a time,
+    /// so memory stays far below the size of WAL.
+    #[test]
+    fn test_wal_iter_large() {
+        let tmp_dir = TempDir::new("agatedb").unwrap();
+        let path = tmp_dir.path().join("WAL");
+
+        // 128MB of entries, and a batch of 4MB
+        let n = 128;
+        let value = Bytes::from(vec![b'v'; 1 << 20]);
+        let mut wal = Wal::open(path).unwrap();
+        for i in 0..n {
+            let entry = Entry::new(Bytes::from(format!("key{:04}", i)), value.clone());
+            wal.write_entry(&entry).unwrap();
+        }
+        let batch: Vec<_> = (n..n + 4)
+            .map(|i| Entry::new(Bytes::from(format!("key{:04}", i)), value.clone()))
+            .collect();
+        wal.write_batch(&batch).unwrap();
+        wal.sync().unwrap();
+        drop(batch);
+
+        let max_record = 4 * (value.len() + 1024);
+        let mut count = 0;
+        let mut it = wal.iter().unwrap();
+        while let Some(entry) = it.next() {
+            let entry = entry.unwrap();
+            assert_eq!(entry.key, format!("key{:04}", count));
+            assert_eq!(entry.value.len(), value.len());
+            assert!(
+                it.buffered_size() <= max_record + (64 << 10),
+                "{} bytes buffered",
+                it.buffered_size()
+            );
+            count += 1;
+        }
+        assert_eq!(count, n + 4);
+        assert!(it.buffered_size() < 100 << 20);
+    }
 }
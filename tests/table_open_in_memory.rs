@@ -0,0 +1,72 @@
+//! Checks that opening an in-memory table doesn't copy its data. It lives
+//! in its own test binary, so the counting allocator doesn't affect other
+//! tests or benchmarks.
+
+use agatedb::{Table, TableBuilder, TableOptions, Value};
+use bytes::Bytes;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct CountingAlloc;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+#[test]
+fn test_open_in_memory_without_copy() {
+    let opts = TableOptions {
+        block_size: 4 * 1024,
+        bloom_false_positive: 0.01,
+        table_size: 0,
+        ..TableOptions::default()
+    };
+    let mut builder = TableBuilder::new(opts.clone());
+    let vs = Value::new(Bytes::from(vec![b'v'; 32]));
+    // about 8MB
+    for i in 0..160000 {
+        builder
+            .add(&Bytes::from(format!("{:032}", i)), vs.clone(), 0)
+            .unwrap();
+    }
+    let data = builder.finish();
+    let len = data.len();
+
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    let table = Table::open_in_memory(data.clone(), 1, opts.clone()).unwrap();
+    let allocated = ALLOCATED.load(Ordering::Relaxed) - before;
+    // only the index is decoded into its own buffers
+    let index_size = table.stats().unwrap().index_size;
+    assert!(
+        allocated < len / 4,
+        "allocated {} bytes to open {} bytes, index is {} bytes",
+        allocated,
+        len,
+        index_size
+    );
+    drop(table);
+
+    let vec = data.to_vec();
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    let table = Table::open_in_memory_from_vec(vec, 1, opts).unwrap();
+    let allocated = ALLOCATED.load(Ordering::Relaxed) - before;
+    assert!(
+        allocated < len / 4,
+        "allocated {} bytes to open {} bytes from a vec",
+        allocated,
+        len
+    );
+    assert_eq!(table.data().unwrap(), data);
+}